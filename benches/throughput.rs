@@ -0,0 +1,97 @@
+//! MMIO dispatch and guest memory throughput.
+//!
+//! The MMIO benchmarks run entirely in memory. The guest memory benchmarks
+//! need a Hypervisor.framework VM and are skipped when one can't be created,
+//! e.g. when the bench binary isn't signed with the hypervisor entitlement.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use simpple_vm::SharedMemory;
+use simpple_vm::devices::MmioManager;
+use simpple_vm::devices::gpio::Pl061Gpio;
+use simpple_vm::devices::uart::Pl011Device;
+use std::hint::black_box;
+use std::io;
+
+const UART_BASE: u64 = 0x9000000;
+const UARTFR: u64 = 0x018; // Flag Register
+const UARTIMSC: u64 = 0x038; // Interrupt Mask Set/Clear Register
+
+const GPIO_BASE: u64 = 0x1000_0000;
+const GPIODIR: u64 = 0x400; // Direction Register
+
+const RAM_BASE: u64 = 0x4000_0000;
+const RAM_SIZE: usize = 16 * 1024 * 1024;
+
+fn uart_round_trip(c: &mut Criterion) {
+    let mut mmio = MmioManager::default();
+    mmio.register_device(UART_BASE, Box::new(Pl011Device::new(io::sink())))
+        .unwrap();
+
+    let mut group = c.benchmark_group("uart");
+    group.bench_function("read", |b| {
+        b.iter(|| mmio.handle_read(black_box(UART_BASE + UARTFR), 4).unwrap())
+    });
+    group.bench_function("write", |b| {
+        b.iter(|| {
+            mmio.handle_write(black_box(UART_BASE + UARTIMSC), 4, 0)
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+// Lookup cost as devices are added, always hitting the last one registered
+fn region_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("region_lookup");
+    for devices in [1u64, 8, 64, 512] {
+        let mut mmio = MmioManager::default();
+        for i in 0..devices {
+            mmio.register_device(GPIO_BASE + i * 0x1000, Box::new(Pl061Gpio::new()))
+                .unwrap();
+        }
+
+        let last = GPIO_BASE + (devices - 1) * 0x1000 + GPIODIR;
+        group.bench_with_input(BenchmarkId::from_parameter(devices), &last, |b, &addr| {
+            b.iter(|| mmio.handle_read(black_box(addr), 4).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn memory_throughput(c: &mut Criterion) {
+    let mut vm = match ahvf::VirtualMachine::new(None) {
+        Ok(vm) => vm,
+        Err(e) => {
+            eprintln!("Skipping guest memory benchmarks, no VM: {e:?}");
+            return;
+        }
+    };
+    let mut mmu = SharedMemory::default();
+    mmu.add_segment(
+        &mut vm,
+        RAM_BASE,
+        RAM_SIZE,
+        ahvf::MemoryPermission::READ_WRITE,
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("memory");
+    for size in [64usize, 4096, 1024 * 1024] {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("read_bytes", size), &size, |b, &size| {
+            b.iter(|| mmu.read_bytes(&vm, black_box(RAM_BASE), size).unwrap())
+        });
+
+        let mut buffer = vec![0; size];
+        group.bench_with_input(BenchmarkId::new("read_into", size), &size, |b, _| {
+            b.iter(|| {
+                mmu.read_into(&vm, black_box(RAM_BASE), &mut buffer)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, uart_round_trip, region_lookup, memory_throughput);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+use crate::{SharedMemory, SimppleError};
+use keystone_engine::{Arch, Keystone, Mode};
+
+/// AArch64 assembler backed by Keystone
+pub struct Assembler {
+    engine: Keystone,
+}
+
+impl Assembler {
+    pub fn new() -> Result<Self, SimppleError> {
+        let engine = Keystone::new(Arch::ARM64, Mode::LITTLE_ENDIAN)?;
+        Ok(Assembler { engine })
+    }
+
+    /// Assemble `asm` as if it were placed at address `base`
+    pub fn assemble(&self, asm: &str, base: u64) -> Result<Vec<u8>, SimppleError> {
+        let result = self.engine.asm(asm.to_string(), base)?;
+        Ok(result.bytes)
+    }
+
+    /// Assemble `asm` and write it into guest memory at `base`.
+    ///
+    /// Returns the number of bytes written.
+    pub fn assemble_and_load(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        mmu: &SharedMemory,
+        base: u64,
+        asm: &str,
+    ) -> Result<usize, SimppleError> {
+        let code = self.assemble(asm, base)?;
+        mmu.write_bytes(vm, base, &code)?;
+        Ok(code.len())
+    }
+}
+
+/// Assemble a one-off AArch64 snippet placed at address `base`
+pub fn assemble(asm: &str, base: u64) -> Result<Vec<u8>, SimppleError> {
+    Assembler::new()?.assemble(asm, base)
+}
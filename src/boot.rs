@@ -0,0 +1,290 @@
+//! Boot a guest and run it until it stops.
+//!
+//! `BootConfig` describes the guest and the run options, `boot` hands it to a
+//! `VmRunner` and runs that to the end. The binary only sets up logging and
+//! fills a `BootConfig` from the `SIMPPLE_VM_*` environment variables.
+
+use crate::SimppleError;
+use crate::debugger::StepMode;
+use crate::devices::MmioFaultPolicy;
+use crate::devices::mailbox::Mailbox;
+use crate::devices::timer::TimerConfig;
+use crate::hypercall::HypercallTable;
+use crate::platform::PlatformConfig;
+use crate::regs::{CpuIdentity, RazWiRegisters};
+use crate::runner::VmRunner;
+use crate::vcpu::TrapConfig;
+use crate::vm::VmExit;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const TIMEOUT_ENV: &str = "SIMPPLE_VM_TIMEOUT"; // Watchdog timeout in seconds
+const RUN_UNTIL_ENV: &str = "SIMPPLE_VM_RUN_UNTIL"; // Hex address to stop at
+const RAZ_WI_ENV: &str = "SIMPPLE_VM_RAZ_WI"; // Extra RAZ/WI registers, e.g. S3_1_C15_C2_1,...
+const MMIO_RECORD_ENV: &str = "SIMPPLE_VM_MMIO_RECORD"; // File to record MMIO accesses to
+const MMIO_REPLAY_ENV: &str = "SIMPPLE_VM_MMIO_REPLAY"; // File to replay MMIO reads from
+const STEP_ENV: &str = "SIMPPLE_VM_STEP"; // Instructions to single-step from entry
+const STEP_MODE_ENV: &str = "SIMPPLE_VM_STEP_MODE"; // "software" (default) or "breakpoint"
+const CONSOLE_HISTORY_ENV: &str = "SIMPPLE_VM_CONSOLE_HISTORY"; // UART lines kept for the debugger
+const VIRTUAL_CLOCK_ENV: &str = "SIMPPLE_VM_VIRTUAL_CLOCK"; // Nanoseconds per exit, enables deterministic time
+const HW_BREAK_ENV: &str = "SIMPPLE_VM_HW_BREAK"; // Hex addresses for hardware breakpoints, comma separated
+const DISK_ENV: &str = "SIMPPLE_VM_DISK"; // Host file backing the sector disk
+const PASSTHROUGH_DEBUG_REGS_ENV: &str = "SIMPPLE_VM_PASSTHROUGH_DEBUG_REGS"; // Let debug registers run natively
+const LAZY_FP_ENV: &str = "SIMPPLE_VM_LAZY_FP"; // Enable FP/SIMD and SVE when the guest traps on them
+const MMIO_FAULT_ENV: &str = "SIMPPLE_VM_MMIO_FAULT"; // "ignore" (default), "halt" or "inject"
+const TIMER_FREQ_ENV: &str = "SIMPPLE_VM_TIMER_FREQ"; // CNTFRQ_EL0 in Hz as the guest sees it
+const WFI_TIMEOUT_ENV: &str = "SIMPPLE_VM_WFI_TIMEOUT"; // Seconds a WFI may wait for an interrupt
+
+/// Everything `boot` needs: the platform, the guest images and run options
+pub struct BootConfig {
+    pub platform: PlatformConfig,
+    /// Loaded at `platform.firmware_base`, where the vCPU starts at EL1
+    pub firmware: Vec<u8>,
+    /// Loaded at `dtb_address`
+    pub dtb: Vec<u8>,
+    /// Where the DTB goes, `platform.memory_base` by default. Must be 8-byte
+    /// aligned; the guest finds it in X0 at entry
+    pub dtb_address: u64,
+    /// Stop with `VmExit::Timeout` when the guest makes no progress for this long
+    pub timeout: Option<Duration>,
+    /// Stop with `VmExit::ReachedAddress` when PC reaches this address
+    pub run_until: Option<u64>,
+    /// Addresses to stop at using hardware breakpoints
+    pub hw_breakpoints: Vec<u64>,
+    /// Instructions to single-step and print from entry
+    pub steps: u64,
+    /// Stop with `VmExit::Stepped` once `steps` instructions ran, instead of running on
+    pub stop_after_steps: bool,
+    pub step_mode: StepMode,
+    pub raz_wi: RazWiRegisters,
+    /// File to save a recording of MMIO accesses to
+    pub mmio_record: Option<PathBuf>,
+    /// Recording to answer MMIO reads from
+    pub mmio_replay: Option<PathBuf>,
+    /// Host file attached as a sector disk at `0x9080000`
+    pub disk: Option<PathBuf>,
+    /// Nanoseconds of guest time per exit; enables the deterministic clock
+    pub virtual_clock_step: Option<u64>,
+    /// Counter frequency the guest sees, the host's when `None`
+    pub timer: Option<TimerConfig>,
+    /// Install a SIGINT handler that breaks into the debugger.
+    ///
+    /// The handler stays for the rest of the process, so only binaries that
+    /// own the terminal should turn this on.
+    pub break_on_interrupt: bool,
+    /// Which guest accesses exit to the host; debug exceptions must keep trapping
+    pub traps: TrapConfig,
+    /// Enable FP/SIMD and SVE in CPACR_EL1 on first use instead of halting
+    pub lazy_fp: bool,
+    /// How failed guest MMIO accesses are handled
+    pub mmio_fault_policy: MmioFaultPolicy,
+    /// Mailbox mapped at `0x9090000`, keep a clone to talk to the guest.
+    ///
+    /// A `ring` is delivered as an IRQ when the guest next exits.
+    pub mailbox: Option<Mailbox>,
+    /// Handlers for `HVC #imm`, other immediates halt the guest
+    pub hypercalls: HypercallTable,
+    /// MIDR_EL1 and MPIDR_EL1 for trapped reads
+    pub cpu_identity: CpuIdentity,
+    /// Stop with `VmExit::Idle` when a WFI or WFE waits longer than this
+    /// without an interrupt, instead of waiting forever
+    pub wfi_timeout: Option<Duration>,
+}
+
+impl BootConfig {
+    /// Boot `firmware` with `dtb` on `platform`, with every option off
+    pub fn new(platform: PlatformConfig, firmware: Vec<u8>, dtb: Vec<u8>) -> Self {
+        Self {
+            dtb_address: platform.memory_base,
+            platform,
+            firmware,
+            dtb,
+            timeout: None,
+            run_until: None,
+            hw_breakpoints: Vec::new(),
+            steps: 0,
+            stop_after_steps: false,
+            step_mode: StepMode::default(),
+            raz_wi: RazWiRegisters::default(),
+            mmio_record: None,
+            mmio_replay: None,
+            disk: None,
+            virtual_clock_step: None,
+            timer: None,
+            break_on_interrupt: false,
+            traps: TrapConfig {
+                debug_exceptions: true,
+                debug_registers: true,
+            },
+            lazy_fp: false,
+            mmio_fault_policy: MmioFaultPolicy::default(),
+            mailbox: None,
+            hypercalls: HypercallTable::default(),
+            cpu_identity: CpuIdentity::default(),
+            wfi_timeout: None,
+        }
+    }
+
+    /// Like `new`, with options read from the `SIMPPLE_VM_*` environment variables
+    pub fn from_env(
+        platform: PlatformConfig,
+        firmware: Vec<u8>,
+        dtb: Vec<u8>,
+    ) -> Result<Self, SimppleError> {
+        let platform = PlatformConfig {
+            console_history: console_history(),
+            console_stdin: true,
+            ..platform
+        };
+        Ok(Self {
+            timeout: env_seconds(TIMEOUT_ENV),
+            run_until: run_until_address(),
+            hw_breakpoints: hw_breakpoint_addresses(),
+            steps: step_count(),
+            step_mode: step_mode(),
+            raz_wi: raz_wi_registers()?,
+            mmio_record: std::env::var_os(MMIO_RECORD_ENV).map(PathBuf::from),
+            mmio_replay: std::env::var_os(MMIO_REPLAY_ENV).map(PathBuf::from),
+            disk: std::env::var_os(DISK_ENV).map(PathBuf::from),
+            virtual_clock_step: virtual_clock_step(),
+            timer: timer_config(),
+            wfi_timeout: env_seconds(WFI_TIMEOUT_ENV),
+            traps: TrapConfig {
+                debug_exceptions: true,
+                debug_registers: std::env::var_os(PASSTHROUGH_DEBUG_REGS_ENV).is_none(),
+            },
+            lazy_fp: std::env::var_os(LAZY_FP_ENV).is_some(),
+            mmio_fault_policy: mmio_fault_policy(),
+            ..Self::new(platform, firmware, dtb)
+        })
+    }
+}
+
+/// Boot the guest described by `config` and run it until it stops
+pub fn boot(config: &BootConfig) -> Result<VmExit, SimppleError> {
+    VmRunner::new(config)?.run()
+}
+
+/// Read a positive duration in seconds from the environment variable `name`
+fn env_seconds(name: &str) -> Option<Duration> {
+    let seconds = std::env::var(name).ok()?;
+    // Out of range values like `inf` or `1e300` are as invalid as garbage
+    match seconds.parse::<f64>().map(Duration::try_from_secs_f64) {
+        Ok(Ok(duration)) if !duration.is_zero() => Some(duration),
+        _ => {
+            log::warn!("Ignoring invalid {name} value: {seconds}");
+            None
+        }
+    }
+}
+
+/// Read the virtual clock step (in nanoseconds per exit) from the environment
+fn virtual_clock_step() -> Option<u64> {
+    let step = std::env::var(VIRTUAL_CLOCK_ENV).ok()?;
+    match step.parse() {
+        Ok(step) => Some(step),
+        Err(_) => {
+            log::warn!("Ignoring invalid {VIRTUAL_CLOCK_ENV} value: {step}");
+            None
+        }
+    }
+}
+
+/// Read the guest's counter frequency in Hz from the environment
+fn timer_config() -> Option<TimerConfig> {
+    let freq = std::env::var(TIMER_FREQ_ENV).ok()?;
+    match freq.parse() {
+        Ok(freq_hz) if freq_hz > 0 => Some(TimerConfig { freq_hz }),
+        _ => {
+            log::warn!("Ignoring invalid {TIMER_FREQ_ENV} value: {freq}");
+            None
+        }
+    }
+}
+
+/// Read how many lines of console output to keep for the debugger
+fn console_history() -> usize {
+    let Ok(lines) = std::env::var(CONSOLE_HISTORY_ENV) else {
+        return 0;
+    };
+    lines.parse().unwrap_or_else(|_| {
+        log::warn!("Ignoring invalid {CONSOLE_HISTORY_ENV} value: {lines}");
+        0
+    })
+}
+
+/// Read the optional number of instructions to single-step from the environment
+fn step_count() -> u64 {
+    let Ok(count) = std::env::var(STEP_ENV) else {
+        return 0;
+    };
+    count.parse().unwrap_or_else(|_| {
+        log::warn!("Ignoring invalid {STEP_ENV} value: {count}");
+        0
+    })
+}
+
+/// Read the MMIO fault policy from the environment
+fn mmio_fault_policy() -> MmioFaultPolicy {
+    match std::env::var(MMIO_FAULT_ENV).as_deref() {
+        Err(_) | Ok("ignore") => MmioFaultPolicy::Ignore,
+        Ok("halt") => MmioFaultPolicy::Halt,
+        Ok("inject") => MmioFaultPolicy::InjectSError,
+        Ok(policy) => {
+            log::warn!("Ignoring invalid {MMIO_FAULT_ENV} value: {policy}");
+            MmioFaultPolicy::default()
+        }
+    }
+}
+
+/// Read the single-step mode from the environment
+fn step_mode() -> StepMode {
+    let Ok(mode) = std::env::var(STEP_MODE_ENV) else {
+        return StepMode::default();
+    };
+    StepMode::from_name(&mode).unwrap_or_else(|| {
+        log::warn!("Ignoring invalid {STEP_MODE_ENV} value: {mode}");
+        StepMode::default()
+    })
+}
+
+/// Default RAZ/WI registers plus any listed in the environment
+fn raz_wi_registers() -> Result<RazWiRegisters, SimppleError> {
+    let mut registers = RazWiRegisters::default();
+    if let Ok(names) = std::env::var(RAZ_WI_ENV) {
+        for name in names.split(',').filter(|name| !name.trim().is_empty()) {
+            registers.insert_named(name)?;
+        }
+    }
+    Ok(registers)
+}
+
+/// Read the hardware breakpoint addresses from the environment
+fn hw_breakpoint_addresses() -> Vec<u64> {
+    let Ok(addresses) = std::env::var(HW_BREAK_ENV) else {
+        return Vec::new();
+    };
+    addresses
+        .split(',')
+        .filter(|address| !address.trim().is_empty())
+        .filter_map(|address| {
+            let digits = address.trim().trim_start_matches("0x");
+            u64::from_str_radix(digits, 16)
+                .inspect_err(|_| log::warn!("Ignoring invalid {HW_BREAK_ENV} address: {address}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// Read the optional address to stop at from the environment
+fn run_until_address() -> Option<u64> {
+    let address = std::env::var(RUN_UNTIL_ENV).ok()?;
+    let digits = address.trim_start_matches("0x");
+    match u64::from_str_radix(digits, 16) {
+        Ok(address) => Some(address),
+        Err(_) => {
+            log::warn!("Ignoring invalid {RUN_UNTIL_ENV} value: {address}");
+            None
+        }
+    }
+}
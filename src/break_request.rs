@@ -0,0 +1,117 @@
+use crate::poll_thread::PollThread;
+use ahvf::VirtualCpu;
+use std::sync::Arc;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// How often the helper thread checks for a pending break
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Exit status of a process killed by SIGINT
+const SIGINT_EXIT_STATUS: libc::c_int = 128 + libc::SIGINT;
+
+// Set by the signal handler, which must stick to async-signal-safe calls
+static SIGINT_RECEIVED: AtomicBool = AtomicBool::new(false);
+// From a Ctrl-C until the run loop takes the break it caused
+static SIGINT_BREAK_PENDING: AtomicBool = AtomicBool::new(false);
+static INSTALL_HANDLER: Once = Once::new();
+
+extern "C" fn on_sigint(_signum: libc::c_int) {
+    if SIGINT_BREAK_PENDING.swap(true, Ordering::AcqRel) {
+        // SAFETY: _exit is async-signal-safe
+        unsafe { libc::_exit(SIGINT_EXIT_STATUS) };
+    }
+    SIGINT_RECEIVED.store(true, Ordering::Release);
+}
+
+// Route SIGINT to `on_sigint` for the rest of the process
+fn install_sigint_handler() {
+    // SAFETY: an all-zero sigaction is valid, and the handler only touches
+    // atomics and calls _exit, which are async-signal-safe
+    let result = unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut())
+    };
+    if result != 0 {
+        log::warn!(
+            "Failed to install the SIGINT handler: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Lets the host stop a running guest without killing the process.
+///
+/// Ctrl-C (SIGINT) or `request()` sets the shared flag. A helper thread then
+/// forces the vCPU out of `run()` with `hv_vcpus_exit`, which makes `run()`
+/// return `Cancelled` even if the guest never traps on its own. The run loop
+/// calls `take()` after every exit; taking the request clears it, so the
+/// guest resumes on the next `run()`.
+///
+/// A second Ctrl-C while the first one's break is still pending exits the
+/// process with status 130, so a vCPU that won't leave `run()` can still be
+/// stopped from the terminal.
+pub struct BreakRequest {
+    requested: Arc<AtomicBool>,
+    _thread: PollThread,
+}
+
+impl BreakRequest {
+    pub fn start(vcpu: &VirtualCpu) -> Self {
+        INSTALL_HANDLER.call_once(install_sigint_handler);
+
+        let requested = Arc::new(AtomicBool::new(false));
+
+        let handle = vcpu.get_handle();
+        let thread_requested = requested.clone();
+        let mut kicked = false;
+        let thread = PollThread::spawn(POLL_INTERVAL, move || {
+            if SIGINT_RECEIVED.swap(false, Ordering::AcqRel) {
+                thread_requested.store(true, Ordering::Release);
+            }
+
+            // Kick the vCPU once per request
+            if !thread_requested.load(Ordering::Acquire) {
+                kicked = false;
+            } else if !kicked {
+                kicked = true;
+                if let Err(e) = ahvf::vcpus_exit(&[handle]) {
+                    log::error!("Failed to force vCPU exit: {e:?}");
+                }
+            }
+            true
+        });
+
+        Self {
+            requested,
+            _thread: thread,
+        }
+    }
+
+    /// Shared flag, for other host threads that want to break into the guest
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.requested.clone()
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Release);
+    }
+
+    /// Whether a break is waiting to be taken
+    pub fn pending(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    /// Whether a break was requested since the last call, clearing the request
+    pub fn take(&self) -> bool {
+        let taken = self.requested.swap(false, Ordering::AcqRel);
+        if taken {
+            SIGINT_BREAK_PENDING.store(false, Ordering::Release);
+        }
+        taken
+    }
+}
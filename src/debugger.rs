@@ -1,4 +1,9 @@
-use crate::regs::SpsrEl3;
+use crate::devices::uart::ConsoleHistory;
+use crate::regs::iss::SysRegAbortISS;
+use crate::regs::{
+    ExceptionClass, ExceptionInfo, GP_REGISTERS, SpsrEl3, get_stack_pointer, read_gp_registers,
+    register_name,
+};
 use crate::{SharedMemory, SimppleError};
 use ahvf::*;
 use anyhow::Result;
@@ -7,6 +12,7 @@ use colored::{ColoredString, Colorize};
 
 pub struct Debugger {
     cs: capstone::Capstone,
+    console: Option<ConsoleHistory>,
 }
 
 impl Debugger {
@@ -16,7 +22,16 @@ impl Debugger {
             .mode(arch::arm64::ArchMode::Arm)
             .detail(true)
             .build()?;
-        Ok(Debugger { cs })
+        Ok(Debugger { cs, console: None })
+    }
+
+    /// Show the guest's latest console lines in `print_debug_info`
+    pub fn set_console_history(&mut self, console: ConsoleHistory) {
+        self.console = Some(console);
+    }
+
+    pub fn console_history(&self) -> Option<&ConsoleHistory> {
+        self.console.as_ref()
     }
 
     pub fn decode(&self, payload: &[u8], address: u64) -> Result<()> {
@@ -36,6 +51,21 @@ impl Debugger {
         Ok(())
     }
 
+    /// Disassemble the MSR/MRS a trapped system register access came from,
+    /// e.g. `mrs x0, cntpct_el0`
+    pub fn disassemble_sysreg(&self, iss: &SysRegAbortISS) -> Result<String, SimppleError> {
+        let insn = iss.reconstruct();
+        let instructions = self.cs.disasm_count(&insn.to_le_bytes(), 0, 1)?;
+        let decoded = instructions
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Capstone could not decode {insn:#010x}"))?;
+        Ok(format!(
+            "{} {}",
+            decoded.mnemonic().unwrap_or(""),
+            decoded.op_str().unwrap_or("")
+        ))
+    }
+
     pub fn print_debug_info(
         &self,
         vm: &VirtualMachine,
@@ -73,6 +103,161 @@ impl Debugger {
         // Print registers in grid format
         self.print_gp_registers_grid(vcpu)?;
 
+        println!(
+            "{}",
+            "--------------------------------------------------".bright_cyan()
+        );
+
+        println!("{}", "Backtrace:".bright_magenta().bold());
+        for (index, address) in self
+            .backtrace(vm, vcpu, mmu, BACKTRACE_MAX_FRAMES)?
+            .iter()
+            .enumerate()
+        {
+            println!("  #{index:<2} {}", format!("{address:#018x}").white());
+        }
+
+        if let Some(console) = &self.console {
+            println!(
+                "{}",
+                "--------------------------------------------------".bright_cyan()
+            );
+            println!("{}", "Recent console output:".bright_magenta().bold());
+            for line in console.recent_lines(CONSOLE_CONTEXT_LINES) {
+                println!("  {line}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collect return addresses by walking the AAPCS64 frame record chain.
+    ///
+    /// The first entries are PC and LR; after that each frame record (the
+    /// saved X29/X30 pair that X29 points to) contributes its saved LR. This
+    /// only works for code compiled with frame pointers, elsewhere X29 is just
+    /// another register and the walk stops at the first implausible record.
+    /// The walk also stops at a null FP, an unmapped or misaligned record, or
+    /// after `max_frames` addresses.
+    pub fn backtrace(
+        &self,
+        vm: &VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        mmu: &SharedMemory,
+        max_frames: usize,
+    ) -> Result<Vec<u64>, SimppleError> {
+        let pc = vcpu.get_register(Register::PC)?;
+        let lr = vcpu.get_register(Register::X30)?;
+        let mut fp = vcpu.get_register(Register::X29)?;
+
+        let mut frames = vec![pc, lr];
+        while frames.len() < max_frames && fp != 0 && fp % 8 == 0 {
+            // X29 is guest-controlled, a record at the top of the address space is bogus
+            let Some(lr_address) = fp.checked_add(8) else {
+                break;
+            };
+            let (Ok(saved_fp), Ok(saved_lr)) =
+                (mmu.read::<u64>(vm, fp), mmu.read::<u64>(vm, lr_address))
+            else {
+                break;
+            };
+
+            // Once the prologue has run, the innermost record repeats LR
+            if !(frames.len() == 2 && saved_lr == lr) {
+                frames.push(saved_lr);
+            }
+
+            // The stack grows down, so callers' records are at higher addresses
+            if saved_fp <= fp {
+                break;
+            }
+            fp = saved_fp;
+        }
+
+        frames.truncate(max_frames);
+        Ok(frames)
+    }
+
+    /// Prominently print the instruction at PC, for crash reports
+    pub fn print_faulting_instruction(
+        &self,
+        vm: &VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        mmu: &SharedMemory,
+    ) -> Result<(), SimppleError> {
+        let pc_addr = vcpu.get_register(Register::PC)?;
+
+        let description = match mmu.read_bytes(vm, pc_addr, 4) {
+            Ok(bytes) => match self.cs.disasm_all(&bytes, pc_addr) {
+                Ok(instructions) if !instructions.is_empty() => {
+                    let insn = &instructions[0];
+                    format!(
+                        "{} {}",
+                        insn.mnemonic().unwrap_or(""),
+                        insn.op_str().unwrap_or("")
+                    )
+                }
+                _ => format!(
+                    "<undecodable {:#010x}>",
+                    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                ),
+            },
+            Err(_) => "<unmapped>".to_string(),
+        };
+
+        println!(
+            "{} {}",
+            format!("failed executing at {pc_addr:#018x}:")
+                .bright_red()
+                .bold(),
+            description.trim_end().bright_yellow().bold()
+        );
+
+        Ok(())
+    }
+
+    /// Print a single register in the style of the register grid
+    pub fn print_register(&self, name: &str, value: u64) {
+        print_register_row(&[(name, value)]);
+    }
+
+    /// Print a classic hexdump of `len` bytes of guest memory at `addr`
+    pub fn hexdump(
+        &self,
+        vm: &VirtualMachine,
+        mmu: &SharedMemory,
+        addr: u64,
+        len: usize,
+    ) -> Result<(), SimppleError> {
+        let bytes = mmu.read_bytes(vm, addr, len)?;
+        let color = colored::control::SHOULD_COLORIZE.should_colorize();
+        print!("{}", format_hexdump(addr, &bytes, color));
+        Ok(())
+    }
+
+    /// Dump `count` doublewords of the stack upwards from `sp`
+    pub fn print_stack(
+        &self,
+        vm: &VirtualMachine,
+        mmu: &SharedMemory,
+        sp: u64,
+        count: usize,
+    ) -> Result<(), SimppleError> {
+        println!("{}", "Stack:".bright_magenta().bold());
+
+        // Round down so a misaligned SP still shows the surrounding frame
+        let base = sp & !0x7;
+        for index in 0..count as u64 {
+            let address = base + index * 8;
+            match mmu.read::<u64>(vm, address) {
+                Ok(value) => println!("  {address:#018x}: {}", format_register_value(value)),
+                Err(_) => {
+                    println!("  {address:#018x}: {}", "<unmapped>".bright_black());
+                    break;
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -147,26 +332,313 @@ impl Debugger {
         let mut register_values = Vec::new();
 
         // Collect all register values
-        for reg in GP_REGISTERS.iter() {
-            let value = vcpu.get_register(*reg)?;
-            register_values.push((*reg, value));
+        let values = read_gp_registers(vcpu)?;
+        for (reg, value) in GP_REGISTERS.iter().zip(values) {
+            register_values.push((register_name(*reg), value));
         }
 
         // Print in grid format
         for chunk in register_values.chunks(COLUMNS) {
-            let mut line = String::new();
-            for (reg, value) in chunk {
-                let reg_str = format!("{reg:?}");
-                let colored_reg = format_register_name(&reg_str);
-                let colored_value = format_register_value(*value);
-                let column_text = &format!("{colored_reg}:{colored_value}");
-                line.push_str(&format!("{column_text:>42}"));
+            print_register_row(chunk);
+        }
+
+        // The stack pointers get their own row: SP selected by PSTATE, then both banked ones
+        print_register_row(&[
+            ("SP", get_stack_pointer(vcpu)?),
+            ("SP_EL0", vcpu.get_system_register(SystemRegister::SP_EL0)?),
+            ("SP_EL1", vcpu.get_system_register(SystemRegister::SP_EL1)?),
+        ]);
+
+        Ok(())
+    }
+}
+
+fn print_register_row(registers: &[(&str, u64)]) {
+    let mut line = String::new();
+    for (name, value) in registers {
+        let colored_reg = format_register_name(name);
+        let colored_value = format_register_value(*value);
+        let column_text = &format!("{colored_reg}:{colored_value}");
+        line.push_str(&format!("{column_text:>42}"));
+    }
+    println!("  {line}");
+}
+
+/// Frames shown by `print_debug_info`
+const BACKTRACE_MAX_FRAMES: usize = 16;
+
+/// Console lines shown by `print_debug_info`
+const CONSOLE_CONTEXT_LINES: usize = 10;
+
+/// `BRK #0`
+const BRK_INSTRUCTION: u32 = 0xd4200000;
+
+/// A one-shot breakpoint made by patching a `BRK` over guest code.
+///
+/// Requires debug exceptions to be trapped so the `BRK` exits to the host.
+pub struct TemporaryBreakpoint {
+    address: u64,
+    original: u32,
+}
+
+impl TemporaryBreakpoint {
+    /// Patch a `BRK` at `address`, remembering the instruction it replaces.
+    ///
+    /// Read-only segments such as ROM can be patched too.
+    pub fn insert(
+        vm: &mut VirtualMachine,
+        mmu: &SharedMemory,
+        address: u64,
+    ) -> Result<Self, SimppleError> {
+        let original = mmu.read::<u32>(vm, address)?;
+        mmu.write_bytes_privileged(vm, address, &BRK_INSTRUCTION.to_le_bytes())?;
+        Ok(TemporaryBreakpoint { address, original })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Restore the original instruction
+    pub fn remove(self, vm: &mut VirtualMachine, mmu: &SharedMemory) -> Result<(), SimppleError> {
+        mmu.write_bytes_privileged(vm, self.address, &self.original.to_le_bytes())
+    }
+}
+
+/// MDSCR_EL1.SS, enables software step
+const MDSCR_SS: u64 = 1 << 0;
+
+/// MDSCR_EL1.MDE, enables breakpoint and watchpoint debug events
+const MDSCR_MDE: u64 = 1 << 15;
+
+/// DBGBCR<n>_EL1.E
+const DBGBCR_ENABLE: u64 = 1 << 0;
+
+/// DBGBCR<n>_EL1 for an enabled address match on an A64 instruction at EL1 or
+/// EL0: BAS = 0b1111, PMC = 0b11
+const DBGBCR_EXECUTE_EL1_EL0: u64 = (0b1111 << 5) | (0b11 << 1) | DBGBCR_ENABLE;
+
+/// Breakpoint value/control register pairs, Apple silicon implements six
+const BREAKPOINT_REGISTERS: [(SystemRegister, SystemRegister); 6] = [
+    (SystemRegister::DBGBVR0_EL1, SystemRegister::DBGBCR0_EL1),
+    (SystemRegister::DBGBVR1_EL1, SystemRegister::DBGBCR1_EL1),
+    (SystemRegister::DBGBVR2_EL1, SystemRegister::DBGBCR2_EL1),
+    (SystemRegister::DBGBVR3_EL1, SystemRegister::DBGBCR3_EL1),
+    (SystemRegister::DBGBVR4_EL1, SystemRegister::DBGBCR4_EL1),
+    (SystemRegister::DBGBVR5_EL1, SystemRegister::DBGBCR5_EL1),
+];
+
+/// Number of breakpoints from ID_AA64DFR0_EL1.BRPs (bits [15:12], count - 1)
+fn breakpoint_slots(id_aa64dfr0: u64) -> usize {
+    let implemented = ((id_aa64dfr0 >> 12) & 0xf) as usize + 1;
+    implemented.min(BREAKPOINT_REGISTERS.len())
+}
+
+/// Hardware breakpoint slots, remembering which ones the host programmed.
+///
+/// Guest accesses to the debug registers trap unless
+/// `TrapConfig::debug_registers` is cleared. The breakpoint registers aren't
+/// emulated, so a trapped guest can't touch the slots at all. With
+/// passthrough a self-hosted guest debugger programs the same slots
+/// directly; slots the guest has enabled are never taken, but the guest may
+/// still overwrite a slot the host programmed earlier.
+pub struct HwBreakpoints {
+    host: Vec<Option<u64>>,
+}
+
+impl HwBreakpoints {
+    pub fn new(vcpu: &mut VirtualCpu) -> Result<Self, SimppleError> {
+        let id_aa64dfr0 = vcpu.get_system_register(SystemRegister::ID_AA64DFR0_EL1)?;
+        Ok(Self {
+            host: vec![None; breakpoint_slots(id_aa64dfr0)],
+        })
+    }
+
+    /// Break before the instruction at `address` executes, returning the slot used
+    pub fn set_hw_breakpoint(
+        &mut self,
+        vcpu: &mut VirtualCpu,
+        address: u64,
+    ) -> Result<usize, SimppleError> {
+        let mut free = None;
+        for (slot, owner) in self.host.iter().enumerate() {
+            let control = vcpu.get_system_register(BREAKPOINT_REGISTERS[slot].1)?;
+            if owner.is_none() && control & DBGBCR_ENABLE == 0 {
+                free = Some(slot);
+                break;
             }
-            println!("  {line}");
         }
+        let slot = free.ok_or(SimppleError::NoFreeBreakpointSlot {
+            slots: self.host.len(),
+        })?;
+
+        let (value, control) = BREAKPOINT_REGISTERS[slot];
+        vcpu.set_system_register(value, address & !0b11)?;
+        vcpu.set_system_register(control, DBGBCR_EXECUTE_EL1_EL0)?;
+        let mdscr = vcpu.get_system_register(SystemRegister::MDSCR_EL1)?;
+        vcpu.set_system_register(SystemRegister::MDSCR_EL1, mdscr | MDSCR_MDE)?;
 
+        self.host[slot] = Some(address);
+        Ok(slot)
+    }
+
+    /// Disable a slot the host programmed
+    pub fn clear(&mut self, vcpu: &mut VirtualCpu, slot: usize) -> Result<(), SimppleError> {
+        if let Some(owner) = self.host.get_mut(slot)
+            && owner.take().is_some()
+        {
+            vcpu.set_system_register(BREAKPOINT_REGISTERS[slot].1, 0)?;
+        }
         Ok(())
     }
+
+    /// The host slot breaking at `address`, if any
+    pub fn host_slot(&self, address: u64) -> Option<usize> {
+        self.host.iter().position(|owner| *owner == Some(address))
+    }
+}
+
+/// How the guest is stopped after a single instruction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StepMode {
+    /// Architectural software step via MDSCR_EL1.SS and PSTATE.SS.
+    ///
+    /// Leaves guest memory untouched, so it works on read-only text (ROM),
+    /// and follows branches. Relies on debug exceptions being trapped to the
+    /// host, which `VcpuConfig::trap_debug` sets up.
+    #[default]
+    Software,
+    /// Patch a `BRK` over the instruction after PC.
+    ///
+    /// Use this when software step is unavailable, e.g. debug exceptions are
+    /// not trapped. Needs writable text and only catches the fall-through
+    /// path: after a taken branch the guest runs on until its next exit.
+    Breakpoint,
+}
+
+impl StepMode {
+    /// Parse a mode name, `software` or `breakpoint`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "software" => Some(StepMode::Software),
+            "breakpoint" => Some(StepMode::Breakpoint),
+            _ => None,
+        }
+    }
+
+    /// Arrange for the vCPU to exit after the instruction at PC
+    pub fn arm(
+        self,
+        vm: &mut VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        mmu: &SharedMemory,
+    ) -> Result<ArmedStep, SimppleError> {
+        match self {
+            StepMode::Software => {
+                let mdscr = vcpu.get_system_register(SystemRegister::MDSCR_EL1)?;
+                vcpu.set_system_register(SystemRegister::MDSCR_EL1, mdscr | MDSCR_SS)?;
+                let mut pstate = SpsrEl3::from_raw(vcpu.get_register(Register::CPSR)?);
+                pstate.set_ss(true);
+                vcpu.set_register(Register::CPSR, pstate.raw())?;
+                Ok(ArmedStep::Software { mdscr })
+            }
+            StepMode::Breakpoint => {
+                let pc = vcpu.get_register(Register::PC)?;
+                let breakpoint = TemporaryBreakpoint::insert(vm, mmu, pc + 4)?;
+                Ok(ArmedStep::Breakpoint(breakpoint))
+            }
+        }
+    }
+}
+
+/// A single step set up by `StepMode::arm`
+pub enum ArmedStep {
+    Software { mdscr: u64 },
+    Breakpoint(TemporaryBreakpoint),
+}
+
+impl ArmedStep {
+    /// Undo the step setup after `run()` returned `exception`, if any.
+    ///
+    /// Returns whether `exception` is the step trap itself, in which case PC
+    /// already points at the next instruction and the exit needs no handling.
+    pub fn finish(
+        self,
+        vm: &mut VirtualMachine,
+        vcpu: &mut VirtualCpu,
+        mmu: &SharedMemory,
+        exception: Option<&ExceptionInfo>,
+    ) -> Result<bool, SimppleError> {
+        let exception_class = exception.map(|exception| exception.class);
+
+        match self {
+            ArmedStep::Software { mdscr } => {
+                vcpu.set_system_register(SystemRegister::MDSCR_EL1, mdscr)?;
+                let mut pstate = SpsrEl3::from_raw(vcpu.get_register(Register::CPSR)?);
+                pstate.set_ss(false);
+                vcpu.set_register(Register::CPSR, pstate.raw())?;
+                Ok(matches!(
+                    exception_class,
+                    Some(ExceptionClass::SoftwareStepLowerEl | ExceptionClass::SoftwareStepSameEl)
+                ))
+            }
+            ArmedStep::Breakpoint(breakpoint) => {
+                let pc = vcpu.get_register(Register::PC)?;
+                let hit = exception_class == Some(ExceptionClass::BrkAArch64)
+                    && pc == breakpoint.address();
+                breakpoint.remove(vm, mmu)?;
+                Ok(hit)
+            }
+        }
+    }
+}
+
+/// Format `bytes` as 16-byte lines of address, hex bytes and an ASCII gutter.
+///
+/// With `color`, bytes outside printable ASCII are dimmed in both columns.
+fn format_hexdump(addr: u64, bytes: &[u8], color: bool) -> String {
+    const BYTES_PER_LINE: usize = 16;
+
+    let paint = |text: String, printable: bool| {
+        if color && !printable {
+            text.bright_black().to_string()
+        } else {
+            text
+        }
+    };
+
+    let mut out = String::new();
+    for (index, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let line_addr = addr + (index * BYTES_PER_LINE) as u64;
+        out.push_str(&format!("{line_addr:016x}  "));
+
+        for column in 0..BYTES_PER_LINE {
+            match line.get(column) {
+                Some(byte) => {
+                    let hex = paint(
+                        format!("{byte:02x}"),
+                        byte.is_ascii_graphic() || *byte == b' ',
+                    );
+                    out.push_str(&hex);
+                    out.push(' ');
+                }
+                // Pad the short tail line so the gutter stays aligned
+                None => out.push_str("   "),
+            }
+            if column == 7 {
+                out.push(' ');
+            }
+        }
+
+        out.push('|');
+        for byte in line {
+            let printable = byte.is_ascii_graphic() || *byte == b' ';
+            let glyph = if printable { *byte as char } else { '.' };
+            out.push_str(&paint(glyph.to_string(), printable));
+        }
+        out.push_str("|\n");
+    }
+    out
 }
 
 fn format_instruction(insn: &capstone::Insn, is_current: bool) -> ColoredString {
@@ -191,6 +663,7 @@ fn format_instruction(insn: &capstone::Insn, is_current: bool) -> ColoredString
 
 fn format_register_name(reg_name: &str) -> ColoredString {
     match reg_name {
+        name if name.starts_with("SP") => name.bright_red(),
         name if name.starts_with("X0") || name.starts_with("X1") => name.bright_green(),
         name if name.starts_with("X2") || name.starts_with("X3") => name.bright_blue(),
         name if name.contains("29") || name.contains("30") => name.bright_yellow(), // FP, LR
@@ -208,37 +681,43 @@ fn format_register_value(value: u64) -> ColoredString {
     }
 }
 
-const GP_REGISTERS: [Register; 32] = [
-    Register::X0,
-    Register::X1,
-    Register::X2,
-    Register::X3,
-    Register::X4,
-    Register::X5,
-    Register::X6,
-    Register::X7,
-    Register::X8,
-    Register::X9,
-    Register::X10,
-    Register::X11,
-    Register::X12,
-    Register::X13,
-    Register::X14,
-    Register::X15,
-    Register::X16,
-    Register::X17,
-    Register::X18,
-    Register::X19,
-    Register::X20,
-    Register::X21,
-    Register::X22,
-    Register::X23,
-    Register::X24,
-    Register::X25,
-    Register::X26,
-    Register::X27,
-    Register::X28,
-    Register::X29,
-    Register::X30,
-    Register::PC,
-];
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breakpoint_slots() {
+        // Cortex-A style ID_AA64DFR0_EL1 with BRPs = 5 (six breakpoints)
+        assert_eq!(breakpoint_slots(0x0000_0000_1030_5106), 6);
+        assert_eq!(breakpoint_slots(0x1000), 2);
+        // More than we have registers for
+        assert_eq!(breakpoint_slots(0xf000), BREAKPOINT_REGISTERS.len());
+    }
+
+    #[test]
+    fn test_step_mode_names() {
+        assert_eq!(StepMode::from_name("software"), Some(StepMode::Software));
+        assert_eq!(
+            StepMode::from_name("breakpoint"),
+            Some(StepMode::Breakpoint)
+        );
+        assert_eq!(StepMode::from_name("Breakpoint"), None);
+        assert_eq!(StepMode::from_name(""), None);
+        assert_eq!(StepMode::default(), StepMode::Software);
+    }
+
+    #[test]
+    fn test_hexdump_format() {
+        let bytes: Vec<u8> = (0x3c..0x3c + 20).collect();
+        assert_eq!(
+            format_hexdump(0x4000_0000, &bytes, false),
+            "0000000040000000  3c 3d 3e 3f 40 41 42 43  44 45 46 47 48 49 4a 4b |<=>?@ABCDEFGHIJK|\n\
+             0000000040000010  4c 4d 4e 4f                                      |LMNO|\n"
+        );
+
+        assert_eq!(
+            format_hexdump(0x10, b"a\0", false),
+            "0000000000000010  61 00                                            |a.|\n"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Guest debug port for printf-style tracing.
+//!
+//! A write-only device early guest code can use before the UART is set up.
+//! Bytes written to the data register are line-buffered like the PL011 and
+//! each completed line goes to `log::info!` under the `guest` target. A value
+//! written to the hex register is logged immediately in hexadecimal.
+
+use crate::devices::MmioDevice;
+use crate::err::MmioError;
+
+// --- Debug Port Register Offsets ---
+const DBG_DATA: u64 = 0x00; // Character output (write-only)
+const DBG_HEX: u64 = 0x08; // Log the written value as hex (write-only)
+
+/// Line-buffered debug output into the host log
+#[derive(Debug, Default)]
+pub struct DebugPort {
+    line_buffer: Vec<u8>,
+}
+
+impl DebugPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log any partial line that has not seen a newline yet
+    pub fn flush_line_buffer(&mut self) {
+        if !self.line_buffer.is_empty() {
+            let line = String::from_utf8_lossy(&self.line_buffer);
+            log::info!(target: "guest", "{}", line.trim_end_matches(['\r', '\n']));
+            self.line_buffer.clear();
+        }
+    }
+
+    fn handle_char(&mut self, byte: u8) {
+        self.line_buffer.push(byte);
+        if byte == b'\n' {
+            self.flush_line_buffer();
+        }
+    }
+}
+
+impl Drop for DebugPort {
+    fn drop(&mut self) {
+        self.flush_line_buffer();
+    }
+}
+
+impl MmioDevice for DebugPort {
+    fn read(&mut self, offset: u64, _size: usize) -> Result<u64, MmioError> {
+        match offset {
+            // Write-only registers read as zero
+            DBG_DATA | DBG_HEX => Ok(0),
+            _ => Err(MmioError::UnmappedAccess(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        match offset {
+            DBG_DATA => self.handle_char(value as u8),
+            DBG_HEX => {
+                let digits = size * 2;
+                log::info!(target: "guest", "{value:#0width$x}", width = digits + 2);
+            }
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.line_buffer.clear();
+    }
+
+    fn get_size(&self) -> u64 {
+        0x1000 // Debug port occupies a 4KB memory region
+    }
+
+    fn name(&self) -> &str {
+        "debug-port"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_are_buffered() {
+        let mut port = DebugPort::new();
+        for &byte in b"boot" {
+            port.write(DBG_DATA, 1, u64::from(byte)).unwrap();
+        }
+        assert_eq!(port.line_buffer, b"boot");
+
+        // A newline sends the line off
+        port.write(DBG_DATA, 1, u64::from(b'\n')).unwrap();
+        assert!(port.line_buffer.is_empty());
+
+        port.write(DBG_DATA, 1, u64::from(b'x')).unwrap();
+        port.reset();
+        assert!(port.line_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut port = DebugPort::new();
+        port.write(DBG_HEX, 4, 0xdead_beef).unwrap();
+        assert_eq!(port.read(DBG_DATA, 4).unwrap(), 0);
+        assert_eq!(port.read(DBG_HEX, 8).unwrap(), 0);
+        assert!(port.read(0x10, 4).is_err());
+        assert!(port.write(0x10, 4, 0).is_err());
+    }
+}
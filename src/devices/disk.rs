@@ -0,0 +1,240 @@
+//! Minimal sector disk backed by a host file.
+//!
+//! Not virtio-blk: the guest programs a sector number and the guest-physical
+//! address of a 512-byte buffer, then writes a command. The transfer happens
+//! synchronously through the DMA handle, after which the status register
+//! reports the outcome. Guests should still poll the status register so they
+//! keep working once transfers become asynchronous.
+
+use crate::SimppleError;
+use crate::devices::{DmaAccess, MmioDevice};
+use crate::err::MmioError;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const SECTOR_SIZE: usize = 512;
+
+// --- Disk Register Offsets ---
+const DISK_SECTOR_LO: u64 = 0x00; // Sector number, low word
+const DISK_SECTOR_HI: u64 = 0x04; // Sector number, high word
+const DISK_BUFFER_LO: u64 = 0x08; // Guest-physical buffer address, low word
+const DISK_BUFFER_HI: u64 = 0x0C; // Guest-physical buffer address, high word
+const DISK_COMMAND: u64 = 0x10; // Start a transfer (write-only)
+const DISK_STATUS: u64 = 0x14; // Outcome of the last command, write to clear
+const DISK_CAPACITY: u64 = 0x18; // Number of sectors (read-only)
+
+// --- Commands ---
+const CMD_READ: u64 = 1; // Disk sector -> guest buffer
+const CMD_WRITE: u64 = 2; // Guest buffer -> disk sector
+
+// --- Status values ---
+const STATUS_IDLE: u32 = 0;
+const STATUS_DONE: u32 = 1;
+const STATUS_ERROR: u32 = 2;
+
+/// Sector disk over any seekable backing store
+pub struct SectorDisk<F: Read + Write + Seek = File> {
+    backing: F,
+    sectors: u64,
+    sector: u64,
+    buffer: u64,
+    status: u32,
+}
+
+impl SectorDisk<File> {
+    /// Open `path` read-write as the disk image.
+    ///
+    /// A trailing partial sector is not accessible to the guest.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SimppleError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(anyhow::Error::from)?;
+        Self::new(file)
+    }
+}
+
+impl<F: Read + Write + Seek> SectorDisk<F> {
+    pub fn new(mut backing: F) -> Result<Self, SimppleError> {
+        let len = backing
+            .seek(SeekFrom::End(0))
+            .map_err(anyhow::Error::from)?;
+        Ok(Self {
+            backing,
+            sectors: len / SECTOR_SIZE as u64,
+            sector: 0,
+            buffer: 0,
+            status: STATUS_IDLE,
+        })
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.sectors
+    }
+
+    /// Run a command, returning a description of what went wrong
+    fn execute(&mut self, command: u64, dma: &mut dyn DmaAccess) -> Result<(), String> {
+        if self.sector >= self.sectors {
+            return Err(format!(
+                "sector {} beyond end of disk ({} sectors)",
+                self.sector, self.sectors
+            ));
+        }
+
+        let position = self.sector * SECTOR_SIZE as u64;
+        let mut data = [0u8; SECTOR_SIZE];
+        match command {
+            CMD_READ => {
+                self.backing
+                    .seek(SeekFrom::Start(position))
+                    .and_then(|_| self.backing.read_exact(&mut data))
+                    .map_err(|e| e.to_string())?;
+                dma.write(self.buffer, &data).map_err(|e| e.to_string())
+            }
+            CMD_WRITE => {
+                dma.read(self.buffer, &mut data)
+                    .map_err(|e| e.to_string())?;
+                self.backing
+                    .seek(SeekFrom::Start(position))
+                    .and_then(|_| self.backing.write_all(&data))
+                    .and_then(|_| self.backing.flush())
+                    .map_err(|e| e.to_string())
+            }
+            _ => Err(format!("unknown command {command:#x}")),
+        }
+    }
+}
+
+impl<F: Read + Write + Seek> MmioDevice for SectorDisk<F> {
+    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let value = match offset {
+            DISK_SECTOR_LO => self.sector & 0xffff_ffff,
+            DISK_SECTOR_HI => self.sector >> 32,
+            DISK_BUFFER_LO => self.buffer & 0xffff_ffff,
+            DISK_BUFFER_HI => self.buffer >> 32,
+            DISK_COMMAND => 0, // Write-only
+            DISK_STATUS => u64::from(self.status),
+            DISK_CAPACITY => self.sectors.min(u64::from(u32::MAX)),
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        };
+
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let value = value & 0xffff_ffff;
+        match offset {
+            DISK_SECTOR_LO => self.sector = (self.sector & !0xffff_ffff) | value,
+            DISK_SECTOR_HI => self.sector = (self.sector & 0xffff_ffff) | (value << 32),
+            DISK_BUFFER_LO => self.buffer = (self.buffer & !0xffff_ffff) | value,
+            DISK_BUFFER_HI => self.buffer = (self.buffer & 0xffff_ffff) | (value << 32),
+            DISK_COMMAND => {
+                return Err(MmioError::DeviceError(
+                    "disk commands need DMA access".to_string(),
+                ));
+            }
+            DISK_STATUS => self.status = STATUS_IDLE,
+
+            // Ignore writes to read-only registers
+            DISK_CAPACITY => { /* Read Only */ }
+
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        }
+
+        Ok(())
+    }
+
+    fn write_dma(
+        &mut self,
+        offset: u64,
+        size: usize,
+        value: u64,
+        dma: &mut dyn DmaAccess,
+    ) -> Result<(), MmioError> {
+        if offset != DISK_COMMAND || size != 4 {
+            return self.write(offset, size, value);
+        }
+
+        // Transfer failures are reported to the guest, not to the host
+        self.status = match self.execute(value, dma) {
+            Ok(()) => STATUS_DONE,
+            Err(e) => {
+                log::warn!(
+                    "Disk command {value:#x} at sector {} failed: {e}",
+                    self.sector
+                );
+                STATUS_ERROR
+            }
+        };
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.sector = 0;
+        self.buffer = 0;
+        self.status = STATUS_IDLE;
+    }
+
+    fn get_size(&self) -> u64 {
+        0x1000 // Disk controller occupies a 4KB memory region
+    }
+
+    fn max_access_size(&self) -> usize {
+        4
+    }
+
+    fn name(&self) -> &str {
+        "disk"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::dma::FakeRam;
+    use std::io::Cursor;
+
+    const RAM_BASE: u64 = 0x4000_0000;
+
+    fn command(disk: &mut SectorDisk<Cursor<Vec<u8>>>, ram: &mut FakeRam, sector: u64, cmd: u64) {
+        disk.write(DISK_SECTOR_LO, 4, sector).unwrap();
+        disk.write(DISK_BUFFER_LO, 4, RAM_BASE).unwrap();
+        disk.write_dma(DISK_COMMAND, 4, cmd, ram).unwrap();
+    }
+
+    #[test]
+    fn test_sector_round_trip() {
+        let mut image = vec![0u8; 4 * SECTOR_SIZE];
+        image[SECTOR_SIZE..2 * SECTOR_SIZE].fill(0xab);
+        let mut disk = SectorDisk::new(Cursor::new(image)).unwrap();
+        let mut ram = FakeRam::new(RAM_BASE, SECTOR_SIZE);
+        assert_eq!(disk.read(DISK_CAPACITY, 4).unwrap(), 4);
+
+        command(&mut disk, &mut ram, 1, CMD_READ);
+        assert_eq!(disk.read(DISK_STATUS, 4).unwrap(), u64::from(STATUS_DONE));
+        let mut sector = [0u8; SECTOR_SIZE];
+        ram.read(RAM_BASE, &mut sector).unwrap();
+        assert!(sector.iter().all(|&byte| byte == 0xab));
+
+        command(&mut disk, &mut ram, 3, CMD_WRITE);
+        assert_eq!(disk.read(DISK_STATUS, 4).unwrap(), u64::from(STATUS_DONE));
+        let image = disk.backing.get_ref();
+        assert!(image[3 * SECTOR_SIZE..].iter().all(|&byte| byte == 0xab));
+
+        // Out of range sectors fail without touching the image
+        command(&mut disk, &mut ram, 4, CMD_READ);
+        assert_eq!(disk.read(DISK_STATUS, 4).unwrap(), u64::from(STATUS_ERROR));
+        disk.write(DISK_STATUS, 4, 0).unwrap();
+        assert_eq!(disk.read(DISK_STATUS, 4).unwrap(), u64::from(STATUS_IDLE));
+    }
+}
@@ -0,0 +1,318 @@
+//! Simple linear framebuffer with host-side readout.
+//!
+//! The pixel data lives in an ordinary RAM segment that the guest draws into
+//! directly. Only the small control block (resolution, pixel format and the
+//! location of the pixel memory) is exposed through MMIO. The host keeps a
+//! clone of the device to snapshot the screen into a PNG file.
+
+use crate::devices::MmioDevice;
+use crate::err::{MemoryError, MmioError};
+use crate::mems::digest::crc32;
+use crate::{SharedMemory, SimppleError};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// --- Control Register Offsets ---
+const FB_WIDTH: u64 = 0x00; // Width in pixels
+const FB_HEIGHT: u64 = 0x04; // Height in pixels
+const FB_FORMAT: u64 = 0x08; // Pixel format, see `PixelFormat`
+const FB_STRIDE: u64 = 0x0C; // Bytes per line (read-only)
+const FB_BASE_LO: u64 = 0x10; // Pixel memory base, low word (read-only)
+const FB_BASE_HI: u64 = 0x14; // Pixel memory base, high word (read-only)
+const FB_SIZE: u64 = 0x18; // Pixel memory size in bytes (read-only)
+
+/// Pixel layouts the guest can select through `FB_FORMAT`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 32 bits per pixel, bytes in memory are B, G, R, X
+    Xrgb8888 = 0,
+    /// 16 bits per pixel, little-endian R5 G6 B5
+    Rgb565 = 1,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Xrgb8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+
+    fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(PixelFormat::Xrgb8888),
+            1 => Some(PixelFormat::Rgb565),
+            _ => None,
+        }
+    }
+
+    /// Expand one pixel into 8-bit R, G, B
+    fn to_rgb(self, pixel: &[u8]) -> [u8; 3] {
+        match self {
+            PixelFormat::Xrgb8888 => [pixel[2], pixel[1], pixel[0]],
+            PixelFormat::Rgb565 => {
+                let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let r = ((value >> 11) & 0x1f) as u8;
+                let g = ((value >> 5) & 0x3f) as u8;
+                let b = (value & 0x1f) as u8;
+                [
+                    (r << 3) | (r >> 2),
+                    (g << 2) | (g >> 4),
+                    (b << 3) | (b >> 2),
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FramebufferState {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+}
+
+/// Framebuffer control block
+///
+/// Cloning yields another handle to the same framebuffer, so one clone can be
+/// registered with the `MmioManager` while the host keeps the other.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pixel_base: u64,
+    pixel_size: usize,
+    state: Arc<Mutex<FramebufferState>>,
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer whose pixels live at `pixel_base` in guest memory
+    pub fn new(pixel_base: u64, pixel_size: usize) -> Self {
+        Self {
+            pixel_base,
+            pixel_size,
+            state: Arc::new(Mutex::new(FramebufferState {
+                width: 0,
+                height: 0,
+                format: PixelFormat::Xrgb8888,
+            })),
+        }
+    }
+
+    /// Maps the RW segment holding the pixel data
+    pub fn map_pixels(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        mmu: &mut SharedMemory,
+    ) -> Result<(), SimppleError> {
+        mmu.add_segment(
+            vm,
+            self.pixel_base,
+            self.pixel_size,
+            ahvf::MemoryPermission::READ_WRITE,
+        )
+    }
+
+    /// Current resolution as (width, height)
+    pub fn resolution(&self) -> (u32, u32) {
+        let state = self.state.lock().unwrap();
+        (state.width, state.height)
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.state.lock().unwrap().format
+    }
+
+    /// Reads the visible pixels and converts them to packed 8-bit RGB
+    pub fn capture_rgb(
+        &self,
+        vm: &ahvf::VirtualMachine,
+        mmu: &SharedMemory,
+    ) -> Result<Vec<u8>, SimppleError> {
+        let (width, height) = self.resolution();
+        let format = self.format();
+        // The resolution is guest-programmed, a product that overflows is too large anyway
+        let size = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(format.bytes_per_pixel()))
+            .unwrap_or(usize::MAX);
+        if size > self.pixel_size {
+            return Err(MemoryError::invalid_size(size).into());
+        }
+
+        let pixels = mmu.read_bytes(vm, self.pixel_base, size)?;
+        Ok(pixels
+            .chunks_exact(format.bytes_per_pixel())
+            .flat_map(|pixel| format.to_rgb(pixel))
+            .collect())
+    }
+
+    /// Snapshots the screen into a PNG file
+    pub fn capture_png<P: AsRef<Path>>(
+        &self,
+        vm: &ahvf::VirtualMachine,
+        mmu: &SharedMemory,
+        path: P,
+    ) -> Result<(), SimppleError> {
+        let (width, height) = self.resolution();
+        let rgb = self.capture_rgb(vm, mmu)?;
+        fs::write(path, encode_png(width, height, &rgb)).map_err(anyhow::Error::from)?;
+        Ok(())
+    }
+}
+
+impl MmioDevice for Framebuffer {
+    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let state = self.state.lock().unwrap();
+        let value = match offset {
+            FB_WIDTH => u64::from(state.width),
+            FB_HEIGHT => u64::from(state.height),
+            FB_FORMAT => state.format as u64,
+            FB_STRIDE => u64::from(state.width) * state.format.bytes_per_pixel() as u64,
+            FB_BASE_LO => self.pixel_base & 0xffff_ffff,
+            FB_BASE_HI => self.pixel_base >> 32,
+            FB_SIZE => self.pixel_size as u64,
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        };
+
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match offset {
+            FB_WIDTH => state.width = value as u32,
+            FB_HEIGHT => state.height = value as u32,
+            FB_FORMAT => {
+                state.format = PixelFormat::from_raw(value).ok_or_else(|| {
+                    MmioError::DeviceError(format!("Unsupported pixel format {value}"))
+                })?;
+            }
+
+            // Ignore writes to read-only registers
+            FB_STRIDE | FB_BASE_LO | FB_BASE_HI | FB_SIZE => { /* Read Only */ }
+
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.width = 0;
+        state.height = 0;
+        state.format = PixelFormat::Xrgb8888;
+    }
+
+    fn get_size(&self) -> u64 {
+        0x1000 // Control block occupies a 4KB memory region
+    }
+
+    fn max_access_size(&self) -> usize {
+        4
+    }
+
+    fn name(&self) -> &str {
+        "framebuffer"
+    }
+}
+
+// --- Minimal PNG encoder (8-bit RGB, uncompressed deflate blocks) ---
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Largest payload of a single stored deflate block
+const DEFLATE_STORED_MAX: usize = 0xffff;
+
+fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit depth, truecolor, no interlace
+    write_chunk(&mut png, b"IHDR", &ihdr);
+
+    // Each scanline is prefixed with filter type 0 (none)
+    let mut raw = Vec::with_capacity(rgb.len() + height as usize);
+    let line = width as usize * 3;
+    if line > 0 {
+        for scanline in rgb.chunks(line) {
+            raw.push(0);
+            raw.extend_from_slice(scanline);
+        }
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; // deflate, 32K window, no compression
+    let mut blocks = data.chunks(DEFLATE_STORED_MAX).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_png_structure() {
+        let png = encode_png(1, 1, &[0xff, 0x00, 0x00]);
+
+        assert_eq!(&png[..8], &PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        // IEND chunk with its well-known CRC
+        assert_eq!(
+            &png[png.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_rgb565_expansion() {
+        assert_eq!(PixelFormat::Rgb565.to_rgb(&[0x00, 0xf8]), [0xff, 0, 0]);
+        assert_eq!(PixelFormat::Rgb565.to_rgb(&[0xe0, 0x07]), [0, 0xff, 0]);
+        assert_eq!(PixelFormat::Rgb565.to_rgb(&[0x1f, 0x00]), [0, 0, 0xff]);
+    }
+}
@@ -8,6 +8,7 @@
 
 use crate::devices::MmioDevice;
 use crate::err::MmioError;
+use std::ops::Range;
 
 // --- ARM PL061 Register Offsets ---
 // These are byte offsets from the base address.
@@ -161,6 +162,14 @@ impl MmioDevice for Pl061Gpio {
         Ok(())
     }
 
+    /// Lists the decoded registers; everything else reads as zero and ignores writes.
+    fn implemented_offsets(&self) -> Vec<Range<u64>> {
+        vec![
+            GPIODATA..GPIOAFSEL + 4, // Masked data window, then the control registers
+            GPIO_PERIPH_ID_BASE..0x1000,
+        ]
+    }
+
     /// Resets the GPIO device to its default state.
     fn reset(&mut self) {
         self.data = 0;
@@ -173,4 +182,8 @@ impl MmioDevice for Pl061Gpio {
     fn get_size(&self) -> u64 {
         0x1000 // PL061 occupies a 4KB memory region
     }
+
+    fn name(&self) -> &str {
+        "pl061"
+    }
 }
@@ -0,0 +1,195 @@
+//! Mailbox for host-guest coordination in tests.
+//!
+//! A fixed number of 64-bit slots that both sides can read and write, plus
+//! a doorbell in each direction. The guest writes `MAILBOX_DOORBELL` to
+//! signal the host; the host calls `ring` to raise the mailbox interrupt,
+//! which the guest acknowledges through `MAILBOX_IRQ_STATUS`. All registers
+//! are 64 bits wide and only doubleword accesses are accepted.
+
+use crate::devices::MmioDevice;
+use crate::err::MmioError;
+use std::sync::{Arc, Mutex};
+
+// --- Mailbox Register Offsets ---
+const MAILBOX_SLOT_COUNT: u64 = 0x00; // Number of slots (read-only)
+const MAILBOX_DOORBELL: u64 = 0x08; // Guest to host doorbell, the value is passed along
+const MAILBOX_IRQ_STATUS: u64 = 0x10; // Host to guest doorbell, write 1 to clear
+const MAILBOX_SLOTS: u64 = 0x100; // First slot, slots follow every 8 bytes
+
+const IRQ_PENDING: u64 = 1 << 0;
+
+#[derive(Debug)]
+struct MailboxState {
+    slots: Vec<u64>,
+    doorbell: Option<u64>, // Last doorbell value the host hasn't taken yet
+    irq_pending: bool,
+}
+
+/// Mailbox device, cloning yields another handle to the same mailbox.
+///
+/// Register one clone with the MMIO manager and keep another on the host.
+#[derive(Clone, Debug)]
+pub struct Mailbox {
+    state: Arc<Mutex<MailboxState>>,
+}
+
+impl Mailbox {
+    /// Create a mailbox with `slots` zeroed slots
+    pub fn new(slots: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MailboxState {
+                slots: vec![0; slots],
+                doorbell: None,
+                irq_pending: false,
+            })),
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.state.lock().unwrap().slots.len()
+    }
+
+    pub fn get_slot(&self, index: usize) -> Option<u64> {
+        self.state.lock().unwrap().slots.get(index).copied()
+    }
+
+    /// Set a slot, returning false if there is no such slot
+    pub fn set_slot(&self, index: usize, value: u64) -> bool {
+        match self.state.lock().unwrap().slots.get_mut(index) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Raise the mailbox interrupt to signal the guest
+    pub fn ring(&self) {
+        self.state.lock().unwrap().irq_pending = true;
+    }
+
+    /// Whether the guest has yet to acknowledge a `ring`
+    pub fn irq_pending(&self) -> bool {
+        self.state.lock().unwrap().irq_pending
+    }
+
+    /// Value of the last guest doorbell write, if any since the previous call
+    pub fn take_doorbell(&self) -> Option<u64> {
+        self.state.lock().unwrap().doorbell.take()
+    }
+
+    // Slot index for a register offset inside the slot array
+    fn slot_index(offset: u64, slots: usize) -> Option<usize> {
+        let index = offset.checked_sub(MAILBOX_SLOTS)?;
+        (index % 8 == 0 && index / 8 < slots as u64).then_some((index / 8) as usize)
+    }
+}
+
+impl MmioDevice for Mailbox {
+    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
+        if size != 8 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let state = self.state.lock().unwrap();
+        let value = match offset {
+            MAILBOX_SLOT_COUNT => state.slots.len() as u64,
+            MAILBOX_DOORBELL => state.doorbell.unwrap_or(0),
+            MAILBOX_IRQ_STATUS => {
+                if state.irq_pending {
+                    IRQ_PENDING
+                } else {
+                    0
+                }
+            }
+            _ => match Self::slot_index(offset, state.slots.len()) {
+                Some(index) => state.slots[index],
+                None => return Err(MmioError::UnmappedAccess(offset)),
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        if size != 8 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match offset {
+            MAILBOX_DOORBELL => {
+                log::debug!("Mailbox doorbell rung by guest: {value:#x}");
+                state.doorbell = Some(value);
+            }
+            MAILBOX_IRQ_STATUS => {
+                if value & IRQ_PENDING != 0 {
+                    state.irq_pending = false;
+                }
+            }
+
+            // Ignore writes to read-only registers
+            MAILBOX_SLOT_COUNT => { /* Read Only */ }
+
+            _ => match Self::slot_index(offset, state.slots.len()) {
+                Some(index) => state.slots[index] = value,
+                None => return Err(MmioError::UnmappedAccess(offset)),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.state.lock().unwrap().irq_pending
+    }
+
+    /// Slots are shared with the host and survive a reset
+    fn reset(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.doorbell = None;
+        state.irq_pending = false;
+    }
+
+    fn get_size(&self) -> u64 {
+        0x1000 // Mailbox occupies a 4KB memory region
+    }
+
+    fn name(&self) -> &str {
+        "mailbox"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_guest_round_trip() {
+        let host = Mailbox::new(4);
+        let mut guest = host.clone();
+        assert_eq!(guest.read(MAILBOX_SLOT_COUNT, 8).unwrap(), 4);
+
+        // Host to guest: fill a slot, then ring
+        assert!(host.set_slot(2, 0xdead_beef));
+        assert!(!host.set_slot(4, 0));
+        host.ring();
+        assert_eq!(guest.read(MAILBOX_SLOTS + 16, 8).unwrap(), 0xdead_beef);
+        assert_eq!(guest.read(MAILBOX_IRQ_STATUS, 8).unwrap(), IRQ_PENDING);
+        guest.write(MAILBOX_IRQ_STATUS, 8, IRQ_PENDING).unwrap();
+        assert!(!host.irq_pending());
+
+        // Guest to host: answer in a slot, then write the doorbell
+        guest.write(MAILBOX_SLOTS, 8, 42).unwrap();
+        guest.write(MAILBOX_DOORBELL, 8, 1).unwrap();
+        assert_eq!(host.get_slot(0), Some(42));
+        assert_eq!(host.take_doorbell(), Some(1));
+        assert_eq!(host.take_doorbell(), None);
+
+        assert!(matches!(
+            guest.read(MAILBOX_SLOTS + 32, 8),
+            Err(MmioError::UnmappedAccess(_))
+        ));
+    }
+}
@@ -1,32 +1,226 @@
 use std::collections::BTreeMap;
+use std::ops::Range;
 
+use crate::devices::DmaAccess;
+use crate::devices::trace::{MmioAccess, MmioAccessKind, MmioLog, MmioTrace};
 use crate::err::MmioError;
 
 pub trait MmioDevice {
     fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError>;
     fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError>;
+
+    /// Like `read`, for devices that need to access guest memory
+    fn read_dma(
+        &mut self,
+        offset: u64,
+        size: usize,
+        _dma: &mut dyn DmaAccess,
+    ) -> Result<u64, MmioError> {
+        self.read(offset, size)
+    }
+
+    /// Like `write`, for devices that need to access guest memory
+    fn write_dma(
+        &mut self,
+        offset: u64,
+        size: usize,
+        value: u64,
+        _dma: &mut dyn DmaAccess,
+    ) -> Result<(), MmioError> {
+        self.write(offset, size, value)
+    }
+
+    /// Apply writes buffered by a coalesced region, in the order the guest made them
+    fn write_batch(&mut self, writes: &[CoalescedWrite]) -> Result<(), MmioError> {
+        for write in writes {
+            self.write(write.offset, write.size, write.value)?;
+        }
+        Ok(())
+    }
+
     fn reset(&mut self);
     fn get_size(&self) -> u64;
+
+    /// Widest access the device takes in one call.
+    ///
+    /// Wider or misaligned guest accesses are split into naturally aligned
+    /// pieces no wider than this, in ascending address order. Devices with
+    /// 32-bit registers return 4.
+    fn max_access_size(&self) -> usize {
+        8
+    }
+
+    /// Register offsets the device decodes, for sweeping its register map.
+    ///
+    /// Accesses outside these ranges fall through to the device's catch-all,
+    /// e.g. `UnmappedAccess` or RAZ/WI. Empty when the device doesn't say.
+    fn implemented_offsets(&self) -> Vec<Range<u64>> {
+        Vec::new()
+    }
+
+    /// Whether the device's interrupt line is asserted.
+    ///
+    /// Polled before every guest entry, devices without an interrupt keep
+    /// the default.
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    /// Short name used in error reports
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Granularity of MMIO windows.
+///
+/// Hypervisor.framework exits on any access to an unmapped IPA, so trapping
+/// itself imposes no alignment. Windows are kept on 512-byte boundaries, the
+/// stride of virtio-mmio transports and the smallest window of any device,
+/// so a device never shares its first or last register block with another.
+pub const MMIO_REGION_ALIGNMENT: u64 = 0x200;
+
+/// Writes a coalesced region buffers before flushing to its device
+pub const COALESCED_MMIO_ENTRIES: usize = 256;
+
+/// A buffered write to a coalesced region, `offset` is relative to the region
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoalescedWrite {
+    pub offset: u64,
+    pub size: usize,
+    pub value: u64,
+}
+
+/// What the run loop does when a guest MMIO access fails
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MmioFaultPolicy {
+    /// Log the error and continue; failed reads leave the register unchanged
+    #[default]
+    Ignore,
+    /// Stop the guest with `VmExit::MmioFault`
+    Halt,
+    /// Complete the instruction, then raise an SError in the guest.
+    ///
+    /// A synchronous external abort would be more precise, but the injector
+    /// only builds SError and UNDEFINED syndromes.
+    InjectSError,
 }
 
 struct MmioRegion {
     base_addr: u64,
     size: u64,
     device: Box<dyn MmioDevice>,
+    // Pending writes, while the region is coalesced
+    coalesced: Option<Vec<CoalescedWrite>>,
+}
+
+impl MmioRegion {
+    fn read(
+        &mut self,
+        offset: u64,
+        size: usize,
+        dma: Option<&mut dyn DmaAccess>,
+    ) -> Result<u64, MmioError> {
+        match dma {
+            Some(dma) => self.device.read_dma(offset, size, dma),
+            None => self.device.read(offset, size),
+        }
+        .map_err(|e| MmioError::device_fault(self.device.name(), self.base_addr + offset, size, e))
+    }
+
+    fn write(
+        &mut self,
+        offset: u64,
+        size: usize,
+        value: u64,
+        dma: Option<&mut dyn DmaAccess>,
+    ) -> Result<(), MmioError> {
+        if let Some(pending) = self.coalesced.as_mut() {
+            pending.push(CoalescedWrite {
+                offset,
+                size,
+                value,
+            });
+            if pending.len() >= COALESCED_MMIO_ENTRIES {
+                return self.flush();
+            }
+            return Ok(());
+        }
+        match dma {
+            Some(dma) => self.device.write_dma(offset, size, value, dma),
+            None => self.device.write(offset, size, value),
+        }
+        .map_err(|e| MmioError::device_fault(self.device.name(), self.base_addr + offset, size, e))
+    }
+
+    fn flush(&mut self) -> Result<(), MmioError> {
+        let Some(pending) = self
+            .coalesced
+            .as_mut()
+            .filter(|pending| !pending.is_empty())
+        else {
+            return Ok(());
+        };
+        let writes = std::mem::take(pending);
+        // Errors surface late, they are blamed on the first write of the batch
+        self.device.write_batch(&writes).map_err(|e| {
+            MmioError::device_fault(
+                self.device.name(),
+                self.base_addr + writes[0].offset,
+                writes[0].size,
+                e,
+            )
+        })
+    }
 }
 
 #[derive(Default)]
 pub struct MmioManager {
     regions: BTreeMap<u64, MmioRegion>, // Sorted by base address
+    trace: MmioTrace,
+    fault_policy: MmioFaultPolicy,
 }
 
 impl MmioManager {
+    /// Choose how the run loop reacts to failed guest accesses
+    pub fn set_mmio_fault_policy(&mut self, policy: MmioFaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    pub fn mmio_fault_policy(&self) -> MmioFaultPolicy {
+        self.fault_policy
+    }
+
     pub fn register_device(
         &mut self,
         base: u64,
         device: Box<dyn MmioDevice>,
     ) -> Result<(), MmioError> {
         let size = device.get_size();
+        self.register_device_with_size(base, size, device)
+    }
+
+    /// Register a device with a window of `size` bytes instead of its `get_size`.
+    ///
+    /// The window may be larger than the device's registers, e.g. to reserve
+    /// room for more queues, but not smaller.
+    pub fn register_device_with_size(
+        &mut self,
+        base: u64,
+        size: u64,
+        device: Box<dyn MmioDevice>,
+    ) -> Result<(), MmioError> {
+        if size == 0
+            || size < device.get_size()
+            || !base.is_multiple_of(MMIO_REGION_ALIGNMENT)
+            || !size.is_multiple_of(MMIO_REGION_ALIGNMENT)
+        {
+            return Err(MmioError::MisalignedRegion {
+                base,
+                size,
+                alignment: MMIO_REGION_ALIGNMENT,
+            });
+        }
 
         // Check for overlaps
         if let Some(existing) = self.find_overlap(base, size) {
@@ -39,19 +233,112 @@ impl MmioManager {
                 base_addr: base,
                 size,
                 device,
+                coalesced: None,
             },
         );
 
         Ok(())
     }
 
+    /// Remove the device registered at `base` and hand it back, `None` if there is none.
+    ///
+    /// Writes still buffered for a coalesced region are delivered first.
+    pub fn unregister_device(&mut self, base: u64) -> Option<Box<dyn MmioDevice>> {
+        let mut region = self.regions.remove(&base)?;
+        if let Err(e) = region.flush() {
+            log::warn!("Unregistering {} at {base:#x}: {e}", region.device.name());
+        }
+        Some(region.device)
+    }
+
+    /// Base and size of every region, by ascending base
+    pub fn iter_regions(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.regions
+            .values()
+            .map(|region| (region.base_addr, region.size))
+    }
+
+    /// Buffer writes to the region at `base` instead of trapping them into the device.
+    ///
+    /// For write-heavy regions such as a framebuffer. Buffered writes reach
+    /// the device through `MmioDevice::write_batch` in the order the guest
+    /// made them, and all of them before any read from the same region is
+    /// handled. They are flushed when the buffer holds
+    /// `COALESCED_MMIO_ENTRIES` writes and on `flush_coalesced`. There is no
+    /// ordering against other regions: a buffered write may reach its device
+    /// after a later write to another device, so only coalesce regions whose
+    /// side effects nothing else waits on. Device errors are reported at
+    /// flush time rather than by the faulting write. Disabling flushes.
+    pub fn set_coalesced(&mut self, base: u64, enabled: bool) -> Result<(), MmioError> {
+        let region = self
+            .regions
+            .get_mut(&base)
+            .ok_or(MmioError::UnmappedAccess(base))?;
+        if enabled {
+            region.coalesced.get_or_insert_with(Vec::new);
+            Ok(())
+        } else {
+            let result = region.flush();
+            region.coalesced = None;
+            result
+        }
+    }
+
+    /// Put every device back in its reset state.
+    ///
+    /// Writes still buffered for coalesced regions are dropped, the reset
+    /// would undo them anyway.
+    pub fn reset_devices(&mut self) {
+        for region in self.regions.values_mut() {
+            if let Some(pending) = region.coalesced.as_mut() {
+                pending.clear();
+            }
+            region.device.reset();
+        }
+    }
+
+    /// Deliver the pending writes of every coalesced region
+    pub fn flush_coalesced(&mut self) -> Result<(), MmioError> {
+        for region in self.regions.values_mut() {
+            region.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Start recording every successful access, discarding any replay
+    pub fn start_recording(&mut self) {
+        self.trace = MmioTrace::Record(MmioLog::new());
+    }
+
+    /// Stop recording and return the accesses seen so far
+    pub fn take_recording(&mut self) -> Option<MmioLog> {
+        match std::mem::take(&mut self.trace) {
+            MmioTrace::Record(log) => Some(log),
+            other => {
+                self.trace = other;
+                None
+            }
+        }
+    }
+
+    /// Whether any device asserts its interrupt line.
+    ///
+    /// There is no interrupt controller, all lines are ORed into the vCPU's IRQ.
+    pub fn irq_pending(&mut self) -> bool {
+        self.regions
+            .values_mut()
+            .any(|region| region.device.irq_pending())
+    }
+
+    /// Answer reads from `log` instead of the devices, checking writes against it
+    pub fn start_replay(&mut self, log: MmioLog) {
+        self.trace = MmioTrace::Replay { log, next: 0 };
+    }
+
     fn locate(&mut self, addr: u64, size: usize) -> Result<&mut MmioRegion, MmioError> {
         if !matches!(size, 1 | 2 | 4 | 8) {
             return Err(MmioError::InvalidSize { size });
         }
-        if addr & (size as u64 - 1) != 0 {
-            return Err(MmioError::InvalidAlignment { addr, size });
-        }
         // Find the device
         let region = self.find_region(addr)?;
         let offset = addr - region.base_addr;
@@ -64,18 +351,146 @@ impl MmioManager {
     }
 
     pub fn handle_write(&mut self, addr: u64, size: usize, value: u64) -> Result<(), MmioError> {
-        log::debug!("Write {value} to {addr:#0x} of size {size}");
+        self.dispatch_write(addr, size, value, None)
+    }
+
+    /// Handle a write, letting the device access guest memory through `dma`
+    pub fn handle_write_dma(
+        &mut self,
+        addr: u64,
+        size: usize,
+        value: u64,
+        dma: &mut dyn DmaAccess,
+    ) -> Result<(), MmioError> {
+        self.dispatch_write(addr, size, value, Some(dma))
+    }
+
+    pub fn handle_read(&mut self, addr: u64, size: usize) -> Result<u64, MmioError> {
+        self.dispatch_read(addr, size, None)
+    }
+
+    /// Read the register at `addr`, pass the value through `f` and write the result back.
+    ///
+    /// The write is skipped if the read fails. Nothing else runs in between,
+    /// so the update is atomic as far as the guest can tell.
+    pub fn modify(
+        &mut self,
+        addr: u64,
+        size: usize,
+        f: impl FnOnce(u64) -> u64,
+    ) -> Result<(), MmioError> {
+        let value = self.handle_read(addr, size)?;
+        self.handle_write(addr, size, f(value))
+    }
+
+    /// Handle a read, letting the device access guest memory through `dma`
+    pub fn handle_read_dma(
+        &mut self,
+        addr: u64,
+        size: usize,
+        dma: &mut dyn DmaAccess,
+    ) -> Result<u64, MmioError> {
+        self.dispatch_read(addr, size, Some(dma))
+    }
+
+    fn dispatch_write(
+        &mut self,
+        addr: u64,
+        size: usize,
+        value: u64,
+        mut dma: Option<&mut dyn DmaAccess>,
+    ) -> Result<(), MmioError> {
+        log::debug!(
+            "Write {value} to {addr:#0x} of size {size} ({})",
+            self.device_name(addr)
+        );
+
+        // Devices only ever see the bits covered by the access size. Byte and
+        // halfword stores routinely carry a wider register, so this is no warning
+        let narrowed = narrow_to_size(value, size);
+        if narrowed != value {
+            log::debug!(
+                "Write of {value:#x} to {addr:#0x} has bits set above its {size}-byte access size"
+            );
+        }
+
+        let access = MmioAccess {
+            kind: MmioAccessKind::Write,
+            addr,
+            size,
+            value: narrowed,
+        };
+        // Replayed writes still reach the device, only their value is checked
+        self.trace.replay(access)?;
+
         let region = self.locate(addr, size)?;
-        let offset = addr - region.base_addr;
-        region.device.write(offset, size, value)?;
+        let max = region.device.max_access_size();
+        let mut done = 0;
+        while done < size {
+            let piece_addr = addr + done as u64;
+            let piece = piece_size(piece_addr, size - done, max);
+            let value = narrow_to_size(narrowed >> (done * 8), piece);
+            region.write(
+                piece_addr - region.base_addr,
+                piece,
+                value,
+                dma.as_mut().map(|dma| &mut **dma as &mut dyn DmaAccess),
+            )?;
+            done += piece;
+        }
+
+        self.trace.record(access);
         Ok(())
     }
 
-    pub fn handle_read(&mut self, addr: u64, size: usize) -> Result<u64, MmioError> {
-        log::debug!("Read from {addr:#0x} of size {size}");
+    fn dispatch_read(
+        &mut self,
+        addr: u64,
+        size: usize,
+        mut dma: Option<&mut dyn DmaAccess>,
+    ) -> Result<u64, MmioError> {
+        log::debug!(
+            "Read from {addr:#0x} of size {size} ({})",
+            self.device_name(addr)
+        );
+
+        let mut access = MmioAccess {
+            kind: MmioAccessKind::Read,
+            addr,
+            size,
+            value: 0,
+        };
+        if let Some(logged) = self.trace.replay(access)? {
+            return Ok(logged.value);
+        }
+
         let region = self.locate(addr, size)?;
-        let offset = addr - region.base_addr;
-        region.device.read(offset, size)
+        region.flush()?;
+        // Pieces are combined little-endian, the lowest address in the low bits
+        let max = region.device.max_access_size();
+        let mut done = 0;
+        while done < size {
+            let piece_addr = addr + done as u64;
+            let piece = piece_size(piece_addr, size - done, max);
+            let value = region.read(
+                piece_addr - region.base_addr,
+                piece,
+                dma.as_mut().map(|dma| &mut **dma as &mut dyn DmaAccess),
+            )?;
+            access.value |= narrow_to_size(value, piece) << (done * 8);
+            done += piece;
+        }
+
+        self.trace.record(access);
+        Ok(access.value)
+    }
+
+    /// Name of the device mapped at `addr`, for logging
+    fn device_name(&self, addr: u64) -> &str {
+        match self.regions.range(..=addr).next_back() {
+            Some((_, region)) if addr < region.base_addr + region.size => region.device.name(),
+            _ => "unmapped",
+        }
     }
 
     fn find_region(&mut self, addr: u64) -> Result<&mut MmioRegion, MmioError> {
@@ -114,3 +529,195 @@ impl MmioManager {
         None
     }
 }
+
+/// Size of the next piece of a split access: the widest naturally aligned
+/// power of two that fits in what remains and in the device's limit
+fn piece_size(addr: u64, remaining: usize, max: usize) -> usize {
+    1 << remaining.min(max).ilog2().min(addr.trailing_zeros())
+}
+
+/// Keep only the low `size` bytes of `value`
+fn narrow_to_size(value: u64, size: usize) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1u64 << (size * 8)) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::spi::Pl022Spi;
+
+    #[test]
+    fn test_device_errors_carry_absolute_address() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device(0x9060000, Box::new(Pl022Spi::new()))
+            .unwrap();
+
+        // Rejected by the device: wrapped with its name and the absolute address
+        match mmio.handle_read(0x9060800, 4) {
+            Err(MmioError::DeviceFault {
+                device,
+                addr,
+                source,
+                ..
+            }) => {
+                assert_eq!(device, "pl022");
+                assert_eq!(addr, 0x9060800);
+                assert!(matches!(*source, MmioError::UnmappedAccess(0x800)));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        // Log lines name the device too
+        assert_eq!(mmio.device_name(0x9060ffc), "pl022");
+        assert_eq!(mmio.device_name(0x9061000), "unmapped");
+
+        // Rejected by the manager: left as is
+        assert!(matches!(
+            mmio.handle_read(0x9060000, 3),
+            Err(MmioError::InvalidSize { size: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_register_with_explicit_size() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device_with_size(0xa000000, 0x4000, Box::new(Pl022Spi::new()))
+            .unwrap();
+
+        // The whole window belongs to the device, past its registers too
+        assert!(matches!(
+            mmio.handle_read(0xa003000, 4),
+            Err(MmioError::DeviceFault { .. })
+        ));
+        assert!(matches!(
+            mmio.handle_read(0xa004000, 4),
+            Err(MmioError::UnmappedAccess(0xa004000))
+        ));
+
+        // Misaligned base, odd size, or a window smaller than the registers
+        for (base, size) in [(0xb000100, 0x1000), (0xb000000, 0x1100), (0xb000000, 0x800)] {
+            assert!(matches!(
+                mmio.register_device_with_size(base, size, Box::new(Pl022Spi::new())),
+                Err(MmioError::MisalignedRegion { .. })
+            ));
+        }
+    }
+
+    /// Remembers the last value written, whether directly or in a batch
+    #[derive(Default)]
+    struct LastWrite {
+        last: u64,
+    }
+
+    impl MmioDevice for LastWrite {
+        fn read(&mut self, _offset: u64, _size: usize) -> Result<u64, MmioError> {
+            Ok(self.last)
+        }
+
+        fn write(&mut self, _offset: u64, _size: usize, value: u64) -> Result<(), MmioError> {
+            self.last = value;
+            Ok(())
+        }
+
+        fn write_batch(&mut self, writes: &[CoalescedWrite]) -> Result<(), MmioError> {
+            self.last = writes.last().map_or(self.last, |write| write.value);
+            Ok(())
+        }
+
+        fn reset(&mut self) {}
+
+        fn get_size(&self) -> u64 {
+            0x1000
+        }
+    }
+
+    #[test]
+    fn test_coalesced_writes_flush_before_reads() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device(0xa000000, Box::new(LastWrite::default()))
+            .unwrap();
+        mmio.set_coalesced(0xa000000, true).unwrap();
+
+        for value in 1..=3 {
+            mmio.handle_write(0xa000000, 4, value).unwrap();
+        }
+        // The read sees every write made before it
+        assert_eq!(mmio.handle_read(0xa000000, 4).unwrap(), 3);
+
+        // A full buffer is flushed without waiting for a read
+        for value in 0..COALESCED_MMIO_ENTRIES as u64 {
+            mmio.handle_write(0xa000004, 4, value).unwrap();
+        }
+        mmio.set_coalesced(0xa000000, false).unwrap();
+        assert_eq!(
+            mmio.handle_read(0xa000000, 4).unwrap(),
+            COALESCED_MMIO_ENTRIES as u64 - 1
+        );
+
+        assert!(mmio.set_coalesced(0xb000000, true).is_err());
+    }
+
+    #[test]
+    fn test_unregister_device() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device(0xa000000, Box::new(LastWrite::default()))
+            .unwrap();
+        mmio.register_device_with_size(0x9060000, 0x2000, Box::new(Pl022Spi::new()))
+            .unwrap();
+        assert_eq!(
+            mmio.iter_regions().collect::<Vec<_>>(),
+            [(0x9060000, 0x2000), (0xa000000, 0x1000)]
+        );
+
+        // Buffered writes reach the device before it is handed back
+        mmio.set_coalesced(0xa000000, true).unwrap();
+        mmio.handle_write(0xa000000, 4, 7).unwrap();
+        let mut device = mmio.unregister_device(0xa000000).unwrap();
+        assert_eq!(device.read(0, 4).unwrap(), 7);
+
+        assert!(mmio.unregister_device(0xa000000).is_none());
+        assert!(mmio.handle_read(0xa000000, 4).is_err());
+        assert_eq!(mmio.iter_regions().count(), 1);
+
+        // The window is free for a replacement
+        mmio.register_device(0xa000000, device).unwrap();
+    }
+
+    #[test]
+    fn test_wide_accesses_split_into_words() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device(0x9060000, Box::new(Pl022Spi::new()))
+            .unwrap();
+
+        // An 8-byte access over SSPCR0 and SSPCR1, the device only takes words
+        mmio.handle_write(0x9060000, 8, 0x2_0000_0007).unwrap();
+        assert_eq!(mmio.handle_read(0x9060000, 4).unwrap(), 0x7);
+        assert_eq!(mmio.handle_read(0x9060004, 4).unwrap(), 0x2);
+        assert_eq!(mmio.handle_read(0x9060000, 8).unwrap(), 0x2_0000_0007);
+
+        assert_eq!(piece_size(0x1004, 8, 8), 4);
+        assert_eq!(piece_size(0x1000, 8, 4), 4);
+        assert_eq!(piece_size(0x1001, 4, 8), 1);
+        assert_eq!(piece_size(0x1002, 3, 8), 2);
+    }
+
+    #[test]
+    fn test_modify() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device(0x9060000, Box::new(Pl022Spi::new()))
+            .unwrap();
+        mmio.handle_write(0x9060000, 4, 0x7).unwrap();
+
+        mmio.modify(0x9060000, 4, |value| value | 0x80).unwrap();
+        assert_eq!(mmio.handle_read(0x9060000, 4).unwrap(), 0x87);
+
+        assert!(matches!(
+            mmio.modify(0xa000000, 4, |value| value),
+            Err(MmioError::UnmappedAccess(0xa000000))
+        ));
+    }
+}
@@ -1,7 +1,18 @@
+pub mod debug_port;
+pub mod disk;
+pub mod dma;
+pub mod framebuffer;
 pub mod gpio;
+pub mod mailbox;
 pub mod mmio;
+pub mod pmu;
 pub mod register;
+pub mod spi;
+pub mod string_port;
 pub mod timer;
+pub mod trace;
 pub mod uart;
+pub mod virtio_console;
 
+pub use dma::{DmaAccess, GuestDma};
 pub use mmio::*;
@@ -0,0 +1,198 @@
+use crate::devices::timer::Clock;
+use crate::regs::EmulatedSystemRegister;
+
+// --- PMCR_EL0 bits ---
+const PMCR_E: u64 = 1 << 0; // Enable all counters
+const PMCR_C: u64 = 1 << 2; // Cycle counter reset (write-only)
+const PMCR_P: u64 = 1 << 1; // Event counter reset (write-only)
+const PMCR_LC: u64 = 1 << 6; // Long cycle counter enable
+const PMCR_WRITABLE: u64 = 0xff; // E, P, C, D, X, DP, LC, LP; N reads as zero
+
+// --- PMCNTENSET_EL0 / PMCNTENCLR_EL0 bits ---
+const PMCNTEN_C: u64 = 1 << 31; // Cycle counter enable
+
+// --- PMUSERENR_EL0 bits ---
+const PMUSERENR_EN: u64 = 1 << 0; // EL0 access to all PMU registers
+const PMUSERENR_CR: u64 = 1 << 2; // EL0 read access to the cycle counter
+const PMUSERENR_WRITABLE: u64 = 0b1111; // EN, SW, CR, ER
+
+/// Cycles per physical counter tick.
+///
+/// The system counter runs at 24 MHz on Apple silicon, so this makes the
+/// cycle counter look like a ~3 GHz core. It is only an approximation: the
+/// count follows the PMU's clock, not instructions retired, and with the host
+/// clock keeps running while the vCPU is not scheduled.
+const CYCLES_PER_TICK: u64 = 125;
+
+/// Emulated PMU cycle counter and control registers
+/// (PMCR_EL0, PMCNTENSET_EL0, PMCNTENCLR_EL0, PMCCNTR_EL0, PMUSERENR_EL0).
+///
+/// No event counters are implemented (PMCR_EL0.N is zero).
+#[derive(Debug, Default, Clone)]
+pub struct Pmu {
+    pmcr: u64,
+    cntenset: u64,
+    userenr: u64,
+    /// Cycle count when the counter was last written or stopped
+    cycles: u64,
+    /// Physical count when the counter last started, while it runs
+    started_at: Option<u64>,
+    clock: Clock,
+}
+
+impl Pmu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a PMU whose cycle counter follows `clock` instead of the host counter
+    pub fn with_clock(clock: Clock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    /// Whether PMCCNTR_EL0 is counting
+    fn running(&self) -> bool {
+        self.pmcr & PMCR_E != 0 && self.cntenset & PMCNTEN_C != 0
+    }
+
+    fn cycles_at(&self, now: u64) -> u64 {
+        let cycles = match self.started_at {
+            Some(start) => self
+                .cycles
+                .wrapping_add(now.wrapping_sub(start).wrapping_mul(CYCLES_PER_TICK)),
+            None => self.cycles,
+        };
+        // Without PMCR_EL0.LC the counter overflows at 32 bits
+        if self.pmcr & PMCR_LC != 0 {
+            cycles
+        } else {
+            cycles & 0xffff_ffff
+        }
+    }
+
+    /// Apply a change to the enable bits, freezing or resuming the count
+    fn update_enables(&mut self, now: u64, change: impl FnOnce(&mut Self)) {
+        self.cycles = self.cycles_at(now);
+        change(self);
+        self.started_at = self.running().then_some(now);
+    }
+
+    pub fn cycle_counter(&self) -> u64 {
+        self.cycles_at(self.clock.now())
+    }
+
+    pub fn set_cycle_counter(&mut self, value: u64) {
+        self.set_cycle_counter_at(self.clock.now(), value);
+    }
+
+    fn set_cycle_counter_at(&mut self, now: u64, value: u64) {
+        self.cycles = value;
+        self.started_at = self.running().then_some(now);
+    }
+
+    pub fn read_pmcr(&self) -> u64 {
+        self.pmcr
+    }
+
+    pub fn write_pmcr(&mut self, value: u64) {
+        self.write_pmcr_at(self.clock.now(), value);
+    }
+
+    fn write_pmcr_at(&mut self, now: u64, value: u64) {
+        // C and P are write-only reset requests
+        self.update_enables(now, |pmu| {
+            pmu.pmcr = value & PMCR_WRITABLE & !(PMCR_C | PMCR_P)
+        });
+        if value & PMCR_C != 0 {
+            self.set_cycle_counter_at(now, 0);
+        }
+    }
+
+    /// PMCNTENSET_EL0 and PMCNTENCLR_EL0 both read the enable mask
+    pub fn counter_enables(&self) -> u64 {
+        self.cntenset
+    }
+
+    /// Write PMCNTENSET_EL0: ones enable counters
+    pub fn set_counter_enables(&mut self, value: u64) {
+        let now = self.clock.now();
+        self.update_enables(now, |pmu| pmu.cntenset |= value & PMCNTEN_C);
+    }
+
+    /// Write PMCNTENCLR_EL0: ones disable counters
+    pub fn clear_counter_enables(&mut self, value: u64) {
+        let now = self.clock.now();
+        self.update_enables(now, |pmu| pmu.cntenset &= !(value & PMCNTEN_C));
+    }
+
+    pub fn userenr(&self) -> u64 {
+        self.userenr
+    }
+
+    pub fn set_userenr(&mut self, value: u64) {
+        self.userenr = value & PMUSERENR_WRITABLE;
+    }
+
+    /// Whether PMUSERENR_EL0 lets EL0 make this access.
+    ///
+    /// Denied accesses should be UNDEFINED at EL0; the caller decides what
+    /// to do about them. Registers outside the PMU are always permitted.
+    pub fn el0_permits(&self, register: EmulatedSystemRegister, is_write: bool) -> bool {
+        match register {
+            EmulatedSystemRegister::PmccntrEl0 if !is_write => {
+                self.userenr & (PMUSERENR_EN | PMUSERENR_CR) != 0
+            }
+            // PMUSERENR_EL0 itself is never writable from EL0
+            EmulatedSystemRegister::PmuserenrEl0 => !is_write,
+            EmulatedSystemRegister::PmcrEl0
+            | EmulatedSystemRegister::PmcntensetEl0
+            | EmulatedSystemRegister::PmcntenclrEl0
+            | EmulatedSystemRegister::PmccntrEl0 => self.userenr & PMUSERENR_EN != 0,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_counter_runs_only_when_enabled() {
+        let mut pmu = Pmu::new();
+        pmu.write_pmcr_at(100, PMCR_E | PMCR_LC);
+        assert_eq!(pmu.cycles_at(200), 0);
+
+        pmu.update_enables(200, |pmu| pmu.cntenset |= PMCNTEN_C);
+        assert_eq!(pmu.cycles_at(210), 10 * CYCLES_PER_TICK);
+
+        // Stopping freezes the count, restarting continues from it
+        pmu.update_enables(220, |pmu| pmu.cntenset &= !PMCNTEN_C);
+        assert_eq!(pmu.cycles_at(1000), 20 * CYCLES_PER_TICK);
+        pmu.update_enables(1000, |pmu| pmu.cntenset |= PMCNTEN_C);
+        assert_eq!(pmu.cycles_at(1001), 21 * CYCLES_PER_TICK);
+
+        // PMCR_EL0.C resets the count but is not stored
+        pmu.write_pmcr_at(1001, PMCR_E | PMCR_LC | PMCR_C);
+        assert_eq!(pmu.cycles_at(1001), 0);
+        assert_eq!(pmu.read_pmcr(), PMCR_E | PMCR_LC);
+    }
+
+    #[test]
+    fn test_userenr_gating() {
+        let mut pmu = Pmu::new();
+        assert!(!pmu.el0_permits(EmulatedSystemRegister::PmccntrEl0, false));
+
+        pmu.set_userenr(PMUSERENR_CR);
+        assert!(pmu.el0_permits(EmulatedSystemRegister::PmccntrEl0, false));
+        assert!(!pmu.el0_permits(EmulatedSystemRegister::PmccntrEl0, true));
+        assert!(!pmu.el0_permits(EmulatedSystemRegister::PmcrEl0, false));
+
+        pmu.set_userenr(PMUSERENR_EN);
+        assert!(pmu.el0_permits(EmulatedSystemRegister::PmcrEl0, true));
+        assert!(!pmu.el0_permits(EmulatedSystemRegister::PmuserenrEl0, true));
+    }
+}
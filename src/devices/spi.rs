@@ -0,0 +1,260 @@
+//! ARM PrimeCell PL022 Synchronous Serial Port (SSP) device emulation.
+//!
+//! This module provides a minimal PL022 controller operating in SPI master
+//! mode. Every byte the guest writes to the data register is clocked out to a
+//! host-side slave callback, whose response is queued in the receive FIFO.
+//! Transfers complete instantly, so the controller is never busy.
+
+use crate::devices::MmioDevice;
+use crate::err::MmioError;
+use std::collections::VecDeque;
+
+// --- ARM PL022 Register Offsets ---
+const SSPCR0: u64 = 0x000; // Control Register 0
+const SSPCR1: u64 = 0x004; // Control Register 1
+const SSPDR: u64 = 0x008; // Data Register
+const SSPSR: u64 = 0x00C; // Status Register
+const SSPCPSR: u64 = 0x010; // Clock Prescale Register
+const SSPIMSC: u64 = 0x014; // Interrupt Mask Set/Clear Register
+const SSPRIS: u64 = 0x018; // Raw Interrupt Status Register
+const SSPMIS: u64 = 0x01C; // Masked Interrupt Status Register
+const SSPICR: u64 = 0x020; // Interrupt Clear Register
+const SSPDMACR: u64 = 0x024; // DMA Control Register
+const SSP_PERIPH_ID_BASE: u64 = 0xFE0; // Start of Peripheral ID registers
+
+// --- Control Register 1 (SSPCR1) bits ---
+const CR1_LBM: u32 = 1 << 0; // Loop back mode
+const CR1_SSE: u32 = 1 << 1; // Synchronous serial port enable
+
+// --- Status Register (SSPSR) bits ---
+const SR_TFE: u32 = 1 << 0; // Transmit FIFO empty
+const SR_TNF: u32 = 1 << 1; // Transmit FIFO not full
+const SR_RNE: u32 = 1 << 2; // Receive FIFO not empty
+const SR_RFF: u32 = 1 << 3; // Receive FIFO full
+
+// The PL022 has 8-entry transmit and receive FIFOs
+const PL022_FIFO_DEPTH: usize = 8;
+
+// Standard ARM PL022 Peripheral & PrimeCell IDs
+const PL022_IDS: [u8; 8] = [0x22, 0x10, 0x04, 0x00, 0x0d, 0xf0, 0x05, 0xb1];
+
+/// Host-side SPI slave: receives each byte shifted out and returns the byte shifted in
+pub type SpiSlave = Box<dyn FnMut(u8) -> u8>;
+
+/// ARM PL022 SPI controller state
+pub struct Pl022Spi {
+    cr0: u32,                // Control Register 0 (frame format, data size, clock rate)
+    cr1: u32,                // Control Register 1 (enable, loopback, master/slave)
+    cpsr: u32,               // Clock prescale divisor
+    imsc: u32,               // Interrupt Mask
+    dmacr: u32,              // DMA Control
+    rx_fifo: VecDeque<u8>,   // Bytes from the slave, waiting for the guest
+    slave: Option<SpiSlave>, // Without a slave the bus reads back 0xFF
+}
+
+impl Pl022Spi {
+    /// Creates a new PL022 controller with no slave attached
+    pub fn new() -> Self {
+        Self {
+            cr0: 0,
+            cr1: 0,
+            cpsr: 0,
+            imsc: 0,
+            dmacr: 0,
+            rx_fifo: VecDeque::new(),
+            slave: None,
+        }
+    }
+
+    /// Creates a new PL022 controller with the given slave attached
+    pub fn with_slave(slave: SpiSlave) -> Self {
+        let mut spi = Self::new();
+        spi.attach_slave(slave);
+        spi
+    }
+
+    /// Attaches a slave device, replacing any previous one
+    pub fn attach_slave(&mut self, slave: SpiSlave) {
+        self.slave = Some(slave);
+    }
+
+    fn status(&self) -> u32 {
+        // Transfers complete immediately, so the TX FIFO is always empty.
+        let mut status = SR_TFE | SR_TNF;
+        if !self.rx_fifo.is_empty() {
+            status |= SR_RNE;
+        }
+        if self.rx_fifo.len() >= PL022_FIFO_DEPTH {
+            status |= SR_RFF;
+        }
+        status
+    }
+
+    // Shift one byte out to the slave and queue its response
+    fn transfer(&mut self, value: u8) {
+        if self.cr1 & CR1_SSE == 0 {
+            // Writes are ignored while the port is disabled.
+            return;
+        }
+
+        let response = if self.cr1 & CR1_LBM != 0 {
+            value
+        } else {
+            match self.slave.as_mut() {
+                Some(slave) => slave(value),
+                None => 0xFF, // Floating MISO line
+            }
+        };
+
+        // Like real hardware, drop incoming data on RX overrun.
+        if self.rx_fifo.len() < PL022_FIFO_DEPTH {
+            self.rx_fifo.push_back(response);
+        }
+    }
+
+    fn get_id_byte(&self, offset: u64) -> u64 {
+        let index = ((offset - SSP_PERIPH_ID_BASE) / 4) as usize;
+        PL022_IDS.get(index).copied().map(u64::from).unwrap_or(0)
+    }
+}
+
+impl Default for Pl022Spi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MmioDevice for Pl022Spi {
+    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let value = match offset {
+            SSPCR0 => u64::from(self.cr0),
+            SSPCR1 => u64::from(self.cr1),
+            SSPDR => u64::from(self.rx_fifo.pop_front().unwrap_or(0)),
+            SSPSR => u64::from(self.status()),
+            SSPCPSR => u64::from(self.cpsr),
+            SSPIMSC => u64::from(self.imsc),
+            SSPDMACR => u64::from(self.dmacr),
+
+            // Interrupts are not implemented, so nothing is ever pending.
+            SSPRIS | SSPMIS => 0,
+
+            SSP_PERIPH_ID_BASE..=0xFFC => self.get_id_byte(offset),
+
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        };
+
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        match offset {
+            SSPCR0 => self.cr0 = value as u32,
+            SSPCR1 => self.cr1 = value as u32,
+            SSPDR => self.transfer(value as u8),
+            SSPCPSR => self.cpsr = value as u32,
+            SSPIMSC => self.imsc = value as u32,
+            SSPDMACR => self.dmacr = value as u32,
+
+            // Acknowledge interrupt clears, nothing to clear.
+            SSPICR => { /* Acknowledge write, do nothing */ }
+
+            // Ignore writes to read-only registers.
+            SSPSR | SSPRIS | SSPMIS => { /* Read Only */ }
+
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        }
+
+        Ok(())
+    }
+
+    // The attached slave is kept
+    fn reset(&mut self) {
+        self.cr0 = 0;
+        self.cr1 = 0;
+        self.cpsr = 0;
+        self.imsc = 0;
+        self.dmacr = 0;
+        self.rx_fifo.clear();
+    }
+
+    fn get_size(&self) -> u64 {
+        0x1000 // PL022 occupies a 4KB memory region
+    }
+
+    fn max_access_size(&self) -> usize {
+        4
+    }
+
+    fn name(&self) -> &str {
+        "pl022"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_through_slave() {
+        let mut spi = Pl022Spi::with_slave(Box::new(|byte| !byte));
+
+        // Ignored until the port is enabled
+        spi.write(SSPDR, 4, 0x0f).unwrap();
+        assert_eq!(spi.read(SSPSR, 4).unwrap() & u64::from(SR_RNE), 0);
+
+        spi.write(SSPCR1, 4, u64::from(CR1_SSE)).unwrap();
+        spi.write(SSPDR, 4, 0x0f).unwrap();
+        spi.write(SSPDR, 4, 0xaa).unwrap();
+        assert_eq!(
+            spi.read(SSPSR, 4).unwrap(),
+            u64::from(SR_TFE | SR_TNF | SR_RNE)
+        );
+        assert_eq!(spi.read(SSPDR, 4).unwrap(), 0xf0);
+        assert_eq!(spi.read(SSPDR, 4).unwrap(), 0x55);
+        assert_eq!(spi.read(SSPSR, 4).unwrap(), u64::from(SR_TFE | SR_TNF));
+    }
+
+    #[test]
+    fn test_loopback_and_floating_bus() {
+        let mut spi = Pl022Spi::new();
+        spi.write(SSPCR1, 4, u64::from(CR1_SSE)).unwrap();
+        spi.write(SSPDR, 4, 0x12).unwrap();
+        assert_eq!(spi.read(SSPDR, 4).unwrap(), 0xff);
+
+        spi.write(SSPCR1, 4, u64::from(CR1_SSE | CR1_LBM)).unwrap();
+        spi.write(SSPDR, 4, 0x12).unwrap();
+        assert_eq!(spi.read(SSPDR, 4).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn test_rx_fifo_overrun_drops_bytes() {
+        let mut spi = Pl022Spi::new();
+        spi.write(SSPCR1, 4, u64::from(CR1_SSE | CR1_LBM)).unwrap();
+        for byte in 0..PL022_FIFO_DEPTH as u64 + 2 {
+            spi.write(SSPDR, 4, byte).unwrap();
+        }
+        assert_ne!(spi.read(SSPSR, 4).unwrap() & u64::from(SR_RFF), 0);
+
+        // The oldest bytes are kept, the last two were dropped
+        for byte in 0..PL022_FIFO_DEPTH as u64 {
+            assert_eq!(spi.read(SSPDR, 4).unwrap(), byte);
+        }
+        assert_eq!(spi.read(SSPSR, 4).unwrap() & u64::from(SR_RNE), 0);
+    }
+
+    #[test]
+    fn test_peripheral_id() {
+        let mut spi = Pl022Spi::new();
+        assert_eq!(spi.read(SSP_PERIPH_ID_BASE, 4).unwrap(), 0x22);
+        assert_eq!(spi.read(0xFFC, 4).unwrap(), 0xb1);
+        assert!(spi.read(SSPCR0, 2).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Guest-to-host message port for test assertions.
+//!
+//! Unlike the UART or the debug port, messages arrive whole: the guest
+//! writes the guest-physical address of a string, then its length, and the
+//! host copies it out of guest memory in one go. Each message is logged
+//! under the `guest` target and the latest ones are kept for `messages()`.
+
+use crate::devices::{DmaAccess, MmioDevice};
+use crate::err::MmioError;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// --- String Port Register Offsets ---
+const STR_ADDRESS: u64 = 0x00; // Guest-physical address of the next message
+const STR_LENGTH: u64 = 0x08; // Length in bytes, writing it sends the message (write-only)
+
+// Longer messages are rejected, a bad length shouldn't copy half of RAM
+const STR_MAX_LENGTH: u64 = 0x1000;
+
+// Older messages are dropped beyond this many, a chatty guest shouldn't grow the host
+const STR_MAX_MESSAGES: usize = 256;
+
+/// Collects whole messages from guest memory.
+///
+/// Cloning yields another handle to the same messages, so a test can keep
+/// one while the port sits in the `MmioManager`.
+#[derive(Clone, Debug, Default)]
+pub struct StringPort {
+    address: u64,
+    messages: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl StringPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The latest messages received, oldest first
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn receive(&mut self, length: u64, dma: &mut dyn DmaAccess) -> Result<(), MmioError> {
+        if length > STR_MAX_LENGTH {
+            return Err(MmioError::DeviceError(format!(
+                "message of {length:#x} bytes exceeds {STR_MAX_LENGTH:#x}"
+            )));
+        }
+
+        let mut bytes = vec![0; length as usize];
+        dma.read(self.address, &mut bytes)
+            .map_err(|e| MmioError::DeviceError(e.to_string()))?;
+        let message = String::from_utf8_lossy(&bytes).into_owned();
+        log::info!(target: "guest", "{message}");
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() == STR_MAX_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(message);
+        Ok(())
+    }
+}
+
+impl MmioDevice for StringPort {
+    fn read(&mut self, offset: u64, _size: usize) -> Result<u64, MmioError> {
+        match offset {
+            STR_ADDRESS => Ok(self.address),
+            // Write-only registers read as zero
+            STR_LENGTH => Ok(0),
+            _ => Err(MmioError::UnmappedAccess(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: u64, _size: usize, value: u64) -> Result<(), MmioError> {
+        match offset {
+            STR_ADDRESS => self.address = value,
+            STR_LENGTH => {
+                return Err(MmioError::DeviceError(
+                    "string port messages need DMA access".to_string(),
+                ));
+            }
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        }
+
+        Ok(())
+    }
+
+    fn write_dma(
+        &mut self,
+        offset: u64,
+        size: usize,
+        value: u64,
+        dma: &mut dyn DmaAccess,
+    ) -> Result<(), MmioError> {
+        match offset {
+            STR_LENGTH => self.receive(value, dma),
+            _ => self.write(offset, size, value),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.address = 0;
+    }
+
+    fn get_size(&self) -> u64 {
+        0x1000 // String port occupies a 4KB memory region
+    }
+
+    fn name(&self) -> &str {
+        "string-port"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::dma::FakeRam;
+
+    const RAM_BASE: u64 = 0x4000_0000;
+
+    #[test]
+    fn test_whole_messages() {
+        let mut ram = FakeRam::new(RAM_BASE, 0x100);
+        ram.write(RAM_BASE + 0x10, b"test passed: 3/3").unwrap();
+        let mut port = StringPort::new();
+        let handle = port.clone();
+
+        port.write(STR_ADDRESS, 8, RAM_BASE + 0x10).unwrap();
+        port.write_dma(STR_LENGTH, 8, 16, &mut ram).unwrap();
+        port.write_dma(STR_LENGTH, 8, 4, &mut ram).unwrap();
+        assert_eq!(handle.messages(), ["test passed: 3/3", "test"]);
+
+        // Messages must lie in RAM
+        port.write(STR_ADDRESS, 8, RAM_BASE + 0xf8).unwrap();
+        assert!(port.write_dma(STR_LENGTH, 8, 16, &mut ram).is_err());
+        assert_eq!(handle.messages().len(), 2);
+    }
+
+    #[test]
+    fn test_keeps_latest_messages() {
+        let mut ram = FakeRam::new(RAM_BASE, 0x100);
+        ram.write(RAM_BASE, b"0123456789").unwrap();
+        let mut port = StringPort::new();
+
+        for start in 0..STR_MAX_MESSAGES as u64 + 2 {
+            port.write(STR_ADDRESS, 8, RAM_BASE + start % 10).unwrap();
+            port.write_dma(STR_LENGTH, 8, 1, &mut ram).unwrap();
+        }
+
+        let messages = port.messages();
+        assert_eq!(messages.len(), STR_MAX_MESSAGES);
+        assert_eq!(messages[0], "2");
+    }
+}
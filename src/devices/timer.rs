@@ -1,4 +1,7 @@
+use crate::regs::EmulatedSystemRegister;
 use std::arch::asm;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub fn get_cntpct_el0() -> u64 {
     let physical_count: u64;
@@ -10,3 +13,481 @@ pub fn get_cntpct_el0() -> u64 {
 
     physical_count
 }
+
+/// Frequency of the host's system counter
+pub fn get_cntfrq_el0() -> u64 {
+    let frequency: u64;
+
+    // SAFETY: This assembly code reads the counter frequency from the CNTFRQ_EL0 register.
+    unsafe {
+        asm!("mrs {}, cntfrq_el0", out(reg) frequency);
+    }
+
+    frequency
+}
+
+/// Frequency of the emulated system counter, CNTFRQ_EL0 on Apple silicon
+pub const COUNTER_FREQUENCY_HZ: u64 = 24_000_000;
+
+/// Guest view of the system counter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerConfig {
+    /// CNTFRQ_EL0 as the guest sees it, trapped counter reads tick at this rate
+    pub freq_hz: u32,
+}
+
+/// Convert a count of `from_hz` ticks to `to_hz` ticks
+fn scale_count(count: u64, from_hz: u64, to_hz: u64) -> u64 {
+    if from_hz == 0 || from_hz == to_hz {
+        return count;
+    }
+    (u128::from(count) * u128::from(to_hz) / u128::from(from_hz)) as u64
+}
+
+/// A system counter that only moves when told to.
+///
+/// Cloning yields another handle to the same clock, so the run loop can
+/// advance the clock that the timer and PMU read.
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    nanoseconds: Arc<AtomicU64>,
+    frequency_hz: u64,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::with_frequency(COUNTER_FREQUENCY_HZ)
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a clock ticking at `frequency_hz` instead of `COUNTER_FREQUENCY_HZ`
+    pub fn with_frequency(frequency_hz: u64) -> Self {
+        Self {
+            nanoseconds: Arc::default(),
+            frequency_hz,
+        }
+    }
+
+    pub fn frequency(&self) -> u64 {
+        self.frequency_hz
+    }
+
+    /// Move the clock forward by `ns` nanoseconds
+    pub fn advance(&self, ns: u64) {
+        self.nanoseconds.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    /// Nanoseconds elapsed since the clock was created
+    pub fn elapsed_ns(&self) -> u64 {
+        self.nanoseconds.load(Ordering::Relaxed)
+    }
+
+    /// Counter ticks at the clock's frequency
+    pub fn ticks(&self) -> u64 {
+        scale_count(self.elapsed_ns(), 1_000_000_000, self.frequency_hz)
+    }
+}
+
+// Host counter time spent stopped in the debugger
+#[derive(Debug, Default)]
+struct PauseAccounting {
+    paused_at: Option<u64>, // Host count when the current pause began
+    stopped: u64,           // Ticks spent in completed pauses
+}
+
+impl PauseAccounting {
+    fn pause(&mut self, now: u64) {
+        self.paused_at.get_or_insert(now);
+    }
+
+    fn resume(&mut self, now: u64) -> u64 {
+        let Some(paused_at) = self.paused_at.take() else {
+            return 0;
+        };
+        let paused = now.wrapping_sub(paused_at);
+        self.stopped = self.stopped.wrapping_add(paused);
+        paused
+    }
+
+    // Guest count for a host count: frozen while paused, minus earlier pauses
+    fn guest_count(&self, now: u64) -> u64 {
+        self.paused_at.unwrap_or(now).wrapping_sub(self.stopped)
+    }
+}
+
+/// The host's physical counter, minus the time the VM spent paused.
+///
+/// Cloning yields another handle to the same clock, so a pause is seen by
+/// every device reading it.
+#[derive(Clone, Debug, Default)]
+pub struct HostClock {
+    pauses: Arc<Mutex<PauseAccounting>>,
+    frequency_hz: Option<u64>, // Rescale to this rate, None keeps the host's
+}
+
+impl HostClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a clock that rescales the host counter to tick at `frequency_hz`
+    pub fn with_frequency(frequency_hz: u64) -> Self {
+        Self {
+            frequency_hz: Some(frequency_hz),
+            ..Self::default()
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        let count = self.pauses.lock().unwrap().guest_count(get_cntpct_el0());
+        match self.frequency_hz {
+            Some(frequency_hz) => scale_count(count, get_cntfrq_el0(), frequency_hz),
+            None => count,
+        }
+    }
+
+    pub fn frequency(&self) -> u64 {
+        self.frequency_hz.unwrap_or_else(get_cntfrq_el0)
+    }
+}
+
+/// Where guest-visible time comes from.
+///
+/// Only counter reads that trap to the host follow a virtual clock; the
+/// vCPU's own view of the counter is not affected.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    /// The host's physical counter
+    Host(HostClock),
+    /// A deterministic clock advanced by the host
+    Virtual(VirtualClock),
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::Host(HostClock::new())
+    }
+}
+
+impl Clock {
+    /// Current system counter value
+    pub fn now(&self) -> u64 {
+        match self {
+            Clock::Host(clock) => clock.now(),
+            Clock::Virtual(clock) => clock.ticks(),
+        }
+    }
+
+    /// Ticks per second, what the guest reads from CNTFRQ_EL0
+    pub fn frequency(&self) -> u64 {
+        match self {
+            Clock::Host(clock) => clock.frequency(),
+            Clock::Virtual(clock) => clock.frequency(),
+        }
+    }
+
+    /// Stop guest time while the VM is halted, e.g. in the debugger.
+    ///
+    /// The host counter keeps running, so the clock remembers the count at
+    /// which it was paused and reports that count until `resume`. The ticks
+    /// spent paused are then added to a running offset that is subtracted
+    /// from every later reading, so timer deadlines don't all expire at once
+    /// when the guest continues. Pausing an already paused clock does nothing.
+    /// A virtual clock only moves when advanced, so it needs no accounting.
+    pub fn pause(&self) {
+        if let Clock::Host(clock) = self {
+            clock.pauses.lock().unwrap().pause(get_cntpct_el0());
+        }
+    }
+
+    /// Let guest time run again, returning the ticks the pause lasted.
+    ///
+    /// Counter reads that don't trap, like the vCPU's CNTVCT_EL0, must be
+    /// compensated by the caller, e.g. by adding the ticks to the vtimer offset.
+    pub fn resume(&self) -> u64 {
+        match self {
+            Clock::Host(clock) => clock.pauses.lock().unwrap().resume(get_cntpct_el0()),
+            Clock::Virtual(_) => 0,
+        }
+    }
+}
+
+// --- CNTKCTL_EL1 bits ---
+const CNTKCTL_EL0PCTEN: u64 = 1 << 0; // EL0 may read CNTPCT_EL0 and CNTPCTSS_EL0
+const CNTKCTL_EL0VCTEN: u64 = 1 << 1; // EL0 may read CNTVCT_EL0 and CNTVCTSS_EL0
+const CNTKCTL_EL0VTEN: u64 = 1 << 8; // EL0 may access CNTV_CTL_EL0, CNTV_CVAL_EL0 and CNTV_TVAL_EL0
+const CNTKCTL_EL0PTEN: u64 = 1 << 9; // EL0 may access CNTP_CTL_EL0, CNTP_CVAL_EL0 and CNTP_TVAL_EL0
+
+/// Whether CNTKCTL_EL1 lets EL0 access this timer register.
+///
+/// EL0PCTEN (bit 0) gates the physical count, EL0VCTEN (bit 1) the virtual
+/// count, either of them the frequency, EL0VTEN (bit 8) the virtual timer
+/// and EL0PTEN (bit 9) the physical timer. Denied accesses should be
+/// UNDEFINED at EL0; the caller decides what to do about them. Registers
+/// outside the generic timer are always permitted.
+pub fn cntkctl_el0_permits(cntkctl: u64, register: EmulatedSystemRegister) -> bool {
+    let enable = match register {
+        EmulatedSystemRegister::CntfrqEl0 => CNTKCTL_EL0PCTEN | CNTKCTL_EL0VCTEN,
+        EmulatedSystemRegister::CntpCtEl0 | EmulatedSystemRegister::CntpCtSsEl0 => CNTKCTL_EL0PCTEN,
+        EmulatedSystemRegister::CntvCtEl0 | EmulatedSystemRegister::CntvCtSsEl0 => CNTKCTL_EL0VCTEN,
+        EmulatedSystemRegister::CntvCtlEl0 | EmulatedSystemRegister::CntvCvalEl0 => CNTKCTL_EL0VTEN,
+        EmulatedSystemRegister::CntpCtlEl0
+        | EmulatedSystemRegister::CntpCvalEl0
+        | EmulatedSystemRegister::CntpTvalEl0 => CNTKCTL_EL0PTEN,
+        _ => return true,
+    };
+    cntkctl & enable != 0
+}
+
+// --- CNTV_CTL_EL0 and CNTP_CTL_EL0 bits ---
+const CTL_ENABLE: u64 = 1 << 0; // Timer enabled
+const CTL_IMASK: u64 = 1 << 1; // Interrupt masked
+const CTL_ISTATUS: u64 = 1 << 2; // Timer condition met (read-only)
+
+/// Whether a timer whose CNTx_CTL_EL0 reads `ctl` is asserting its interrupt
+pub fn ctl_asserts_interrupt(ctl: u64) -> bool {
+    ctl & (CTL_ENABLE | CTL_IMASK | CTL_ISTATUS) == CTL_ENABLE | CTL_ISTATUS
+}
+
+/// Whether a timer programmed with `ctl` and `cval` fires once its count is `count`
+pub fn fires_at(ctl: u64, cval: u64, count: u64) -> bool {
+    ctl & (CTL_ENABLE | CTL_IMASK) == CTL_ENABLE && count >= cval
+}
+
+/// Emulated EL1 generic timer counting the system counter.
+///
+/// Hypervisor.framework runs the virtual timer (CNTV_*) in hardware, so
+/// only the physical timer (CNTP_*) is emulated.
+#[derive(Debug, Default, Clone)]
+pub struct GenericTimer {
+    ctl: u64,  // CNTx_CTL_EL0 without ISTATUS
+    cval: u64, // CNTx_CVAL_EL0
+    clock: Clock,
+}
+
+/// EL1 physical timer (CNTPCT_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0, CNTP_TVAL_EL0)
+pub type PhysicalTimer = GenericTimer;
+
+impl GenericTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a timer counting from `clock` instead of the host counter
+    pub fn with_clock(clock: Clock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    /// The timer's count
+    pub fn counter(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Whether the timer condition (count >= CVAL) is met while enabled
+    pub fn condition_met(&self) -> bool {
+        self.condition_met_at(self.clock.now())
+    }
+
+    fn condition_met_at(&self, now: u64) -> bool {
+        self.ctl & CTL_ENABLE != 0 && now >= self.cval
+    }
+
+    /// Whether the timer asserts its interrupt when the physical count is `now`.
+    ///
+    /// IMASK only keeps the interrupt from firing, ISTATUS still reports
+    /// that the condition is met.
+    pub fn poll(&self, now: u64) -> bool {
+        self.condition_met_at(now) && self.ctl & CTL_IMASK == 0
+    }
+
+    /// Whether the timer is asserting its interrupt
+    pub fn interrupt_pending(&self) -> bool {
+        self.poll(self.clock.now())
+    }
+
+    pub fn read_ctl(&self) -> u64 {
+        if self.condition_met() {
+            self.ctl | CTL_ISTATUS
+        } else {
+            self.ctl
+        }
+    }
+
+    pub fn write_ctl(&mut self, value: u64) {
+        // ISTATUS is read-only
+        self.ctl = value & (CTL_ENABLE | CTL_IMASK);
+    }
+
+    pub fn cval(&self) -> u64 {
+        self.cval
+    }
+
+    pub fn set_cval(&mut self, value: u64) {
+        self.cval = value;
+    }
+
+    /// Ticks until the deadline, negative once it has passed.
+    ///
+    /// TVAL is a signed 32-bit view of CVAL minus the count, the upper half
+    /// of the register reads as zero.
+    pub fn tval(&self) -> u64 {
+        u64::from(self.cval.wrapping_sub(self.counter()) as u32)
+    }
+
+    /// Set the deadline `value` ticks from now, sign-extending the low 32 bits
+    pub fn set_tval(&mut self, value: u64) {
+        let ticks = i64::from(value as u32 as i32);
+        self.cval = self.counter().wrapping_add_signed(ticks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timer_fires_when_enabled_and_unmasked() {
+        let mut timer = PhysicalTimer::new();
+        timer.set_cval(0);
+        assert!(!timer.interrupt_pending());
+
+        timer.write_ctl(CTL_ENABLE | CTL_ISTATUS);
+        assert!(timer.interrupt_pending());
+        assert_eq!(timer.read_ctl(), CTL_ENABLE | CTL_ISTATUS);
+
+        timer.write_ctl(CTL_ENABLE | CTL_IMASK);
+        assert!(!timer.interrupt_pending());
+        assert!(timer.condition_met());
+    }
+
+    #[test]
+    fn test_virtual_clock_drives_timer() {
+        let clock = VirtualClock::new();
+        let mut timer = PhysicalTimer::with_clock(Clock::Virtual(clock.clone()));
+        timer.set_cval(COUNTER_FREQUENCY_HZ / 1000);
+        timer.write_ctl(CTL_ENABLE);
+        assert_eq!(timer.counter(), 0);
+        assert!(!timer.interrupt_pending());
+
+        // One millisecond later the deadline is reached
+        clock.advance(1_000_000);
+        assert_eq!(timer.counter(), COUNTER_FREQUENCY_HZ / 1000);
+        assert!(timer.interrupt_pending());
+    }
+
+    #[test]
+    fn test_physical_timer_tval() {
+        let clock = VirtualClock::new();
+        clock.advance(1_000_000);
+        let mut timer = PhysicalTimer::with_clock(Clock::Virtual(clock.clone()));
+        let now = timer.counter();
+
+        // TVAL sets the deadline relative to now and counts down to it
+        timer.set_tval(1000);
+        assert_eq!(timer.cval(), now + 1000);
+        assert_eq!(timer.tval(), 1000);
+        timer.write_ctl(CTL_ENABLE);
+        assert_eq!(timer.read_ctl(), CTL_ENABLE);
+
+        clock.advance(1_000_000);
+        assert!(timer.interrupt_pending());
+        assert_eq!(timer.read_ctl(), CTL_ENABLE | CTL_ISTATUS);
+        assert_eq!(
+            timer.tval() as u32 as i32,
+            1000 - (COUNTER_FREQUENCY_HZ / 1000) as i32
+        );
+
+        // Negative values put the deadline in the past
+        timer.set_tval(u64::from(-5i32 as u32));
+        assert_eq!(timer.cval(), timer.counter() - 5);
+    }
+
+    #[test]
+    fn test_physical_timer_poll() {
+        let mut timer = PhysicalTimer::with_clock(Clock::Virtual(VirtualClock::new()));
+        timer.set_cval(100);
+        timer.write_ctl(CTL_ENABLE);
+        assert!(!timer.poll(99));
+        assert!(timer.poll(100));
+
+        // Masking silences the interrupt but leaves ISTATUS set
+        timer.write_ctl(CTL_ENABLE | CTL_IMASK);
+        assert!(!timer.poll(100));
+        timer.set_cval(0);
+        assert_eq!(timer.read_ctl(), CTL_ENABLE | CTL_IMASK | CTL_ISTATUS);
+
+        timer.write_ctl(0);
+        assert!(!timer.poll(100));
+    }
+
+    #[test]
+    fn test_ctl_asserts_interrupt() {
+        assert!(ctl_asserts_interrupt(CTL_ENABLE | CTL_ISTATUS));
+        assert!(!ctl_asserts_interrupt(CTL_ENABLE));
+        assert!(!ctl_asserts_interrupt(CTL_ENABLE | CTL_IMASK | CTL_ISTATUS));
+        assert!(!ctl_asserts_interrupt(CTL_ISTATUS));
+    }
+
+    #[test]
+    fn test_fires_at_deadline() {
+        assert!(!fires_at(CTL_ENABLE, 100, 99));
+        assert!(fires_at(CTL_ENABLE, 100, 100));
+        assert!(!fires_at(CTL_ENABLE | CTL_IMASK, 100, 100));
+        assert!(!fires_at(0, 100, 100));
+    }
+
+    #[test]
+    fn test_configured_frequency() {
+        let clock = VirtualClock::with_frequency(1_000_000);
+        clock.advance(2_000_000_000);
+        assert_eq!(Clock::Virtual(clock).now(), 2_000_000);
+
+        // 24 MHz host ticks rescaled to 1 GHz
+        assert_eq!(scale_count(24, 24_000_000, 1_000_000_000), 1000);
+        assert_eq!(scale_count(u64::MAX, 24_000_000, 24_000_000), u64::MAX);
+        assert_eq!(scale_count(5, 0, 1_000_000), 5);
+    }
+
+    #[test]
+    fn test_pause_accounting() {
+        let mut pauses = PauseAccounting::default();
+        assert_eq!(pauses.guest_count(1000), 1000);
+
+        // Guest time stands still while paused
+        pauses.pause(1000);
+        pauses.pause(1500);
+        assert_eq!(pauses.guest_count(4000), 1000);
+
+        // and resumes where it stopped
+        assert_eq!(pauses.resume(5000), 4000);
+        assert_eq!(pauses.guest_count(5000), 1000);
+        assert_eq!(pauses.guest_count(5100), 1100);
+        assert_eq!(pauses.resume(6000), 0);
+    }
+
+    #[test]
+    fn test_cntkctl_gates_el0_access() {
+        let cntkctl = CNTKCTL_EL0VCTEN;
+        assert!(cntkctl_el0_permits(
+            cntkctl,
+            EmulatedSystemRegister::CntvCtEl0
+        ));
+        assert!(!cntkctl_el0_permits(
+            cntkctl,
+            EmulatedSystemRegister::CntpCtEl0
+        ));
+        assert!(!cntkctl_el0_permits(
+            cntkctl,
+            EmulatedSystemRegister::CntvCtlEl0
+        ));
+        assert!(cntkctl_el0_permits(0, EmulatedSystemRegister::TpidrEl0));
+    }
+}
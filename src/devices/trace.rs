@@ -0,0 +1,221 @@
+//! Record and replay of MMIO accesses.
+//!
+//! A recording captures every access that reaches `MmioManager` together with
+//! the value read or written. Replaying it answers reads from the log instead
+//! of the devices, so values that depend on host timing (counters, RNG,
+//! console input) are identical across runs. Writes still reach the devices
+//! and are compared against the log to detect where the guest diverged.
+//!
+//! The on-disk format is one access per line: `R|W <address> <size> <value>`,
+//! with the address and value in hex.
+
+use crate::err::MmioError;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmioAccessKind {
+    Read,
+    Write,
+}
+
+/// A single MMIO access and the value it transferred
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MmioAccess {
+    pub kind: MmioAccessKind,
+    pub addr: u64,
+    pub size: usize,
+    pub value: u64,
+}
+
+impl fmt::Display for MmioAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            MmioAccessKind::Read => 'R',
+            MmioAccessKind::Write => 'W',
+        };
+        write!(f, "{kind} {:#x} {} {:#x}", self.addr, self.size, self.value)
+    }
+}
+
+impl MmioAccess {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let kind = match fields.next()? {
+            "R" => MmioAccessKind::Read,
+            "W" => MmioAccessKind::Write,
+            _ => return None,
+        };
+        let hex = |field: &str| u64::from_str_radix(field.trim_start_matches("0x"), 16).ok();
+        let access = Self {
+            kind,
+            addr: hex(fields.next()?)?,
+            size: fields.next()?.parse().ok()?,
+            value: hex(fields.next()?)?,
+        };
+        fields.next().is_none().then_some(access)
+    }
+}
+
+/// An ordered sequence of MMIO accesses
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MmioLog {
+    entries: Vec<MmioAccess>,
+}
+
+impl MmioLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[MmioAccess] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, access: MmioAccess) {
+        self.entries.push(access);
+    }
+
+    /// Parse a log in the text format described in the module docs
+    pub fn parse(text: &str) -> Result<Self, MmioError> {
+        let entries = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                MmioAccess::parse(line).ok_or_else(|| {
+                    MmioError::DeviceError(format!(
+                        "malformed MMIO log line {}: {line}",
+                        number + 1
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MmioError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| MmioError::DeviceError(format!("reading MMIO log: {e}")))?;
+        Self::parse(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MmioError> {
+        fs::write(path, self.to_string())
+            .map_err(|e| MmioError::DeviceError(format!("writing MMIO log: {e}")))
+    }
+}
+
+impl fmt::Display for MmioLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for access in &self.entries {
+            writeln!(f, "{access}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What `MmioManager` does with each access
+#[derive(Debug, Default)]
+pub(crate) enum MmioTrace {
+    #[default]
+    Off,
+    Record(MmioLog),
+    Replay {
+        log: MmioLog,
+        next: usize,
+    },
+}
+
+impl MmioTrace {
+    /// Check `actual` against the next replayed access.
+    ///
+    /// Returns the logged access; for reads its value is what the guest sees.
+    /// Replay stops at the first divergence, later accesses go to the devices.
+    pub(crate) fn replay(&mut self, actual: MmioAccess) -> Result<Option<MmioAccess>, MmioError> {
+        let MmioTrace::Replay { log, next } = self else {
+            return Ok(None);
+        };
+
+        let index = *next;
+        let expected = log.entries.get(index).copied();
+        let matches = expected.is_some_and(|expected| {
+            expected.kind == actual.kind
+                && expected.addr == actual.addr
+                && expected.size == actual.size
+                && (actual.kind == MmioAccessKind::Read || expected.value == actual.value)
+        });
+
+        if !matches {
+            *self = MmioTrace::Off;
+            return Err(MmioError::replay_divergence(index, expected, actual));
+        }
+
+        *next += 1;
+        Ok(expected)
+    }
+
+    pub(crate) fn record(&mut self, access: MmioAccess) {
+        if let MmioTrace::Record(log) = self {
+            log.push(access);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MmioManager;
+    use crate::devices::uart::Pl011Device;
+
+    #[test]
+    fn test_log_text_round_trip() {
+        let mut log = MmioLog::new();
+        log.push(MmioAccess {
+            kind: MmioAccessKind::Read,
+            addr: 0x900_0018,
+            size: 4,
+            value: 0x90,
+        });
+        log.push(MmioAccess {
+            kind: MmioAccessKind::Write,
+            addr: 0x900_0000,
+            size: 4,
+            value: 0x41,
+        });
+
+        assert_eq!(MmioLog::parse(&log.to_string()).unwrap(), log);
+        assert!(MmioLog::parse("X 0x0 4 0x0").is_err());
+    }
+
+    #[test]
+    fn test_replay_returns_recorded_reads() {
+        let mut mmio = MmioManager::default();
+        mmio.register_device(0x900_0000, Box::new(Pl011Device::buffer()))
+            .unwrap();
+
+        mmio.start_recording();
+        let flags = mmio.handle_read(0x900_0018, 4).unwrap();
+        mmio.handle_write(0x900_0000, 4, u64::from(b'A')).unwrap();
+        let log = mmio.take_recording().unwrap();
+        assert_eq!(log.entries().len(), 2);
+
+        // Replay against a device that would answer differently
+        let mut recorded = log.clone();
+        recorded.entries[0].value = 0x1234;
+        let mut replay = MmioManager::default();
+        replay
+            .register_device(0x900_0000, Box::new(Pl011Device::buffer()))
+            .unwrap();
+        replay.start_replay(recorded);
+        assert_ne!(flags, 0x1234);
+        assert_eq!(replay.handle_read(0x900_0018, 4).unwrap(), 0x1234);
+
+        // Writing something else than what was recorded is reported
+        assert!(matches!(
+            replay.handle_write(0x900_0000, 4, u64::from(b'B')),
+            Err(MmioError::ReplayDivergence { index: 1, .. })
+        ));
+    }
+}
@@ -1,51 +1,183 @@
 use crate::devices::MmioDevice;
 use crate::err::MmioError;
 use std::collections::VecDeque;
-use std::io::{self, Write};
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 // --- ARM PL011 Register Offsets ---
 // Note: These are 4-byte (word) aligned offsets.
 const UARTDR: u64 = 0x000; // Data Register
+const UARTRSR: u64 = 0x004; // Receive Status / Error Clear Register
 const UARTFR: u64 = 0x018; // Flag Register
+const UARTIBRD: u64 = 0x024; // Integer Baud Rate Register
+const UARTFBRD: u64 = 0x028; // Fractional Baud Rate Register
 const UARTLCR_H: u64 = 0x02C; // Line Control Register
 const UARTCR: u64 = 0x030; // Control Register
 const UARTIMSC: u64 = 0x038; // Interrupt Mask Set/Clear Register
+const UARTRIS: u64 = 0x03C; // Raw Interrupt Status Register
+const UARTMIS: u64 = 0x040; // Masked Interrupt Status Register
 const UARTICR: u64 = 0x044; // Interrupt Clear Register
 const UART_PERIPH_ID_BASE: u64 = 0xFE0; // Start of Peripheral ID registers
 
+// --- Receive Status Register (UARTRSR) bits ---
+const RSR_OE: u32 = 1 << 3; // Overrun error
+
+// --- Data Register (UARTDR) receive error bits ---
+// Bits 8-11 carry FE, PE, BE and OE, in the same order as UARTRSR bits 0-3.
+// Only overruns can happen here, there is no line to garble a frame.
+const DR_ERROR_SHIFT: u32 = 8;
+const DR_OE: u16 = (RSR_OE as u16) << DR_ERROR_SHIFT; // Overrun error
+
 // --- Flag Register (UARTFR) bits ---
 const FLAG_TXFE: u32 = 1 << 7; // Transmit FIFO empty
 const FLAG_RXFF: u32 = 1 << 6; // Receive FIFO full
 const FLAG_TXFF: u32 = 1 << 5; // Transmit FIFO full
 const FLAG_RXFE: u32 = 1 << 4; // Receive FIFO empty
 
+// --- Interrupt bits (UARTIMSC, UARTRIS, UARTMIS, UARTICR) ---
+const INT_RX: u32 = 1 << 4; // Receive
+const INT_TX: u32 = 1 << 5; // Transmit
+const INT_OE: u32 = 1 << 10; // Overrun error
+
+// --- Baud rate divisor widths ---
+const IBRD_MASK: u32 = 0xffff;
+const FBRD_MASK: u32 = 0x3f;
+
 // --- Line Control Register (UARTLCR_H) bits ---
 const LCR_H_FEN: u32 = 1 << 4; // FIFO Enable
 
 // --- Control Register (UARTCR) bits ---
 const CR_RXE: u32 = 1 << 9; // Receive Enable
+const CR_LBE: u32 = 1 << 7; // Loopback Enable
 const CR_TXE: u32 = 1 << 8; // Transmit Enable
 const CR_UARTEN: u32 = 1 << 0; // UART Enable
 
 // Default FIFO size when enabled, from QEMU's implementation.
 const PL011_FIFO_DEPTH: usize = 16;
+const PL011_MAX_FIFO_DEPTH: usize = 64;
+
+// Host reads from an input stream are at most this many bytes
+const INPUT_CHUNK: usize = 256;
+
+// How long a pseudo-terminal read waits before its thread checks for shutdown
+const PTY_POLL_TIMEOUT_MS: libc::c_int = 100;
 
 // Standard ARM PL011 Peripheral ID
 const PL011_PERIPHERAL_ID: [u8; 8] = [0x11, 0x10, 0x14, 0x00, 0x0d, 0xf0, 0x05, 0xb1];
 
+/// Called with each completed line of output, including its terminator
+pub type LineCallback = Box<dyn FnMut(&[u8])>;
+
+/// Ring of the most recent completed output lines.
+///
+/// Cloning yields another handle to the same ring, so the debugger can keep
+/// one while the UART sits in the `MmioManager`.
+#[derive(Clone, Debug)]
+pub struct ConsoleHistory {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+/// Host end of a PL011 receive line.
+///
+/// Cloning yields another handle to the same queue. Bytes sent here move
+/// into the RX FIFO as the guest makes room, so input can be scripted
+/// ahead of time without overflowing the FIFO.
+#[derive(Clone, Debug, Default)]
+pub struct ConsoleInput {
+    pending: Arc<Mutex<VecDeque<u8>>>,
+    overrun: bool,
+}
+
+impl ConsoleInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like `new`, but bytes arrive one per device access whether or not
+    /// the RX FIFO has room, and are lost with an overrun error when it is
+    /// full, like on a real serial line.
+    pub fn with_overrun() -> Self {
+        Self {
+            overrun: true,
+            ..Self::default()
+        }
+    }
+
+    /// Queue `data` for the guest to receive
+    pub fn send(&self, data: &[u8]) {
+        self.pending.lock().unwrap().extend(data);
+    }
+
+    /// Bytes the guest has yet to receive
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    // Whether this is the last handle, so nobody can receive what is sent
+    fn is_last_handle(&self) -> bool {
+        Arc::strong_count(&self.pending) == 1
+    }
+}
+
+impl ConsoleHistory {
+    /// Create a ring keeping at most `capacity` lines
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    fn push(&self, line: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        let line = String::from_utf8_lossy(line);
+        lines.push_back(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    /// Up to `n` of the latest lines, oldest first
+    pub fn recent_lines(&self, n: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
 /// ARM PL011 UART device state machine (generic over output interface)
 pub struct Pl011Device<W: Write> {
     // Data FIFOs
-    rx_fifo: VecDeque<u8>,
+    rx_fifo: VecDeque<u16>, // Received bytes with their error bits, as read from UARTDR
     tx_fifo: VecDeque<u8>,
 
     // Register state (using simple u32 for word-sized registers)
-    flags: u32, // Flag Register (Read-Only)
-    lcr_h: u32, // Line Control Register
-    cr: u32,    // Control Register
-    imsc: u32,  // Interrupt Mask
+    flags: u32,       // Flag Register (Read-Only)
+    ibrd: u32,        // Integer part of the baud rate divisor
+    fbrd: u32,        // Fractional part of the baud rate divisor, in 64ths
+    lcr_h: u32,       // Line Control Register
+    cr: u32,          // Control Register
+    imsc: u32,        // Interrupt Mask
+    ris: u32,         // Raw (latched) interrupt status
+    rsr: u32,         // Receive errors
+    rx_overrun: bool, // Tag the next received byte with an overrun error
 
     // FIFO configuration
+    fifo_depth: usize, // Size of each FIFO while enabled
     fifo_enabled: bool,
     rx_fifo_size: usize,
     tx_fifo_size: usize,
@@ -53,6 +185,18 @@ pub struct Pl011Device<W: Write> {
     // Line buffering for output
     line_buffer: Vec<u8>,
 
+    // Host-side echo of received bytes, for interactive use
+    echo: bool,
+
+    // Observer of completed output lines
+    on_line: Option<LineCallback>,
+
+    // Recent output lines, off unless enabled
+    history: Option<ConsoleHistory>,
+
+    // Host input waiting for room in the RX FIFO
+    input: Option<ConsoleInput>,
+
     // Generic output interface
     output: W,
 }
@@ -66,28 +210,180 @@ impl<W: Write> Pl011Device<W> {
 
             // Initialize registers to match QEMU's reset state
             flags: FLAG_TXFE | FLAG_RXFE, // TX and RX FIFOs are empty
+            ibrd: 0,
+            fbrd: 0,
             lcr_h: 0,
             cr: CR_TXE | CR_RXE, // U-Boot expects TX/RX to be enabled
             imsc: 0,
+            ris: 0,
+            rsr: 0,
+            rx_overrun: false,
 
+            fifo_depth: PL011_FIFO_DEPTH,
             fifo_enabled: false,
             rx_fifo_size: 1,
             tx_fifo_size: 1,
             line_buffer: Vec::new(),
+            echo: false,
+            on_line: None,
+            history: None,
+            input: None,
             output,
         };
         uart.update_status();
         uart
     }
 
-    /// Input data to the UART (simulates receiving data)
+    /// Input data to the UART (simulates receiving data).
+    ///
+    /// When the RX FIFO is full the byte is lost and an overrun is flagged.
     pub fn input_data(&mut self, data: u8) {
-        if self.rx_fifo.len() < self.rx_fifo_size {
-            self.rx_fifo.push_back(data);
+        if self.push_rx(data) && self.echo {
+            // Echo is a host convenience, ignore I/O errors like transmission does
+            let _ = self.echo_input(data);
         }
         self.update_status();
     }
 
+    /// Put a received byte in the RX FIFO, false if it overran
+    fn push_rx(&mut self, data: u8) -> bool {
+        if self.rx_fifo.len() < self.rx_fifo_size {
+            // Like real hardware, the byte after the lost ones carries the error
+            let errors = if self.rx_overrun { DR_OE } else { 0 };
+            self.rx_overrun = false;
+            self.rx_fifo.push_back(u16::from(data) | errors);
+            self.ris |= INT_RX;
+            true
+        } else {
+            self.rx_overrun = true;
+            self.rsr |= RSR_OE;
+            self.ris |= INT_OE;
+            false
+        }
+    }
+
+    /// Enable or disable host-side echo of received bytes.
+    ///
+    /// Useful before the guest's own line discipline is up. Off by default so
+    /// it doesn't double-echo once the guest echoes input itself.
+    pub fn set_echo(&mut self, enabled: bool) {
+        self.echo = enabled;
+    }
+
+    /// Invoke `callback` with every line the guest prints, whatever the output sink.
+    ///
+    /// A trailing partial line is passed on when the line buffer is flushed,
+    /// including when the device is dropped. Replaces any previous callback.
+    pub fn on_line(&mut self, callback: LineCallback) {
+        self.on_line = Some(callback);
+    }
+
+    /// Keep the last `capacity` output lines, returning a handle to read them.
+    ///
+    /// Replaces any previous history.
+    pub fn enable_history(&mut self, capacity: usize) -> ConsoleHistory {
+        let history = ConsoleHistory::new(capacity);
+        self.history = Some(history.clone());
+        history
+    }
+
+    /// Feed the RX FIFO from `input`. Replaces any previous input.
+    pub fn attach_input(&mut self, input: ConsoleInput) {
+        self.input = Some(input);
+        self.receive_input();
+    }
+
+    /// Use FIFOs of `depth` entries once the guest enables them, instead of 16.
+    ///
+    /// `depth` must be a power of two from 1 to 64.
+    pub fn with_fifo_depth(mut self, depth: usize) -> Result<Self, MmioError> {
+        if !depth.is_power_of_two() || depth > PL011_MAX_FIFO_DEPTH {
+            return Err(MmioError::InvalidFifoDepth {
+                depth,
+                max: PL011_MAX_FIFO_DEPTH,
+            });
+        }
+        self.fifo_depth = depth;
+        if self.fifo_enabled {
+            self.rx_fifo_size = depth;
+            self.tx_fifo_size = depth;
+            self.update_status();
+        }
+        Ok(self)
+    }
+
+    /// Receive from `read` and transmit to `write`.
+    ///
+    /// A helper thread reads `read` into the RX queue until it reaches end
+    /// of file or fails, the guest then receives it like `ConsoleInput`.
+    pub fn from_streams<R: Read + Send + 'static>(read: R, write: W) -> Self {
+        let mut uart = Self::new(write);
+        uart.attach_input(spawn_reader(read, ConsoleInput::new()));
+        uart
+    }
+
+    /// Move queued host input into the RX FIFO while it has room
+    fn receive_input(&mut self) {
+        let Some(input) = self.input.clone() else {
+            return;
+        };
+        let mut pending = input.pending.lock().unwrap();
+        if input.overrun {
+            if let Some(byte) = pending.pop_front() {
+                self.input_data(byte);
+            }
+            return;
+        }
+        while self.rx_fifo.len() < self.rx_fifo_size {
+            match pending.pop_front() {
+                Some(byte) => self.input_data(byte),
+                None => break,
+            }
+        }
+    }
+
+    /// Whether an unmasked interrupt is raised, for the run loop to poll.
+    ///
+    /// The receive interrupt is raised for every received byte and the
+    /// transmit interrupt whenever a byte has been sent, as transmission is
+    /// instant. Both stay latched until UARTICR clears them; the receive
+    /// interrupt also clears once the RX FIFO is drained.
+    pub fn pending_interrupt(&self) -> bool {
+        self.ris & self.imsc != 0
+    }
+
+    /// Baud rate the programmed divisors give with a `uart_clk_hz` reference clock.
+    ///
+    /// The divisor is UARTIBRD + UARTFBRD / 64 and the baud rate is
+    /// `uart_clk_hz / (16 * divisor)`, 0 while no divisor is programmed.
+    pub fn baud_rate(&self, uart_clk_hz: u32) -> u32 {
+        // In 64ths: uart_clk_hz * 64 / (16 * (64 * IBRD + FBRD))
+        let divisor = u64::from((self.ibrd << 6) | self.fbrd);
+        if divisor == 0 {
+            return 0;
+        }
+        (u64::from(uart_clk_hz) * 4 / divisor) as u32
+    }
+
+    /// Up to `n` of the latest output lines, empty if history is disabled
+    pub fn recent_lines(&self, n: usize) -> Vec<String> {
+        self.history
+            .as_ref()
+            .map(|history| history.recent_lines(n))
+            .unwrap_or_default()
+    }
+
+    /// Echo a received byte to the output, in cooked terminal style
+    fn echo_input(&mut self, byte: u8) -> io::Result<()> {
+        match byte {
+            // Backspace / DEL: erase the previous character on screen
+            0x08 | 0x7f => self.output.write_all(b"\x08 \x08")?,
+            b'\r' | b'\n' => self.output.write_all(b"\r\n")?,
+            _ => self.output.write_all(&[byte])?,
+        }
+        self.output.flush()
+    }
+
     /// Get a mutable reference to the output interface
     pub fn output_mut(&mut self) -> &mut W {
         &mut self.output
@@ -101,22 +397,34 @@ impl<W: Write> Pl011Device<W> {
     /// Flush any remaining content in the line buffer to the output
     pub fn flush_line_buffer(&mut self) -> io::Result<()> {
         if !self.line_buffer.is_empty() {
-            self.output.write_all(&self.line_buffer)?;
-            self.output.flush()?;
-            self.line_buffer.clear();
+            self.emit_line()?;
         }
         Ok(())
     }
 
+    /// Write out the buffered line and hand it to the line callback
+    fn emit_line(&mut self) -> io::Result<()> {
+        if let Some(callback) = self.on_line.as_mut() {
+            callback(&self.line_buffer);
+        }
+        if let Some(history) = &self.history {
+            history.push(&self.line_buffer);
+        }
+        let result = self
+            .output
+            .write_all(&self.line_buffer)
+            .and_then(|_| self.output.flush());
+        self.line_buffer.clear();
+        result
+    }
+
     /// Handle transmitted character with line buffering
     fn handle_transmitted_char(&mut self, byte: u8) -> io::Result<()> {
         match byte {
             b'\n' => {
                 // Line complete - print the entire line including newline
                 self.line_buffer.push(byte);
-                self.output.write_all(&self.line_buffer)?;
-                self.output.flush()?;
-                self.line_buffer.clear();
+                self.emit_line()?;
             }
             b'\r' => {
                 // Carriage return - handle different line ending styles
@@ -156,6 +464,11 @@ impl<W: Write> Pl011Device<W> {
     /// Read from the data register (receives data)
     fn read_dr(&mut self) -> u64 {
         let data = self.rx_fifo.pop_front().unwrap_or(0);
+        self.rsr |= u32::from(data >> DR_ERROR_SHIFT);
+        if self.rx_fifo.is_empty() {
+            self.ris &= !INT_RX;
+        }
+        self.receive_input();
         self.update_status();
         u64::from(data)
     }
@@ -168,12 +481,17 @@ impl<W: Write> Pl011Device<W> {
             return;
         }
 
-        if self.tx_fifo.len() < self.tx_fifo_size {
+        if self.cr & CR_LBE != 0 {
+            // Loopback feeds the transmitter straight into the receiver
+            self.push_rx(value);
+            self.ris |= INT_TX;
+        } else if self.tx_fifo.len() < self.tx_fifo_size {
             self.tx_fifo.push_back(value);
             // For simplicity, we immediately "transmit" the character.
             // Ignore I/O errors during transmission (hardware behavior)
             let _ = self.handle_transmitted_char(value);
             self.tx_fifo.pop_front(); // Immediately sent
+            self.ris |= INT_TX; // The TX FIFO has room again
         }
         self.update_status();
     }
@@ -188,8 +506,8 @@ impl<W: Write> Pl011Device<W> {
 
             // Update FIFO sizes based on enable state
             if self.fifo_enabled {
-                self.rx_fifo_size = PL011_FIFO_DEPTH;
-                self.tx_fifo_size = PL011_FIFO_DEPTH;
+                self.rx_fifo_size = self.fifo_depth;
+                self.tx_fifo_size = self.fifo_depth;
             } else {
                 self.rx_fifo_size = 1;
                 self.tx_fifo_size = 1;
@@ -198,6 +516,7 @@ impl<W: Write> Pl011Device<W> {
             // Real hardware would reset FIFOs here, so we do too.
             self.rx_fifo.clear();
             self.tx_fifo.clear();
+            self.ris &= !INT_RX;
             self.update_status();
         }
     }
@@ -214,25 +533,20 @@ impl<W: Write> Drop for Pl011Device<W> {
     }
 }
 
-impl<W: Write> MmioDevice for Pl011Device<W> {
-    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
-        // PL011 has 4-byte registers
-        if size != 4 {
-            return Err(MmioError::InvalidSize { size });
-        }
-
+impl<W: Write> Pl011Device<W> {
+    /// Read a single 32-bit register
+    fn read_register(&mut self, offset: u64) -> Result<u64, MmioError> {
         let value = match offset {
             UARTDR => self.read_dr(),
+            UARTRSR => u64::from(self.rsr),
             UARTFR => u64::from(self.flags),
+            UARTIBRD => u64::from(self.ibrd),
+            UARTFBRD => u64::from(self.fbrd),
             UARTLCR_H => u64::from(self.lcr_h),
             UARTCR => u64::from(self.cr),
             UARTIMSC => u64::from(self.imsc),
-
-            // Stub other common registers to prevent unmapped access errors
-            0x028 => 0, // UARTFBRD (Fractional Baud Rate)
-            0x024 => 0, // UARTIBRD (Integer Baud Rate)
-            0x03C => 0, // UARTRIS (Raw Interrupt Status)
-            0x040 => 0, // UARTMIS (Masked Interrupt Status)
+            UARTRIS => u64::from(self.ris),
+            UARTMIS => u64::from(self.ris & self.imsc),
 
             // Peripheral ID registers
             UART_PERIPH_ID_BASE..=0xFFC => {
@@ -246,48 +560,150 @@ impl<W: Write> MmioDevice for Pl011Device<W> {
         Ok(value)
     }
 
-    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
-        if size != 4 {
-            return Err(MmioError::InvalidSize { size });
-        }
-
+    /// Write a single 32-bit register
+    fn write_register(&mut self, offset: u64, value: u64) -> Result<(), MmioError> {
         match offset {
             UARTDR => self.write_dr(value as u8),
+            UARTRSR => self.rsr = 0, // Any write clears the errors
+            UARTIBRD => self.ibrd = value as u32 & IBRD_MASK,
+            UARTFBRD => self.fbrd = value as u32 & FBRD_MASK,
             UARTLCR_H => self.write_lcr_h(value as u32),
             UARTCR => self.cr = value as u32,
             UARTIMSC => self.imsc = value as u32,
 
-            // On write, clear the specified interrupt flags from the (unimplemented) level
-            UARTICR => { /* Acknowledge write, do nothing */ }
+            UARTICR => self.ris &= !(value as u32),
 
-            // Ignore writes to read-only or stubbed registers
+            // Ignore writes to read-only registers
             UARTFR => { /* Read Only */ }
-            0x028 | 0x024 => { /* Stubbed */ }
 
             _ => return Err(MmioError::UnmappedAccess(offset)),
         }
 
         Ok(())
     }
+}
+
+impl<W: Write> MmioDevice for Pl011Device<W> {
+    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
+        // Input may have arrived since the last access, e.g. while polling UARTFR
+        self.receive_input();
+
+        // PL011 has 4-byte registers. A doubleword access (e.g. LDP over the
+        // register block) covers two consecutive registers, byte and halfword
+        // accesses return part of one, zero-extended.
+        match size {
+            1 | 2 => {
+                let shift = (offset & 3) * 8;
+                let value = self.read_register(offset & !3)? >> shift;
+                Ok(value & ((1 << (size * 8)) - 1))
+            }
+            4 => self.read_register(offset),
+            8 => {
+                let low = self.read_register(offset)?;
+                let high = self.read_register(offset + 4)?;
+                Ok(low | (high << 32))
+            }
+            _ => Err(MmioError::InvalidSize { size }),
+        }
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        match size {
+            1 | 2 => {
+                let register = offset & !3;
+                let shift = (offset & 3) * 8;
+                let mask = ((1u64 << (size * 8)) - 1) << shift;
+                if register == UARTDR {
+                    // Only the low byte carries data, reading DR back would pop the RX FIFO
+                    if shift == 0 {
+                        self.write_dr(value as u8);
+                    }
+                    return Ok(());
+                }
+                let current = self.read_register(register)?;
+                self.write_register(register, (current & !mask) | ((value << shift) & mask))
+            }
+            4 => self.write_register(offset, value & 0xffff_ffff),
+            8 => {
+                self.write_register(offset, value & 0xffff_ffff)?;
+                self.write_register(offset + 4, value >> 32)
+            }
+            _ => Err(MmioError::InvalidSize { size }),
+        }
+    }
+
+    fn implemented_offsets(&self) -> Vec<Range<u64>> {
+        vec![
+            UARTDR..UARTRSR + 4,
+            UARTFR..UARTFR + 4,
+            UARTIBRD..UARTCR + 4, // IBRD, FBRD, LCR_H, CR
+            UARTIMSC..UARTICR + 4,
+            UART_PERIPH_ID_BASE..0x1000,
+        ]
+    }
+
+    /// Asserted while an unmasked interrupt is raised
+    fn irq_pending(&mut self) -> bool {
+        self.receive_input();
+        self.pending_interrupt()
+    }
 
     fn reset(&mut self) {
         // We can't easily reset to default with a generic type, so we clear state manually
         self.rx_fifo.clear();
         self.tx_fifo.clear();
         self.flags = FLAG_TXFE | FLAG_RXFE;
+        self.ibrd = 0;
+        self.fbrd = 0;
         self.lcr_h = 0;
         self.cr = CR_TXE | CR_RXE;
         self.imsc = 0;
+        self.ris = 0;
+        self.rsr = 0;
+        self.rx_overrun = false;
         self.fifo_enabled = false;
         self.rx_fifo_size = 1;
         self.tx_fifo_size = 1;
         self.line_buffer.clear();
         self.update_status();
+        self.receive_input();
     }
 
     fn get_size(&self) -> u64 {
         0x1000 // PL011 occupies a 4KB memory region
     }
+
+    fn name(&self) -> &str {
+        "pl011"
+    }
+}
+
+/// Forward everything read from `read` to `input`.
+///
+/// The thread stops at end of file, on an error, or once every other handle
+/// to `input` is gone, which it notices when a read returns. Readers that
+/// time out with `ErrorKind::TimedOut` are retried, so they stop promptly.
+fn spawn_reader<R: Read + Send + 'static>(mut read: R, input: ConsoleInput) -> ConsoleInput {
+    let sender = input.clone();
+    thread::spawn(move || {
+        let mut buffer = [0u8; INPUT_CHUNK];
+        while !sender.is_last_handle() {
+            match read.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => sender.send(&buffer[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(e) => {
+                    log::warn!("UART input stopped: {e}");
+                    break;
+                }
+            }
+        }
+    });
+    input
 }
 
 // Type aliases for common use cases
@@ -301,6 +717,17 @@ impl Pl011Device<io::Stdout> {
     pub fn stdout() -> Self {
         Self::new(io::stdout())
     }
+
+    /// Create a PL011 device on the host terminal: stdout for output, stdin for input.
+    ///
+    /// Input waits for room in the RX FIFO like `ConsoleInput::new`, so a
+    /// polling guest doesn't lose pasted lines. The terminal stays in cooked
+    /// mode, so typed input reaches the guest a line at a time.
+    pub fn stdio() -> Self {
+        let mut uart = Self::stdout();
+        uart.attach_input(spawn_reader(io::stdin(), ConsoleInput::new()));
+        uart
+    }
 }
 
 impl Pl011Device<std::fs::File> {
@@ -309,6 +736,71 @@ impl Pl011Device<std::fs::File> {
         let file = std::fs::File::create(path)?;
         Ok(Self::new(file))
     }
+
+    /// Create a PL011 device backed by a new pseudo-terminal.
+    ///
+    /// Returns the device and the path of the terminal's slave side, for
+    /// `screen` or `minicom` to attach to. Output written while nothing is
+    /// attached is buffered by the terminal until it fills up. The reader
+    /// thread and the terminal go away shortly after the device is dropped.
+    pub fn pty() -> io::Result<(Self, PathBuf)> {
+        let master = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open("/dev/ptmx")?;
+        let fd = master.as_raw_fd();
+        // SAFETY: `fd` is an open pseudo-terminal master, `ptsname` returns a
+        // NUL-terminated static buffer that is copied before the next call
+        let path = unsafe {
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            PathBuf::from(CStr::from_ptr(name).to_string_lossy().into_owned())
+        };
+
+        // Holding the slave open keeps master reads from failing with EIO
+        // whenever no terminal program is attached
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        let reader = PtyReader {
+            master: master.try_clone()?,
+            _slave: slave,
+        };
+        Ok((Self::from_streams(reader, master), path))
+    }
+}
+
+/// Master side of a pseudo-terminal, keeping its slave side open.
+///
+/// Reads time out after `PTY_POLL_TIMEOUT_MS` without input, so the reader
+/// thread can see when the device is gone.
+struct PtyReader {
+    master: File,
+    _slave: File,
+}
+
+impl Read for PtyReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll_fd` is a single valid pollfd for an open descriptor
+        match unsafe { libc::poll(&mut poll_fd, 1, PTY_POLL_TIMEOUT_MS) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Err(io::ErrorKind::TimedOut.into()),
+            _ => self.master.read(buffer),
+        }
+    }
 }
 
 impl Pl011Device<std::io::Cursor<Vec<u8>>> {
@@ -327,3 +819,317 @@ impl Pl011Device<std::io::Cursor<Vec<u8>>> {
         String::from_utf8(self.output.get_ref().clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implemented_offsets() {
+        let mut uart = Pl011Device::new(io::sink());
+        let implemented = uart.implemented_offsets();
+
+        // Implemented registers that only decode one direction
+        let write_only = |offset| offset == UARTICR;
+        let read_only =
+            |offset| matches!(offset, UARTRIS | UARTMIS) || offset >= UART_PERIPH_ID_BASE;
+
+        for offset in (0..uart.get_size()).step_by(4) {
+            let mapped = implemented.iter().any(|range| range.contains(&offset));
+            let unmapped =
+                |result: Result<(), MmioError>| matches!(result, Err(MmioError::UnmappedAccess(_)));
+            assert_eq!(
+                unmapped(uart.read(offset, 4).map(|_| ())),
+                !mapped || write_only(offset),
+                "read {offset:#x}"
+            );
+            assert_eq!(
+                unmapped(uart.write(offset, 4, 0)),
+                !mapped || read_only(offset),
+                "write {offset:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_doubleword_read_at_base() {
+        let mut uart = Pl011Device::buffer();
+        uart.input_data(b'A');
+
+        // UARTDR in the low word, UARTRSR in the high word
+        let value = uart.read(UARTDR, 8).unwrap();
+        assert_eq!(value & 0xffff_ffff, u64::from(b'A'));
+        assert_eq!(value >> 32, 0);
+
+        // The read drained the RX FIFO
+        let flags = uart.read(UARTFR, 4).unwrap();
+        assert_ne!(flags & u64::from(FLAG_RXFE), 0);
+    }
+
+    #[test]
+    fn test_on_line_callback() {
+        use std::sync::mpsc;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut uart = Pl011Device::new(io::sink());
+        uart.write(UARTCR, 4, u64::from(CR_UARTEN | CR_TXE | CR_RXE))
+            .unwrap();
+        uart.on_line(Box::new(move |line| {
+            sender
+                .send(String::from_utf8_lossy(line).into_owned())
+                .unwrap();
+        }));
+
+        for byte in b"U-Boot 2025.01\r\nlogin:" {
+            uart.write(UARTDR, 4, u64::from(*byte)).unwrap();
+        }
+        assert_eq!(receiver.try_recv().unwrap(), "U-Boot 2025.01\r\n");
+        assert!(receiver.try_recv().is_err());
+
+        // The prompt has no newline, it is delivered when the device goes away
+        drop(uart);
+        assert_eq!(receiver.recv().unwrap(), "login:");
+    }
+
+    #[test]
+    fn test_history_keeps_latest_lines() {
+        let mut uart = Pl011Device::new(io::sink());
+        uart.write(UARTCR, 4, u64::from(CR_UARTEN | CR_TXE | CR_RXE))
+            .unwrap();
+        assert!(uart.recent_lines(2).is_empty());
+
+        let history = uart.enable_history(2);
+        for byte in b"one\r\ntwo\nthree\n" {
+            uart.write(UARTDR, 4, u64::from(*byte)).unwrap();
+        }
+        assert_eq!(history.recent_lines(5), ["two", "three"]);
+        assert_eq!(uart.recent_lines(1), ["three"]);
+    }
+
+    #[test]
+    fn test_rx_interrupt_follows_fifo() {
+        let input = ConsoleInput::new();
+        let mut uart = Pl011Device::new(io::sink());
+        uart.attach_input(input.clone());
+        input.send(b"ok");
+
+        // Raised but masked until the guest sets RXIM
+        assert!(!uart.irq_pending());
+        assert_eq!(uart.read(UARTRIS, 4).unwrap(), u64::from(INT_RX));
+        uart.write(UARTIMSC, 4, u64::from(INT_RX)).unwrap();
+        assert!(uart.irq_pending());
+        assert_eq!(uart.read(UARTMIS, 4).unwrap(), u64::from(INT_RX));
+
+        // Without FIFOs one byte is received at a time
+        assert_eq!(input.pending(), 1);
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'o'));
+        assert!(uart.irq_pending());
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'k'));
+        assert!(!uart.irq_pending());
+        assert_eq!(uart.read(UARTMIS, 4).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_from_streams_receives_input() {
+        let mut uart = Pl011Device::from_streams(io::Cursor::new(b"hi".to_vec()), io::sink());
+        uart.write(UARTLCR_H, 4, u64::from(LCR_H_FEN)).unwrap();
+
+        // The reader thread fills the queue in the background
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            if uart.read(UARTFR, 4).unwrap() & u64::from(FLAG_RXFE) == 0 {
+                received.push(uart.read(UARTDR, 4).unwrap() as u8);
+                if received.len() == 2 {
+                    break;
+                }
+            } else {
+                thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+        assert_eq!(received, b"hi");
+    }
+
+    /// Never has input, and records when the reader thread lets go of it
+    struct IdleReader(Arc<Mutex<bool>>);
+
+    impl Read for IdleReader {
+        fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(std::time::Duration::from_millis(1));
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    impl Drop for IdleReader {
+        fn drop(&mut self) {
+            *self.0.lock().unwrap() = true;
+        }
+    }
+
+    #[test]
+    fn test_reader_stops_with_device() {
+        let dropped = Arc::new(Mutex::new(false));
+        let uart = Pl011Device::from_streams(IdleReader(dropped.clone()), io::sink());
+        thread::sleep(std::time::Duration::from_millis(10));
+        assert!(!*dropped.lock().unwrap());
+
+        drop(uart);
+        for _ in 0..1000 {
+            if *dropped.lock().unwrap() {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("reader thread outlived the device");
+    }
+
+    #[test]
+    fn test_icr_clears_latched_interrupts() {
+        let mut uart = Pl011Device::new(io::sink());
+        uart.write(UARTCR, 4, u64::from(CR_UARTEN | CR_TXE | CR_RXE))
+            .unwrap();
+        uart.write(UARTIMSC, 4, u64::from(INT_TX)).unwrap();
+        assert!(!uart.pending_interrupt());
+
+        uart.write(UARTDR, 4, u64::from(b'x')).unwrap();
+        uart.input_data(b'y');
+        assert_eq!(uart.read(UARTRIS, 4).unwrap(), u64::from(INT_RX | INT_TX));
+        assert_eq!(uart.read(UARTMIS, 4).unwrap(), u64::from(INT_TX));
+        assert!(uart.pending_interrupt());
+
+        uart.write(UARTICR, 4, u64::from(INT_TX)).unwrap();
+        assert!(!uart.pending_interrupt());
+        assert_eq!(uart.read(UARTRIS, 4).unwrap(), u64::from(INT_RX));
+
+        // Clearing RX leaves the byte in the FIFO
+        uart.write(UARTICR, 4, u64::from(INT_RX)).unwrap();
+        assert_eq!(uart.read(UARTRIS, 4).unwrap(), 0);
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'y'));
+    }
+
+    #[test]
+    fn test_overrun_drops_input() {
+        let input = ConsoleInput::with_overrun();
+        let mut uart = Pl011Device::new(io::sink());
+        uart.attach_input(input.clone());
+        input.send(b"abc");
+
+        // One byte arrives per access, the FIFO only holds one
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), 0);
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), u64::from(RSR_OE));
+        assert_ne!(uart.read(UARTRIS, 4).unwrap() & u64::from(INT_OE), 0);
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'a'));
+
+        uart.write(UARTRSR, 4, 0).unwrap();
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), 0);
+        assert_eq!(input.pending(), 0);
+    }
+
+    #[test]
+    fn test_narrow_accesses() {
+        let mut uart = Pl011Device::buffer();
+        uart.write(UARTCR, 2, u64::from(CR_UARTEN | CR_TXE | CR_RXE))
+            .unwrap();
+        uart.write(UARTDR, 1, u64::from(b'!')).unwrap();
+        uart.write(UARTDR, 1, u64::from(b'\n')).unwrap();
+        assert_eq!(uart.get_output_string().unwrap(), "!\n");
+
+        let flags = uart.read(UARTFR, 4).unwrap();
+        assert_eq!(uart.read(UARTFR, 2).unwrap(), flags & 0xffff);
+        assert_eq!(
+            uart.read(UARTCR + 1, 1).unwrap(),
+            u64::from(CR_TXE | CR_RXE) >> 8
+        );
+
+        // Updating one byte keeps the rest of the register
+        uart.write(UARTCR, 1, 0).unwrap();
+        assert_eq!(uart.read(UARTCR, 4).unwrap(), u64::from(CR_TXE | CR_RXE));
+
+        assert!(matches!(
+            uart.read(UARTFR, 3),
+            Err(MmioError::InvalidSize { size: 3 })
+        ));
+        assert!(matches!(
+            uart.write(UARTDR, 0, 0),
+            Err(MmioError::InvalidSize { size: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_fifo_depth() {
+        let mut uart = Pl011Device::new(io::sink()).with_fifo_depth(32).unwrap();
+        uart.write(UARTLCR_H, 4, u64::from(LCR_H_FEN)).unwrap();
+        for byte in 0..33 {
+            uart.input_data(byte);
+        }
+        assert_eq!(uart.rx_fifo.len(), 32);
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), u64::from(RSR_OE));
+
+        // Reset disables the FIFOs but keeps the configured depth
+        uart.reset();
+        uart.write(UARTLCR_H, 4, u64::from(LCR_H_FEN)).unwrap();
+        assert_eq!(uart.rx_fifo_size, 32);
+
+        for depth in [0, 24, 128] {
+            assert!(matches!(
+                Pl011Device::new(io::sink()).with_fifo_depth(depth),
+                Err(MmioError::InvalidFifoDepth { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_baud_rate_divisors() {
+        // 24 MHz / (16 * 115200) = 13.0208, so IBRD = 13 and FBRD = 1
+        let mut uart = Pl011Device::new(io::sink());
+        assert_eq!(uart.baud_rate(24_000_000), 0);
+        uart.write(UARTIBRD, 4, 13).unwrap();
+        uart.write(UARTFBRD, 4, 1).unwrap();
+        assert_eq!(uart.read(UARTIBRD, 4).unwrap(), 13);
+        assert_eq!(uart.read(UARTFBRD, 4).unwrap(), 1);
+        assert_eq!(uart.baud_rate(24_000_000), 115_246);
+
+        // FBRD is 6 bits wide
+        uart.write(UARTFBRD, 4, 0xff).unwrap();
+        assert_eq!(uart.read(UARTFBRD, 4).unwrap(), 0x3f);
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut uart = Pl011Device::buffer();
+        uart.write(UARTCR, 4, u64::from(CR_UARTEN | CR_TXE | CR_RXE | CR_LBE))
+            .unwrap();
+        uart.write(UARTDR, 4, u64::from(b'x')).unwrap();
+        assert_eq!(uart.read(UARTFR, 4).unwrap() & u64::from(FLAG_RXFE), 0);
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'x'));
+
+        // Clearing LBE sends bytes to the output again
+        uart.write(UARTCR, 4, u64::from(CR_UARTEN | CR_TXE | CR_RXE))
+            .unwrap();
+        uart.write(UARTDR, 4, u64::from(b'\n')).unwrap();
+        assert_eq!(uart.get_output_string().unwrap(), "\n");
+        assert_ne!(uart.read(UARTFR, 4).unwrap() & u64::from(FLAG_RXFE), 0);
+    }
+
+    #[test]
+    fn test_overrun_tags_next_byte() {
+        let mut uart = Pl011Device::new(io::sink());
+        uart.input_data(b'a');
+        uart.input_data(b'b'); // Lost, the FIFO holds one byte
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), u64::from(RSR_OE));
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'a'));
+
+        uart.write(UARTRSR, 4, 0).unwrap();
+        uart.input_data(b'c');
+        assert_eq!(
+            uart.read(UARTDR, 4).unwrap(),
+            u64::from(DR_OE) | u64::from(b'c')
+        );
+        // Reading the byte latches its error in UARTRSR again
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), u64::from(RSR_OE));
+
+        uart.write(UARTRSR, 4, 0).unwrap();
+        uart.input_data(b'd');
+        assert_eq!(uart.read(UARTDR, 4).unwrap(), u64::from(b'd'));
+        assert_eq!(uart.read(UARTRSR, 4).unwrap(), 0);
+    }
+}
@@ -0,0 +1,556 @@
+//! virtio-mmio console device (transmit path only).
+//!
+//! Implements the version 2 virtio-mmio register layout for a console
+//! (device ID 3) with the two queues of port 0: receiveq (0) and transmitq (1).
+//! When the guest notifies the transmit queue, the descriptor chains it made
+//! available are read out of guest memory and written to the output sink.
+//! The receive queue can be configured but never gets buffers returned.
+//!
+//! Returned buffers set InterruptStatus, which holds the interrupt line up
+//! until the guest acknowledges it through InterruptACK.
+
+use crate::devices::{DmaAccess, MmioDevice};
+use crate::err::MmioError;
+use std::io::{self, Write};
+
+// --- virtio-mmio Register Offsets ---
+const VIRTIO_MMIO_MAGIC_VALUE: u64 = 0x000;
+const VIRTIO_MMIO_VERSION: u64 = 0x004;
+const VIRTIO_MMIO_DEVICE_ID: u64 = 0x008;
+const VIRTIO_MMIO_VENDOR_ID: u64 = 0x00C;
+const VIRTIO_MMIO_DEVICE_FEATURES: u64 = 0x010;
+const VIRTIO_MMIO_DEVICE_FEATURES_SEL: u64 = 0x014;
+const VIRTIO_MMIO_DRIVER_FEATURES: u64 = 0x020;
+const VIRTIO_MMIO_DRIVER_FEATURES_SEL: u64 = 0x024;
+const VIRTIO_MMIO_QUEUE_SEL: u64 = 0x030;
+const VIRTIO_MMIO_QUEUE_NUM_MAX: u64 = 0x034;
+const VIRTIO_MMIO_QUEUE_NUM: u64 = 0x038;
+const VIRTIO_MMIO_QUEUE_READY: u64 = 0x044;
+const VIRTIO_MMIO_QUEUE_NOTIFY: u64 = 0x050;
+const VIRTIO_MMIO_INTERRUPT_STATUS: u64 = 0x060;
+const VIRTIO_MMIO_INTERRUPT_ACK: u64 = 0x064;
+const VIRTIO_MMIO_STATUS: u64 = 0x070;
+const VIRTIO_MMIO_QUEUE_DESC_LOW: u64 = 0x080;
+const VIRTIO_MMIO_QUEUE_DESC_HIGH: u64 = 0x084;
+const VIRTIO_MMIO_QUEUE_DRIVER_LOW: u64 = 0x090;
+const VIRTIO_MMIO_QUEUE_DRIVER_HIGH: u64 = 0x094;
+const VIRTIO_MMIO_QUEUE_DEVICE_LOW: u64 = 0x0A0;
+const VIRTIO_MMIO_QUEUE_DEVICE_HIGH: u64 = 0x0A4;
+const VIRTIO_MMIO_CONFIG_GENERATION: u64 = 0x0FC;
+const VIRTIO_MMIO_CONFIG: u64 = 0x100;
+
+const VIRTIO_MAGIC: u64 = 0x7472_6976; // "virt"
+const VIRTIO_MMIO_VERSION_2: u64 = 2;
+const VIRTIO_ID_CONSOLE: u64 = 3;
+const VIRTIO_VENDOR_ID: u64 = 0x554d_4551; // "QEMU", what guests expect to see
+
+// VIRTIO_F_VERSION_1, bit 32: the only feature offered
+const DEVICE_FEATURES: u64 = 1 << 32;
+
+// --- Device status bits ---
+const STATUS_FEATURES_OK: u32 = 1 << 3;
+
+// --- Interrupt status bits ---
+const INT_USED_BUFFER: u32 = 1 << 0;
+
+// --- Descriptor flags ---
+const VIRTQ_DESC_F_NEXT: u16 = 1 << 0;
+const VIRTQ_DESC_F_WRITE: u16 = 1 << 1;
+const VIRTQ_DESC_F_INDIRECT: u16 = 1 << 2;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+const QUEUE_NUM_MAX: u32 = 64;
+
+// Longer chains are rejected, descriptor lengths come from the guest
+const MAX_CHAIN_BYTES: usize = 64 * 1024;
+
+/// One split virtqueue, as configured by the driver
+#[derive(Clone, Debug, Default)]
+struct Virtqueue {
+    num: u32,
+    ready: bool,
+    desc: u64,
+    driver: u64,
+    device: u64,
+    /// Next available ring entry the device will consume
+    last_avail: u16,
+}
+
+/// A descriptor from the descriptor table
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+fn dma_error(err: impl ToString) -> MmioError {
+    MmioError::DeviceError(err.to_string())
+}
+
+fn read_u16(dma: &dyn DmaAccess, gpa: u64) -> Result<u16, MmioError> {
+    let mut bytes = [0u8; 2];
+    dma.read(gpa, &mut bytes).map_err(dma_error)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+impl Virtqueue {
+    fn read_descriptor(&self, dma: &dyn DmaAccess, index: u16) -> Result<Descriptor, MmioError> {
+        let mut raw = [0u8; 16];
+        dma.read(self.desc + u64::from(index) * 16, &mut raw)
+            .map_err(dma_error)?;
+        Ok(Descriptor {
+            addr: u64::from_le_bytes(raw[0..8].try_into().unwrap()),
+            len: u32::from_le_bytes(raw[8..12].try_into().unwrap()),
+            flags: u16::from_le_bytes(raw[12..14].try_into().unwrap()),
+            next: u16::from_le_bytes(raw[14..16].try_into().unwrap()),
+        })
+    }
+
+    /// Gather the device-readable bytes of the chain starting at `head`
+    fn read_chain(&self, dma: &dyn DmaAccess, head: u16) -> Result<Vec<u8>, MmioError> {
+        let mut data = Vec::new();
+        let mut index = head;
+
+        // A chain can't be longer than the queue, anything else is a loop
+        for _ in 0..self.num {
+            if u32::from(index) >= self.num {
+                return Err(MmioError::DeviceError(format!(
+                    "descriptor index {index} out of range"
+                )));
+            }
+
+            let desc = self.read_descriptor(dma, index)?;
+            if desc.flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                return Err(MmioError::DeviceError(
+                    "indirect descriptors were not negotiated".to_string(),
+                ));
+            }
+            if desc.flags & VIRTQ_DESC_F_WRITE == 0 {
+                let start = data.len();
+                let end = start + desc.len as usize;
+                if end > MAX_CHAIN_BYTES {
+                    return Err(MmioError::DeviceError(format!(
+                        "descriptor chain at {head} exceeds {MAX_CHAIN_BYTES:#x} bytes"
+                    )));
+                }
+                data.resize(end, 0);
+                dma.read(desc.addr, &mut data[start..]).map_err(dma_error)?;
+            }
+
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(data);
+            }
+            index = desc.next;
+        }
+
+        Err(MmioError::DeviceError(format!(
+            "descriptor chain at {head} does not terminate"
+        )))
+    }
+
+    /// Append `head` to the used ring and publish the new index
+    fn push_used(&self, dma: &mut dyn DmaAccess, head: u16, len: u32) -> Result<(), MmioError> {
+        let used_idx = read_u16(dma, self.device + 2)?;
+        let slot = self.device + 4 + u64::from(used_idx % self.num as u16) * 8;
+
+        let mut elem = [0u8; 8];
+        elem[0..4].copy_from_slice(&u32::from(head).to_le_bytes());
+        elem[4..8].copy_from_slice(&len.to_le_bytes());
+        dma.write(slot, &elem).map_err(dma_error)?;
+        dma.write(self.device + 2, &used_idx.wrapping_add(1).to_le_bytes())
+            .map_err(dma_error)
+    }
+
+    /// Consume every available chain, handing its readable bytes to `consume`
+    fn drain(
+        &mut self,
+        dma: &mut dyn DmaAccess,
+        mut consume: impl FnMut(&[u8]),
+    ) -> Result<bool, MmioError> {
+        if !self.ready || self.num == 0 {
+            return Ok(false);
+        }
+
+        let avail_idx = read_u16(dma, self.driver + 2)?;
+        let mut used_any = false;
+        while self.last_avail != avail_idx {
+            let slot = self.driver + 4 + u64::from(self.last_avail % self.num as u16) * 2;
+            let head = read_u16(dma, slot)?;
+
+            let data = self.read_chain(dma, head)?;
+            consume(&data);
+
+            // Nothing was written to the buffers
+            self.push_used(dma, head, 0)?;
+            self.last_avail = self.last_avail.wrapping_add(1);
+            used_any = true;
+        }
+
+        Ok(used_any)
+    }
+}
+
+/// virtio-mmio console device writing to a generic output
+pub struct VirtioConsole<W: Write> {
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: [Virtqueue; 2],
+    interrupt_status: u32,
+    status: u32,
+    output: W,
+}
+
+impl<W: Write> VirtioConsole<W> {
+    /// Creates a console that writes guest output to `output`
+    pub fn new(output: W) -> Self {
+        Self {
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues: Default::default(),
+            interrupt_status: 0,
+            status: 0,
+            output,
+        }
+    }
+
+    /// Get a reference to the output interface
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    fn selected_queue(&mut self) -> Option<&mut Virtqueue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    /// Replace the low or high half of a 64-bit address
+    fn set_half(target: &mut u64, value: u64, high: bool) {
+        if high {
+            *target = (*target & 0xffff_ffff) | (value << 32);
+        } else {
+            *target = (*target & !0xffff_ffff) | (value & 0xffff_ffff);
+        }
+    }
+
+    fn write_queue_register(&mut self, offset: u64, value: u64) {
+        let Some(queue) = self.selected_queue() else {
+            log::warn!("virtio-console: access to nonexistent queue");
+            return;
+        };
+
+        match offset {
+            VIRTIO_MMIO_QUEUE_NUM => queue.num = (value as u32).min(QUEUE_NUM_MAX),
+            VIRTIO_MMIO_QUEUE_READY => queue.ready = value & 1 != 0,
+            VIRTIO_MMIO_QUEUE_DESC_LOW | VIRTIO_MMIO_QUEUE_DESC_HIGH => Self::set_half(
+                &mut queue.desc,
+                value,
+                offset == VIRTIO_MMIO_QUEUE_DESC_HIGH,
+            ),
+            VIRTIO_MMIO_QUEUE_DRIVER_LOW | VIRTIO_MMIO_QUEUE_DRIVER_HIGH => Self::set_half(
+                &mut queue.driver,
+                value,
+                offset == VIRTIO_MMIO_QUEUE_DRIVER_HIGH,
+            ),
+            VIRTIO_MMIO_QUEUE_DEVICE_LOW | VIRTIO_MMIO_QUEUE_DEVICE_HIGH => Self::set_half(
+                &mut queue.device,
+                value,
+                offset == VIRTIO_MMIO_QUEUE_DEVICE_HIGH,
+            ),
+            _ => unreachable!("not a queue register"),
+        }
+    }
+
+    /// Process the transmit queue after the guest kicked it
+    fn notify(&mut self, queue: u64, dma: &mut dyn DmaAccess) -> Result<(), MmioError> {
+        match queue as usize {
+            TRANSMITQ => {
+                let output = &mut self.output;
+                let used = self.queues[TRANSMITQ].drain(dma, |data| {
+                    // Ignore I/O errors during transmission, like the PL011
+                    let _ = output.write_all(data).and_then(|_| output.flush());
+                })?;
+                if used {
+                    self.interrupt_status |= INT_USED_BUFFER;
+                }
+            }
+            // Input is not implemented, receive buffers stay with the device
+            RECEIVEQ => {}
+            _ => log::warn!("virtio-console: notify for nonexistent queue {queue}"),
+        }
+        Ok(())
+    }
+
+    fn reset_state(&mut self) {
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.queues = Default::default();
+        self.interrupt_status = 0;
+        self.status = 0;
+    }
+}
+
+impl<W: Write> MmioDevice for VirtioConsole<W> {
+    fn read(&mut self, offset: u64, size: usize) -> Result<u64, MmioError> {
+        if offset >= VIRTIO_MMIO_CONFIG {
+            // struct virtio_console_config is all zero: no size, no ports
+            return Ok(0);
+        }
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        let value = match offset {
+            VIRTIO_MMIO_MAGIC_VALUE => VIRTIO_MAGIC,
+            VIRTIO_MMIO_VERSION => VIRTIO_MMIO_VERSION_2,
+            VIRTIO_MMIO_DEVICE_ID => VIRTIO_ID_CONSOLE,
+            VIRTIO_MMIO_VENDOR_ID => VIRTIO_VENDOR_ID,
+            VIRTIO_MMIO_DEVICE_FEATURES => match self.device_features_sel {
+                0 => DEVICE_FEATURES & 0xffff_ffff,
+                1 => DEVICE_FEATURES >> 32,
+                _ => 0,
+            },
+            VIRTIO_MMIO_QUEUE_NUM_MAX => match self.selected_queue() {
+                Some(_) => u64::from(QUEUE_NUM_MAX),
+                None => 0,
+            },
+            VIRTIO_MMIO_QUEUE_READY => self
+                .selected_queue()
+                .map_or(0, |queue| u64::from(queue.ready)),
+            VIRTIO_MMIO_INTERRUPT_STATUS => u64::from(self.interrupt_status),
+            VIRTIO_MMIO_STATUS => u64::from(self.status),
+            VIRTIO_MMIO_CONFIG_GENERATION => 0,
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        };
+
+        Ok(value)
+    }
+
+    fn write(&mut self, offset: u64, size: usize, value: u64) -> Result<(), MmioError> {
+        if size != 4 {
+            return Err(MmioError::InvalidSize { size });
+        }
+
+        match offset {
+            VIRTIO_MMIO_DEVICE_FEATURES_SEL => self.device_features_sel = value as u32,
+            VIRTIO_MMIO_DRIVER_FEATURES => match self.driver_features_sel {
+                0 => Self::set_half(&mut self.driver_features, value, false),
+                1 => Self::set_half(&mut self.driver_features, value, true),
+                _ => {}
+            },
+            VIRTIO_MMIO_DRIVER_FEATURES_SEL => self.driver_features_sel = value as u32,
+            VIRTIO_MMIO_QUEUE_SEL => self.queue_sel = value as u32,
+            VIRTIO_MMIO_QUEUE_NUM
+            | VIRTIO_MMIO_QUEUE_READY
+            | VIRTIO_MMIO_QUEUE_DESC_LOW
+            | VIRTIO_MMIO_QUEUE_DESC_HIGH
+            | VIRTIO_MMIO_QUEUE_DRIVER_LOW
+            | VIRTIO_MMIO_QUEUE_DRIVER_HIGH
+            | VIRTIO_MMIO_QUEUE_DEVICE_LOW
+            | VIRTIO_MMIO_QUEUE_DEVICE_HIGH => self.write_queue_register(offset, value),
+            VIRTIO_MMIO_QUEUE_NOTIFY => {
+                return Err(MmioError::DeviceError(
+                    "queue notify needs guest memory access".to_string(),
+                ));
+            }
+            VIRTIO_MMIO_INTERRUPT_ACK => self.interrupt_status &= !(value as u32),
+            VIRTIO_MMIO_STATUS => {
+                if value == 0 {
+                    self.reset_state();
+                } else {
+                    let mut status = value as u32;
+                    // Refuse feature sets we didn't offer
+                    if self.driver_features & !DEVICE_FEATURES != 0 {
+                        status &= !STATUS_FEATURES_OK;
+                    }
+                    self.status = status;
+                }
+            }
+
+            // Ignore writes to read-only registers
+            VIRTIO_MMIO_MAGIC_VALUE
+            | VIRTIO_MMIO_VERSION
+            | VIRTIO_MMIO_DEVICE_ID
+            | VIRTIO_MMIO_VENDOR_ID
+            | VIRTIO_MMIO_DEVICE_FEATURES
+            | VIRTIO_MMIO_QUEUE_NUM_MAX
+            | VIRTIO_MMIO_INTERRUPT_STATUS
+            | VIRTIO_MMIO_CONFIG_GENERATION => { /* Read Only */ }
+
+            // The console config space is read-only without VIRTIO_CONSOLE_F_EMERG_WRITE
+            VIRTIO_MMIO_CONFIG.. => { /* Read Only */ }
+
+            _ => return Err(MmioError::UnmappedAccess(offset)),
+        }
+
+        Ok(())
+    }
+
+    fn write_dma(
+        &mut self,
+        offset: u64,
+        size: usize,
+        value: u64,
+        dma: &mut dyn DmaAccess,
+    ) -> Result<(), MmioError> {
+        if offset == VIRTIO_MMIO_QUEUE_NOTIFY && size == 4 {
+            return self.notify(value, dma);
+        }
+        self.write(offset, size, value)
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn get_size(&self) -> u64 {
+        0x200 // virtio-mmio transports occupy 512 bytes
+    }
+
+    fn max_access_size(&self) -> usize {
+        4
+    }
+
+    /// Asserted while InterruptStatus has unacknowledged bits
+    fn irq_pending(&mut self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    fn name(&self) -> &str {
+        "virtio-console"
+    }
+}
+
+pub type VirtioConsoleStdout = VirtioConsole<io::Stdout>;
+
+impl VirtioConsole<io::Stdout> {
+    /// Create a virtio console that outputs to stdout
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::dma::FakeRam;
+
+    const RAM_BASE: u64 = 0x4000_0000;
+    const DESC: u64 = RAM_BASE;
+    const AVAIL: u64 = RAM_BASE + 0x400;
+    const USED: u64 = RAM_BASE + 0x600;
+    const BUFFERS: u64 = RAM_BASE + 0x800;
+
+    fn write_descriptor(ram: &mut FakeRam, index: u64, addr: u64, len: u32, flags: u16, next: u16) {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&addr.to_le_bytes());
+        raw.extend_from_slice(&len.to_le_bytes());
+        raw.extend_from_slice(&flags.to_le_bytes());
+        raw.extend_from_slice(&next.to_le_bytes());
+        ram.write(DESC + index * 16, &raw).unwrap();
+    }
+
+    fn configure_transmitq(console: &mut VirtioConsole<Vec<u8>>) {
+        console.write(VIRTIO_MMIO_STATUS, 4, 0x1 | 0x2).unwrap();
+        console
+            .write(VIRTIO_MMIO_DRIVER_FEATURES_SEL, 4, 1)
+            .unwrap();
+        console.write(VIRTIO_MMIO_DRIVER_FEATURES, 4, 1).unwrap();
+        console.write(VIRTIO_MMIO_STATUS, 4, 0xb).unwrap();
+        assert_ne!(
+            console.read(VIRTIO_MMIO_STATUS, 4).unwrap() & u64::from(STATUS_FEATURES_OK),
+            0
+        );
+
+        console
+            .write(VIRTIO_MMIO_QUEUE_SEL, 4, TRANSMITQ as u64)
+            .unwrap();
+        console.write(VIRTIO_MMIO_QUEUE_NUM, 4, 8).unwrap();
+        console.write(VIRTIO_MMIO_QUEUE_DESC_LOW, 4, DESC).unwrap();
+        console
+            .write(VIRTIO_MMIO_QUEUE_DRIVER_LOW, 4, AVAIL)
+            .unwrap();
+        console
+            .write(VIRTIO_MMIO_QUEUE_DEVICE_LOW, 4, USED)
+            .unwrap();
+        console.write(VIRTIO_MMIO_QUEUE_READY, 4, 1).unwrap();
+    }
+
+    #[test]
+    fn test_identification() {
+        let mut console = VirtioConsole::new(Vec::new());
+        assert_eq!(
+            console.read(VIRTIO_MMIO_MAGIC_VALUE, 4).unwrap(),
+            0x7472_6976
+        );
+        assert_eq!(console.read(VIRTIO_MMIO_VERSION, 4).unwrap(), 2);
+        assert_eq!(console.read(VIRTIO_MMIO_DEVICE_ID, 4).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_transmit_reaches_sink() {
+        let mut ram = FakeRam::new(RAM_BASE, 0x1000);
+        let mut console = VirtioConsole::new(Vec::new());
+        configure_transmitq(&mut console);
+
+        // "hello, " and "world\n" as a two-descriptor chain
+        ram.write(BUFFERS, b"hello, ").unwrap();
+        ram.write(BUFFERS + 0x100, b"world\n").unwrap();
+        write_descriptor(&mut ram, 0, BUFFERS, 7, VIRTQ_DESC_F_NEXT, 1);
+        write_descriptor(&mut ram, 1, BUFFERS + 0x100, 6, 0, 0);
+
+        // avail.ring[0] = 0, avail.idx = 1
+        ram.write(AVAIL + 4, &0u16.to_le_bytes()).unwrap();
+        ram.write(AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+
+        console
+            .write_dma(VIRTIO_MMIO_QUEUE_NOTIFY, 4, TRANSMITQ as u64, &mut ram)
+            .unwrap();
+
+        assert_eq!(console.output(), b"hello, world\n");
+
+        // The chain was returned through the used ring
+        let mut used_idx = [0u8; 2];
+        ram.read(USED + 2, &mut used_idx).unwrap();
+        assert_eq!(u16::from_le_bytes(used_idx), 1);
+        assert_eq!(
+            console.read(VIRTIO_MMIO_INTERRUPT_STATUS, 4).unwrap(),
+            u64::from(INT_USED_BUFFER)
+        );
+        assert!(console.irq_pending());
+        console
+            .write(VIRTIO_MMIO_INTERRUPT_ACK, 4, u64::from(INT_USED_BUFFER))
+            .unwrap();
+        assert!(!console.irq_pending());
+
+        // A second kick without new buffers writes nothing more
+        console
+            .write_dma(VIRTIO_MMIO_QUEUE_NOTIFY, 4, TRANSMITQ as u64, &mut ram)
+            .unwrap();
+        assert_eq!(console.output(), b"hello, world\n");
+    }
+
+    #[test]
+    fn test_oversized_chain_rejected() {
+        let mut ram = FakeRam::new(RAM_BASE, 0x1000);
+        let mut console = VirtioConsole::new(Vec::new());
+        configure_transmitq(&mut console);
+
+        write_descriptor(&mut ram, 0, BUFFERS, u32::MAX, 0, 0);
+        ram.write(AVAIL + 4, &0u16.to_le_bytes()).unwrap();
+        ram.write(AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+
+        assert!(
+            console
+                .write_dma(VIRTIO_MMIO_QUEUE_NOTIFY, 4, TRANSMITQ as u64, &mut ram)
+                .is_err()
+        );
+        assert!(console.output().is_empty());
+    }
+}
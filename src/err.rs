@@ -1,3 +1,4 @@
+use crate::devices::trace::MmioAccess;
 use ahvf::HypervisorError;
 use thiserror::Error;
 
@@ -23,6 +24,39 @@ pub enum SimppleError {
 
     #[error("System register not found: {0}")]
     SysRegNotFound(String),
+
+    #[error("Cannot start a vCPU at EL{requested}: the VM supports up to EL{supported}")]
+    UnsupportedExceptionLevel { requested: u8, supported: u8 },
+
+    #[error("Invalid exception class {0:#x}: EC is a 6-bit field")]
+    InvalidExceptionClass(u64),
+
+    #[error("Invalid syndrome access size encoding: {0:#b}")]
+    InvalidAccessSize(u8),
+
+    #[error("Inconsistent exception syndrome 0x{esr:016x}: {reason}")]
+    InvalidSyndrome { esr: u64, reason: String },
+
+    #[error("All {slots} hardware breakpoint slots are in use")]
+    NoFreeBreakpointSlot { slots: usize },
+
+    #[error(
+        "PARange {pa_range:#06b} ({advertised:?} bits) does not fit the VM's {ipa_bits}-bit IPA space"
+    )]
+    PaRangeExceedsIpa {
+        pa_range: u8,
+        advertised: Option<u8>,
+        ipa_bits: u8,
+    },
+}
+
+impl SimppleError {
+    pub fn invalid_syndrome(esr: u64, reason: impl Into<String>) -> Self {
+        Self::InvalidSyndrome {
+            esr,
+            reason: reason.into(),
+        }
+    }
 }
 
 impl From<HypervisorError> for SimppleError {
@@ -45,6 +79,37 @@ pub enum MemoryError {
 
     #[error("Invalid size: {size} bytes is invalid for this operation")]
     InvalidSize { size: usize },
+
+    #[error("No segment is mapped at base address 0x{base:x}")]
+    SegmentNotFound { base: u64 },
+
+    #[error(
+        "Image overlap: image at 0x{first_start:x}-0x{first_end:x} collides with image at 0x{second_start:x}-0x{second_end:x}"
+    )]
+    ImageOverlap {
+        first_start: u64,
+        first_end: u64,
+        second_start: u64,
+        second_end: u64,
+    },
+
+    #[error("Image at 0x{address:x} is not aligned to {alignment:#x}")]
+    MisalignedImage { address: u64, alignment: u64 },
+
+    #[error("Boot manifest has no images, so there is no entry point")]
+    NoEntryPoint,
+
+    #[error("Region 0x{start:x}-0x{end:x} lies beyond the {ipa_bits}-bit IPA space")]
+    BeyondIpaSpace { start: u64, end: u64, ipa_bits: u8 },
+
+    #[error(
+        "Permission denied: {access} at address 0x{address:x} (size: {size}) in a segment without {access} access"
+    )]
+    PermissionDenied {
+        address: u64,
+        size: usize,
+        access: &'static str,
+    },
 }
 
 impl MemoryError {
@@ -63,6 +128,39 @@ impl MemoryError {
     pub fn invalid_size(size: usize) -> Self {
         Self::InvalidSize { size }
     }
+
+    pub fn segment_not_found(base: u64) -> Self {
+        Self::SegmentNotFound { base }
+    }
+
+    pub fn misaligned_image(address: u64, alignment: u64) -> Self {
+        Self::MisalignedImage { address, alignment }
+    }
+
+    pub fn beyond_ipa_space(start: u64, end: u64, ipa_bits: u8) -> Self {
+        Self::BeyondIpaSpace {
+            start,
+            end,
+            ipa_bits,
+        }
+    }
+
+    pub fn permission_denied(address: u64, size: usize, access: &'static str) -> Self {
+        Self::PermissionDenied {
+            address,
+            size,
+            access,
+        }
+    }
+
+    pub fn image_overlap(first: (u64, u64), second: (u64, u64)) -> Self {
+        Self::ImageOverlap {
+            first_start: first.0,
+            first_end: first.1,
+            second_start: second.0,
+            second_end: second.1,
+        }
+    }
 }
 
 #[derive(Error, Debug, Clone)]
@@ -70,15 +168,23 @@ pub enum MmioError {
     #[error("Unmapped memory access at address 0x{0:016x}")]
     UnmappedAccess(u64),
 
-    #[error("Invalid alignment: address 0x{addr:016x} not aligned for {size}-byte access")]
-    InvalidAlignment { addr: u64, size: usize },
-
     #[error("Invalid access size: {size} bytes (must be 1, 2, 4, or 8)")]
     InvalidSize { size: usize },
 
     #[error("Device error: {0}")]
     DeviceError(String),
 
+    #[error("Invalid FIFO depth {depth}: must be a power of two from 1 to {max}")]
+    InvalidFifoDepth { depth: usize, max: usize },
+
+    #[error("{device} failed {size}-byte access at 0x{addr:016x}")]
+    DeviceFault {
+        device: String,
+        addr: u64,
+        size: usize,
+        source: Box<MmioError>,
+    },
+
     #[error(
         "Overlapping MMIO region: new region [0x{new_start:016x}, 0x{new_end:016x}) overlaps with existing region [0x{existing_start:016x}, 0x{existing_end:016x})"
     )]
@@ -88,9 +194,25 @@ pub enum MmioError {
         new_start: u64,
         new_end: u64,
     },
+
+    #[error(
+        "Invalid MMIO window at 0x{base:016x} of size 0x{size:x}: must be non-empty, cover the device and be {alignment}-byte aligned"
+    )]
+    MisalignedRegion {
+        base: u64,
+        size: u64,
+        alignment: u64,
+    },
+
+    #[error("MMIO replay diverged at access {index}: expected {expected}, got {actual}")]
+    ReplayDivergence {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
 }
 
-// Helper constructor for the overlapping region error
+// Helper constructors for MMIO errors
 impl MmioError {
     pub fn overlapping_region(existing: (u64, u64), new: (u64, u64)) -> Self {
         Self::OverlappingRegion {
@@ -100,4 +222,26 @@ impl MmioError {
             new_end: new.1,
         }
     }
+
+    /// Attach the device name and absolute address to an error raised by a device
+    pub fn device_fault(device: &str, addr: u64, size: usize, source: MmioError) -> Self {
+        Self::DeviceFault {
+            device: device.to_string(),
+            addr,
+            size,
+            source: Box::new(source),
+        }
+    }
+
+    pub fn replay_divergence(
+        index: usize,
+        expected: Option<MmioAccess>,
+        actual: MmioAccess,
+    ) -> Self {
+        Self::ReplayDivergence {
+            index,
+            expected: expected.map_or_else(|| "end of log".to_string(), |e| e.to_string()),
+            actual: actual.to_string(),
+        }
+    }
 }
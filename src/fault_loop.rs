@@ -0,0 +1,98 @@
+use crate::regs::ExceptionInfo;
+
+/// Notices a guest stuck taking the same fault over and over.
+///
+/// A fault is an exit the run loop could not emulate cleanly, such as a
+/// failed MMIO access, or one it restarts without advancing PC. When the
+/// guest's handler for it faults the same way, every exit looks identical
+/// and the VM would spin forever. Call `begin_exit()` on every exit and
+/// `record()` on every fault; a streak ends at the first exit that isn't one.
+#[derive(Debug)]
+pub struct FaultLoopDetector {
+    limit: u32,
+    last: Option<(u64, u64)>, // PC and syndrome of the current streak
+    count: u32,
+    faulted: bool, // Whether the previous exit recorded a fault
+}
+
+impl FaultLoopDetector {
+    /// Trip after `limit` identical faults in a row
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            last: None,
+            count: 0,
+            faulted: false,
+        }
+    }
+
+    pub fn begin_exit(&mut self) {
+        if !self.faulted {
+            self.last = None;
+            self.count = 0;
+        }
+        self.faulted = false;
+    }
+
+    /// Record a fault, true once the streak reaches the limit
+    pub fn record(&mut self, exception: &ExceptionInfo) -> bool {
+        let key = (exception.pc, exception.esr.raw());
+        if self.last == Some(key) {
+            self.count += 1;
+        } else {
+            self.last = Some(key);
+            self.count = 1;
+        }
+        self.faulted = true;
+        self.count >= self.limit
+    }
+
+    /// Length of the current streak
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ahvf::VirtualCpuExitException;
+
+    fn fault(pc: u64, syndrome: u64) -> ExceptionInfo {
+        ExceptionInfo::new(
+            pc,
+            &VirtualCpuExitException {
+                syndrome,
+                virtual_address: 0,
+                physical_address: 0,
+            },
+        )
+    }
+
+    #[test]
+    fn test_identical_faults_trip() {
+        let mut detector = FaultLoopDetector::new(3);
+        let abort = fault(0x1000, 0x9200_0046);
+
+        detector.begin_exit();
+        assert!(!detector.record(&abort));
+        detector.begin_exit();
+        assert!(!detector.record(&abort));
+
+        // A clean exit in between ends the streak
+        detector.begin_exit();
+        detector.begin_exit();
+        assert!(!detector.record(&abort));
+        assert_eq!(detector.count(), 1);
+
+        // So does a different fault
+        detector.begin_exit();
+        assert!(!detector.record(&fault(0x1004, 0x9200_0046)));
+        for _ in 0..2 {
+            detector.begin_exit();
+            assert!(!detector.record(&abort));
+        }
+        detector.begin_exit();
+        assert!(detector.record(&abort));
+    }
+}
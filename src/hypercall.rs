@@ -0,0 +1,115 @@
+//! Hypercalls the guest makes with `HVC #imm`.
+//!
+//! The immediate selects the handler and arguments are passed in X0-X7.
+//! The guest resumes after the HVC once the handler returns. The default
+//! table has two debug hypercalls:
+//!
+//! - `HVC #0xDEB`: print a guest register, X0 is its index in
+//!   `ALL_REGISTERS` (0-30 are X0-X30, then PC, FPCR, FPSR and CPSR)
+//! - `HVC #0xDEC`: hexdump X1 bytes of guest memory starting at X0
+
+use crate::debugger::Debugger;
+use crate::regs::{ALL_REGISTERS, register_name};
+use crate::{SharedMemory, SimppleError};
+use ahvf::{Register, VirtualCpu, VirtualMachine};
+use std::collections::BTreeMap;
+
+/// Print the guest register indexed by X0
+pub const HVC_PRINT_REGISTER: u16 = 0xDEB;
+/// Hexdump X1 bytes of guest memory at X0
+pub const HVC_HEXDUMP: u16 = 0xDEC;
+
+// Larger dumps are cut short, a wild length shouldn't flood the terminal
+const HEXDUMP_MAX_LEN: u64 = 0x1000;
+
+/// What a hypercall handler may touch
+pub struct HypercallContext<'a> {
+    pub vm: &'a VirtualMachine,
+    pub vcpu: &'a mut VirtualCpu,
+    pub mmu: &'a SharedMemory,
+    pub debugger: &'a Debugger,
+}
+
+pub type Hypercall = fn(&mut HypercallContext) -> Result<(), SimppleError>;
+
+/// Hypercall handlers by HVC immediate
+#[derive(Clone, Debug)]
+pub struct HypercallTable {
+    handlers: BTreeMap<u16, Hypercall>,
+}
+
+impl Default for HypercallTable {
+    fn default() -> Self {
+        let mut table = Self::empty();
+        table.register(HVC_PRINT_REGISTER, print_register);
+        table.register(HVC_HEXDUMP, hexdump);
+        table
+    }
+}
+
+impl HypercallTable {
+    /// A table without the debug hypercalls
+    pub fn empty() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    /// Handle `HVC #imm` with `handler`, replacing any previous one
+    pub fn register(&mut self, imm: u16, handler: Hypercall) {
+        self.handlers.insert(imm, handler);
+    }
+
+    pub fn get(&self, imm: u16) -> Option<Hypercall> {
+        self.handlers.get(&imm).copied()
+    }
+}
+
+fn print_register(context: &mut HypercallContext) -> Result<(), SimppleError> {
+    let index = context.vcpu.get_register(Register::X0)?;
+    match ALL_REGISTERS.get(index as usize) {
+        Some(&register) => {
+            let value = context.vcpu.get_register(register)?;
+            context
+                .debugger
+                .print_register(register_name(register), value);
+        }
+        None => log::warn!("HVC #{HVC_PRINT_REGISTER:#x}: no register with index {index}"),
+    }
+    Ok(())
+}
+
+fn hexdump(context: &mut HypercallContext) -> Result<(), SimppleError> {
+    let address = context.vcpu.get_register(Register::X0)?;
+    let len = context.vcpu.get_register(Register::X1)?;
+    if len > HEXDUMP_MAX_LEN {
+        log::warn!(
+            "HVC #{HVC_HEXDUMP:#x}: dumping the first {HEXDUMP_MAX_LEN:#x} of {len:#x} bytes"
+        );
+    }
+    log::info!("Guest memory at {address:#x}:");
+    // A bad address is the guest's bug to fix, not a reason to stop it
+    if let Err(e) = context.debugger.hexdump(
+        context.vm,
+        context.mmu,
+        address,
+        len.min(HEXDUMP_MAX_LEN) as usize,
+    ) {
+        log::warn!("HVC #{HVC_HEXDUMP:#x}: {e}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_table() {
+        let table = HypercallTable::default();
+        assert!(table.get(HVC_PRINT_REGISTER).is_some());
+        assert!(table.get(HVC_HEXDUMP).is_some());
+        assert!(table.get(0).is_none());
+        assert!(HypercallTable::empty().get(HVC_HEXDUMP).is_none());
+    }
+}
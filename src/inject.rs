@@ -0,0 +1,172 @@
+use crate::SimppleError;
+use crate::regs::{EsrEl2, SpsrEl3};
+use ahvf::{Register, SystemRegister, VirtualCpu};
+
+// Offsets of the exception vector groups from VBAR_EL1
+const VECTOR_CURRENT_EL_SP0: u64 = 0x000;
+const VECTOR_CURRENT_EL_SPX: u64 = 0x200;
+const VECTOR_LOWER_EL_AARCH64: u64 = 0x400;
+
+// Offsets of the entries within a vector group
+const VECTOR_SYNCHRONOUS: u64 = 0x000;
+const VECTOR_SERROR: u64 = 0x180;
+
+// Exception class of an SError interrupt
+const EC_SERROR: u64 = 0b101111;
+// Exception class of an instruction that is UNDEFINED for an unknown reason
+const EC_UNKNOWN: u64 = 0b000000;
+
+/// ISS of an injected SError when no syndrome is given: IDS=0, AET=0b000
+/// (uncontainable), EA=0, DFSC=0b010001 (asynchronous SError interrupt)
+pub const SERROR_ISS_UNCONTAINABLE: u32 = 0b010001;
+
+/// Build the ESR_EL1 value reported for an injected SError.
+///
+/// EC is 0b101111 and IL is set. The ISS either has IDS (bit 24) set with an
+/// IMPLEMENTATION DEFINED syndrome in bits [23:0], or IDS clear with
+/// AET [12:10], EA [9] and DFSC [5:0] (0b010001 for an asynchronous SError).
+pub fn serror_syndrome(iss: u32) -> u64 {
+    let mut esr = EsrEl2::new();
+    esr.set_ec(EC_SERROR);
+    esr.set_il(true);
+    esr.set_iss(u64::from(iss & 0x1ff_ffff));
+    debug_assert!(esr.is_consistent());
+    esr.raw()
+}
+
+/// Build the ESR_EL1 value reported for an UNDEFINED instruction: EC 0, IL set
+pub fn undefined_syndrome() -> u64 {
+    let mut esr = EsrEl2::new();
+    esr.set_ec(EC_UNKNOWN);
+    esr.set_il(true);
+    esr.raw()
+}
+
+/// Delivers exceptions to the guest's EL1 vectors.
+///
+/// Hypervisor.framework can only pend virtual IRQs and FIQs, so SErrors and
+/// UNDEFINED instruction exceptions are emulated by performing the exception
+/// entry in software: the PSTATE and PC are saved into SPSR_EL1/ELR_EL1,
+/// ESR_EL1 receives the syndrome and the vCPU resumes at the vector. While
+/// PSTATE.A masks SErrors the injection stays pending; UNDEFINED exceptions
+/// are synchronous and taken at once.
+#[derive(Debug, Default)]
+pub struct ExceptionInjector {
+    pending_serror: Option<u64>,
+}
+
+impl ExceptionInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make an SError with the given ISS pending and deliver it if unmasked
+    pub fn inject_serror(
+        &mut self,
+        vcpu: &mut VirtualCpu,
+        esr_iss: u32,
+    ) -> Result<(), SimppleError> {
+        self.pending_serror = Some(serror_syndrome(esr_iss));
+        self.deliver_pending(vcpu)?;
+        Ok(())
+    }
+
+    /// Take an UNDEFINED instruction exception at the current PC.
+    ///
+    /// Call with PC still at the offending instruction, it becomes ELR_EL1.
+    pub fn inject_undefined(&mut self, vcpu: &mut VirtualCpu) -> Result<(), SimppleError> {
+        let esr = undefined_syndrome();
+        let pc = take_exception(vcpu, esr, VECTOR_SYNCHRONOUS)?;
+        log::info!("Injected UNDEFINED instruction exception at PC {pc:#x}");
+        Ok(())
+    }
+
+    /// Whether an SError is waiting for the guest to unmask PSTATE.A
+    pub fn serror_pending(&self) -> bool {
+        self.pending_serror.is_some()
+    }
+
+    /// Deliver a pending SError if the guest no longer masks it.
+    ///
+    /// Call before every `vcpu.run()`. Returns whether an exception was taken.
+    pub fn deliver_pending(&mut self, vcpu: &mut VirtualCpu) -> Result<bool, SimppleError> {
+        let Some(esr) = self.pending_serror else {
+            return Ok(false);
+        };
+
+        let pstate = SpsrEl3::from_raw(vcpu.get_register(Register::CPSR)?);
+        if pstate.a() {
+            return Ok(false);
+        }
+
+        let pc = take_exception(vcpu, esr, VECTOR_SERROR)?;
+        self.pending_serror = None;
+        log::info!("Injected SError (ESR_EL1 = {esr:#x}) taken from PC {pc:#x}");
+        Ok(true)
+    }
+}
+
+/// Enter the EL1 vector `entry` with syndrome `esr`, returning the PC the exception was taken from
+fn take_exception(vcpu: &mut VirtualCpu, esr: u64, entry: u64) -> Result<u64, SimppleError> {
+    let pstate = SpsrEl3::from_raw(vcpu.get_register(Register::CPSR)?);
+    let vbar = vcpu.get_system_register(SystemRegister::VBAR_EL1)?;
+    let pc = vcpu.get_register(Register::PC)?;
+
+    vcpu.set_system_register(SystemRegister::ESR_EL1, esr)?;
+    vcpu.set_system_register(SystemRegister::ELR_EL1, pc)?;
+    vcpu.set_system_register(SystemRegister::SPSR_EL1, pstate.raw())?;
+
+    let mut target = SpsrEl3::new();
+    target.set_m3_0(SpsrEl3::EL1H);
+    target.set_interrupt_masks(true, true, true, true);
+    vcpu.set_register(Register::CPSR, target.raw())?;
+    vcpu.set_register(Register::PC, vbar + vector_offset(&pstate, entry))?;
+    Ok(pc)
+}
+
+/// Offset from VBAR_EL1 of the vector `entry` used when taking an exception from `pstate`
+fn vector_offset(pstate: &SpsrEl3, entry: u64) -> u64 {
+    let group = match (pstate.exception_level(), pstate.stack_pointer_is_el0()) {
+        (0, _) => VECTOR_LOWER_EL_AARCH64,
+        (_, true) => VECTOR_CURRENT_EL_SP0,
+        (_, false) => VECTOR_CURRENT_EL_SPX,
+    };
+    group + entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::ExceptionClass;
+
+    #[test]
+    fn test_serror_syndrome() {
+        let esr = EsrEl2::from_raw(serror_syndrome(SERROR_ISS_UNCONTAINABLE));
+        assert_eq!(esr.exception_class(), ExceptionClass::SError);
+        assert!(esr.il());
+        assert_eq!(esr.iss(), 0b010001);
+    }
+
+    #[test]
+    fn test_undefined_syndrome() {
+        let esr = EsrEl2::from_raw(undefined_syndrome());
+        assert_eq!(esr.exception_class(), ExceptionClass::Unknown);
+        assert!(esr.il());
+        assert_eq!(esr.iss(), 0);
+    }
+
+    #[test]
+    fn test_vector_offset() {
+        let mut pstate = SpsrEl3::new();
+        pstate.set_m3_0(SpsrEl3::EL1H);
+        assert_eq!(vector_offset(&pstate, VECTOR_SERROR), 0x380);
+        assert_eq!(vector_offset(&pstate, VECTOR_SYNCHRONOUS), 0x200);
+
+        pstate.set_m3_0(SpsrEl3::EL1T);
+        assert_eq!(vector_offset(&pstate, VECTOR_SERROR), 0x180);
+
+        pstate.set_m3_0(SpsrEl3::EL0);
+        assert_eq!(vector_offset(&pstate, VECTOR_SERROR), 0x580);
+        assert_eq!(vector_offset(&pstate, VECTOR_SYNCHRONOUS), 0x400);
+    }
+}
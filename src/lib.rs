@@ -1,9 +1,23 @@
+pub mod asm;
+pub mod boot;
+pub mod break_request;
 pub mod debugger;
 pub mod devices;
 pub mod err;
+pub mod fault_loop;
+pub mod hypercall;
+pub mod inject;
 pub mod mems;
+pub mod platform;
+mod poll_thread;
+pub mod psci;
 pub mod regs;
+pub mod runner;
+pub mod vcpu;
+pub mod vm;
+pub mod watchdog;
 
+pub use boot::{BootConfig, boot};
 pub use devices::MmioManager;
 pub use err::SimppleError;
 pub use mems::SharedMemory;
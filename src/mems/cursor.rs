@@ -0,0 +1,173 @@
+use crate::{SharedMemory, SimppleError};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Byte-addressed read access to guest-physical memory
+pub trait GuestMemory {
+    /// Number of contiguous mapped bytes starting at `address`, `None` if unmapped
+    fn mapped_len(&self, address: u64) -> Option<u64>;
+
+    /// Fill `buffer` from `address`; the whole range must be mapped contiguously
+    fn read_into(&self, address: u64, buffer: &mut [u8]) -> Result<(), SimppleError>;
+}
+
+/// A `SharedMemory` paired with the VM owning its allocations
+#[derive(Clone, Copy)]
+pub struct GuestView<'a> {
+    mmu: &'a SharedMemory,
+    vm: &'a ahvf::VirtualMachine,
+}
+
+impl<'a> GuestView<'a> {
+    pub fn new(mmu: &'a SharedMemory, vm: &'a ahvf::VirtualMachine) -> Self {
+        Self { mmu, vm }
+    }
+}
+
+impl GuestMemory for GuestView<'_> {
+    fn mapped_len(&self, address: u64) -> Option<u64> {
+        self.mmu.mapped_len(address)
+    }
+
+    fn read_into(&self, address: u64, buffer: &mut [u8]) -> Result<(), SimppleError> {
+        self.mmu.read_into(self.vm, address, buffer)
+    }
+}
+
+/// `Read + Seek` stream over guest memory.
+///
+/// The position is a guest-physical address. Reads stop at the end of the
+/// current segment and continue in the next one if it is adjacent; reading
+/// from an unmapped address is an error.
+pub struct GuestCursor<M: GuestMemory> {
+    memory: M,
+    position: u64,
+}
+
+impl<'a> GuestCursor<GuestView<'a>> {
+    /// Create a cursor over `mmu` positioned at `address`
+    pub fn new(mmu: &'a SharedMemory, vm: &'a ahvf::VirtualMachine, address: u64) -> Self {
+        Self::with_memory(GuestView::new(mmu, vm), address)
+    }
+}
+
+impl<M: GuestMemory> GuestCursor<M> {
+    pub fn with_memory(memory: M, address: u64) -> Self {
+        Self {
+            memory,
+            position: address,
+        }
+    }
+
+    /// Current guest-physical address
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<M: GuestMemory> Read for GuestCursor<M> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let available = self.memory.mapped_len(self.position).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("unmapped guest address {:#x}", self.position),
+            )
+        })?;
+
+        let size = buf.len().min(available as usize);
+        self.memory
+            .read_into(self.position, &mut buf[..size])
+            .map_err(io::Error::other)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<M: GuestMemory> Seek for GuestCursor<M> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(address) => Some(address),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "guest memory has no end to seek from",
+                ));
+            }
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek outside the address space",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err::MemoryError;
+
+    // Two adjacent regions, like two back-to-back segments
+    struct FakeMemory {
+        regions: Vec<(u64, Vec<u8>)>,
+    }
+
+    impl GuestMemory for FakeMemory {
+        fn mapped_len(&self, address: u64) -> Option<u64> {
+            self.regions.iter().find_map(|(base, data)| {
+                (address >= *base && address < base + data.len() as u64)
+                    .then(|| base + data.len() as u64 - address)
+            })
+        }
+
+        fn read_into(&self, address: u64, buffer: &mut [u8]) -> Result<(), SimppleError> {
+            let (base, data) = self
+                .regions
+                .iter()
+                .find(|(base, data)| address >= *base && address < base + data.len() as u64)
+                .ok_or_else(|| MemoryError::segfault(address, buffer.len(), "unmapped"))?;
+            let offset = (address - base) as usize;
+            buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_struct_fields() {
+        // struct { u32 magic; u16 version; u16 flags; u64 entry; } straddling two regions
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0xd00dfeedu32.to_le_bytes());
+        bytes.extend_from_slice(&17u16.to_le_bytes());
+        bytes.extend_from_slice(&0x1u16.to_le_bytes());
+        bytes.extend_from_slice(&0x4008_0000u64.to_le_bytes());
+        let (low, high) = bytes.split_at(6);
+        let memory = FakeMemory {
+            regions: vec![(0x1000, low.to_vec()), (0x1006, high.to_vec())],
+        };
+
+        let mut cursor = GuestCursor::with_memory(memory, 0x1000);
+        let mut u32_buf = [0u8; 4];
+        let mut u16_buf = [0u8; 2];
+        let mut u64_buf = [0u8; 8];
+
+        cursor.read_exact(&mut u32_buf).unwrap();
+        assert_eq!(u32::from_le_bytes(u32_buf), 0xd00dfeed);
+        cursor.read_exact(&mut u16_buf).unwrap();
+        assert_eq!(u16::from_le_bytes(u16_buf), 17);
+
+        // Skip the flags field
+        cursor.seek(SeekFrom::Current(2)).unwrap();
+        cursor.read_exact(&mut u64_buf).unwrap();
+        assert_eq!(u64::from_le_bytes(u64_buf), 0x4008_0000);
+
+        // Past the last region is a gap
+        assert!(cursor.read_exact(&mut u16_buf).is_err());
+    }
+}
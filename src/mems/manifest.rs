@@ -0,0 +1,184 @@
+//! Describe where each boot image goes and load them all in one step.
+//!
+//! A manifest lists images (firmware, DTB, kernel, initrd, ...) with their
+//! load address and required alignment. Loading reads every image and checks
+//! alignment, overlap and segment membership before anything is written, so
+//! a bad entry leaves guest memory untouched.
+
+use crate::err::MemoryError;
+use crate::{SharedMemory, SimppleError};
+use anyhow::Context;
+use std::borrow::Cow;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where an image's contents come from, bytes may be borrowed
+#[derive(Clone, Debug)]
+pub enum ImageSource<'a> {
+    Path(PathBuf),
+    Bytes(Cow<'a, [u8]>),
+}
+
+impl From<PathBuf> for ImageSource<'_> {
+    fn from(path: PathBuf) -> Self {
+        ImageSource::Path(path)
+    }
+}
+
+impl From<Vec<u8>> for ImageSource<'_> {
+    fn from(bytes: Vec<u8>) -> Self {
+        ImageSource::Bytes(Cow::Owned(bytes))
+    }
+}
+
+impl<'a> From<&'a [u8]> for ImageSource<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        ImageSource::Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl ImageSource<'_> {
+    fn read(&self) -> Result<Cow<'_, [u8]>, SimppleError> {
+        match self {
+            ImageSource::Path(path) => {
+                Ok(Cow::Owned(fs::read(path).with_context(|| {
+                    format!("Failed to read boot image {}", path.display())
+                })?))
+            }
+            ImageSource::Bytes(bytes) => Ok(Cow::Borrowed(bytes)),
+        }
+    }
+}
+
+// An image read from its source, with its load address
+type LoadedImage<'a> = (u64, Cow<'a, [u8]>);
+
+/// One image of a `BootManifest`
+#[derive(Clone, Debug)]
+pub struct ManifestEntry<'a> {
+    pub source: ImageSource<'a>,
+    pub load_address: u64,
+    /// Required alignment of `load_address`, a power of two
+    pub alignment: u64,
+    /// Whether the vCPU starts at this image's load address
+    pub entry: bool,
+}
+
+/// The set of images to place in guest memory before boot
+#[derive(Clone, Debug, Default)]
+pub struct BootManifest<'a> {
+    entries: Vec<ManifestEntry<'a>>,
+}
+
+impl<'a> BootManifest<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an image loaded at `load_address`
+    pub fn image(
+        mut self,
+        source: impl Into<ImageSource<'a>>,
+        load_address: u64,
+        alignment: u64,
+    ) -> Self {
+        self.entries.push(ManifestEntry {
+            source: source.into(),
+            load_address,
+            alignment,
+            entry: false,
+        });
+        self
+    }
+
+    /// Add the image the vCPU starts executing at
+    pub fn entry_image(
+        mut self,
+        source: impl Into<ImageSource<'a>>,
+        load_address: u64,
+        alignment: u64,
+    ) -> Self {
+        self = self.image(source, load_address, alignment);
+        if let Some(last) = self.entries.last_mut() {
+            last.entry = true;
+        }
+        self
+    }
+
+    pub fn entries(&self) -> &[ManifestEntry<'a>] {
+        &self.entries
+    }
+
+    /// Load every image, returning the entry PC.
+    ///
+    /// The entry PC is the load address of the image added with
+    /// `entry_image`, or of the first image if none was.
+    pub fn load(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        mmu: &SharedMemory,
+    ) -> Result<u64, SimppleError> {
+        let (images, entry) = self.prepare()?;
+        let images: Vec<(u64, &[u8])> = images
+            .iter()
+            .map(|(address, data)| (*address, data.as_ref()))
+            .collect();
+        // Checks overlap and segment membership before writing anything
+        mmu.load_images(vm, &images)?;
+        Ok(entry)
+    }
+
+    /// Read every image and check its alignment
+    fn prepare(&self) -> Result<(Vec<LoadedImage<'_>>, u64), SimppleError> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.entry)
+            .or(self.entries.first())
+            .map(|entry| entry.load_address)
+            .ok_or(MemoryError::NoEntryPoint)?;
+
+        let mut images = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            if !entry.alignment.is_power_of_two()
+                || !entry.load_address.is_multiple_of(entry.alignment)
+            {
+                return Err(
+                    MemoryError::misaligned_image(entry.load_address, entry.alignment).into(),
+                );
+            }
+            images.push((entry.load_address, entry.source.read()?));
+        }
+        Ok((images, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_misaligned_image_rejected() {
+        let manifest = BootManifest::new()
+            .entry_image(vec![0u8; 0x100], 0x0, 0x1000)
+            .image(vec![0u8; 0x10], 0x4000_0100, 0x20_0000);
+
+        assert!(matches!(
+            manifest.prepare(),
+            Err(SimppleError::Memory(MemoryError::MisalignedImage {
+                address: 0x4000_0100,
+                alignment: 0x20_0000,
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_entry_pc() {
+        let manifest = BootManifest::new()
+            .image(vec![0u8; 0x10], 0x4000_0000, 8)
+            .entry_image(vec![0u8; 0x100], 0x4008_0000, 0x1000);
+        assert_eq!(manifest.prepare().unwrap().1, 0x4008_0000);
+
+        assert!(BootManifest::new().prepare().is_err());
+    }
+}
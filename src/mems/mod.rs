@@ -1,3 +1,8 @@
+pub mod cursor;
+pub mod digest;
+pub mod manifest;
 pub mod shared;
 
+pub use cursor::*;
+pub use manifest::{BootManifest, ImageSource, ManifestEntry};
 pub use shared::*;
@@ -1,18 +1,88 @@
 use crate::SimppleError;
 use crate::err::MemoryError;
+use crate::mems::digest;
 use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Access accounting for a segment, only updated while profiling is enabled
+#[derive(Debug, Default)]
+struct SegmentCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl SegmentCounters {
+    fn record_read(&self, bytes: usize) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn record_write(&self, bytes: usize) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, base: u64, size: usize) -> SegmentStats {
+        SegmentStats {
+            base,
+            size,
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the access counters of one segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentStats {
+    pub base: u64,
+    pub size: usize,
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Location and permission of one RAM segment
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentInfo {
+    pub base: u64,
+    pub size: usize,
+    pub permission: ahvf::MemoryPermission,
+}
+
+// Stage-2 mappings are made in units of the 16KiB Apple silicon page
+const HOST_PAGE_SIZE: usize = 0x4000;
 
 // Shared memory management
 #[derive(Debug)]
 struct Segment {
-    base: u64,                      // base address (guest physical)
-    size: usize,                    // size
-    handle: ahvf::AllocationHandle, // handle to the memory allocator
+    base: u64,                          // base address (guest physical)
+    size: usize,                        // size
+    handle: ahvf::AllocationHandle,     // handle to the memory allocator
+    permission: ahvf::MemoryPermission, // current stage-2 permission
+    counters: SegmentCounters,          // access statistics
 }
 
 impl Segment {
-    pub fn new(handle: ahvf::AllocationHandle, base: u64, size: usize) -> Self {
-        Segment { base, size, handle }
+    pub fn new(
+        handle: ahvf::AllocationHandle,
+        base: u64,
+        size: usize,
+        permission: ahvf::MemoryPermission,
+    ) -> Self {
+        Segment {
+            base,
+            size,
+            handle,
+            permission,
+            counters: SegmentCounters::default(),
+        }
     }
 
     pub fn contains(&self, address: u64, size: usize) -> bool {
@@ -33,6 +103,7 @@ impl Segment {
 #[derive(Debug, Default)]
 pub struct SharedMemory {
     segments: Vec<Segment>, // list of segments
+    profiling: bool,        // whether to count accesses per segment
 }
 
 impl SharedMemory {
@@ -59,11 +130,128 @@ impl SharedMemory {
         let handle = vm.allocate(size)?;
         vm.map(handle, base, permission)?;
 
-        let segment = Segment::new(handle, base, size);
+        let segment = Segment::new(handle, base, size, permission);
         self.segments.push(segment);
         Ok(())
     }
 
+    /// Unmap the segment starting at `base` and free its host memory
+    pub fn remove_segment(
+        &mut self,
+        vm: &mut ahvf::VirtualMachine,
+        base: u64,
+    ) -> Result<(), SimppleError> {
+        let index = self
+            .segments
+            .iter()
+            .position(|seg| seg.base == base)
+            .ok_or(MemoryError::segment_not_found(base))?;
+
+        let segment = &self.segments[index];
+        vm.unmap(segment.base, segment.size)?;
+        // The guest can't reach it any more, forget it even if freeing fails
+        let segment = self.segments.remove(index);
+        vm.deallocate(segment.handle)?;
+        Ok(())
+    }
+
+    /// Map `contents` at `base` as read-only, executable memory.
+    ///
+    /// The segment is rounded up to whole host pages, padded with zeros.
+    /// Guest stores into it fail at stage 2 and exit as data aborts, and
+    /// `write_bytes` rejects host writes the same way. The loader and the
+    /// debugger still get in through `write_bytes_privileged`.
+    pub fn add_rom_segment(
+        &mut self,
+        vm: &mut ahvf::VirtualMachine,
+        base: u64,
+        contents: &[u8],
+    ) -> Result<(), SimppleError> {
+        if contents.is_empty() {
+            return Err(MemoryError::invalid_size(0).into());
+        }
+
+        let size = contents.len().next_multiple_of(HOST_PAGE_SIZE);
+        self.add_segment(vm, base, size, ahvf::MemoryPermission::READ_EXECUTE)?;
+        self.write_bytes_privileged(vm, base, contents)
+    }
+
+    /// Change the permission of the segment starting at `base`.
+    ///
+    /// Hypervisor.framework supports protecting a mapping in place
+    /// (`hv_vm_protect`), so the segment stays mapped and keeps its contents.
+    pub fn set_permission(
+        &mut self,
+        vm: &mut ahvf::VirtualMachine,
+        base: u64,
+        permission: ahvf::MemoryPermission,
+    ) -> Result<(), SimppleError> {
+        let segment = self
+            .segments
+            .iter_mut()
+            .find(|seg| seg.base == base)
+            .ok_or(MemoryError::segment_not_found(base))?;
+
+        vm.reprotect(segment.base, segment.size, permission)?;
+        segment.permission = permission;
+        Ok(())
+    }
+
+    /// Get the permission of the segment starting at `base`
+    pub fn permission(&self, base: u64) -> Option<ahvf::MemoryPermission> {
+        self.segments
+            .iter()
+            .find(|seg| seg.base == base)
+            .map(|seg| seg.permission)
+    }
+
+    /// Get the permission of the segment containing `address`
+    pub fn segment_permission(&self, address: u64) -> Option<ahvf::MemoryPermission> {
+        self.segments
+            .iter()
+            .find(|seg| seg.get_offset(address).is_some())
+            .map(|seg| seg.permission)
+    }
+
+    /// The guest's RAM segments, in the order they were added
+    pub fn segments(&self) -> impl Iterator<Item = SegmentInfo> + '_ {
+        self.segments.iter().map(|segment| SegmentInfo {
+            base: segment.base,
+            size: segment.size,
+            permission: segment.permission,
+        })
+    }
+
+    /// Enable or disable per-segment access counting
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiling = enabled;
+    }
+
+    /// Snapshot the access counters of every segment, sorted by base address
+    pub fn stats(&self) -> Vec<SegmentStats> {
+        let mut stats: Vec<SegmentStats> = self
+            .segments
+            .iter()
+            .map(|segment| segment.counters.snapshot(segment.base, segment.size))
+            .collect();
+        stats.sort_by_key(|stat| stat.base);
+        stats
+    }
+
+    // Count a read of `bytes` bytes from a segment, if profiling
+    fn count_read(&self, counters: &SegmentCounters, bytes: usize) {
+        if self.profiling {
+            counters.record_read(bytes);
+        }
+    }
+
+    // Count a write of `bytes` bytes to a segment, if profiling
+    fn count_write(&self, counters: &SegmentCounters, bytes: usize) {
+        if self.profiling {
+            counters.record_write(bytes);
+        }
+    }
+
     // Find segment containing the address range
     fn find_segment(&self, address: u64, size: usize) -> Result<&Segment, MemoryError> {
         self.segments
@@ -82,6 +270,25 @@ impl SharedMemory {
             })
     }
 
+    // Find the segment containing the address range and check it allows `access`
+    fn find_accessible_segment(
+        &self,
+        address: u64,
+        size: usize,
+        access: ahvf::MemoryPermission,
+    ) -> Result<&Segment, MemoryError> {
+        let segment = self.find_segment(address, size)?;
+        if !segment.permission.contains(access) {
+            let access = if access.contains(ahvf::MemoryPermission::WRITE) {
+                "write"
+            } else {
+                "read"
+            };
+            return Err(MemoryError::permission_denied(address, size, access));
+        }
+        Ok(segment)
+    }
+
     // Raw byte operations
     pub fn read_bytes(
         &self,
@@ -89,16 +296,76 @@ impl SharedMemory {
         address: u64,
         size: usize,
     ) -> Result<Vec<u8>, SimppleError> {
+        let mut buffer = vec![0; size];
+        self.read_into(vm, address, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Fill `buffer` with guest memory starting at `address`
+    pub fn read_into(
+        &self,
+        vm: &ahvf::VirtualMachine,
+        address: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), SimppleError> {
+        let size = buffer.len();
         if size == 0 {
-            return Ok(Vec::new());
+            return Ok(());
         }
 
-        let segment = self.find_segment(address, size)?;
+        let segment = self.find_accessible_segment(address, size, ahvf::MemoryPermission::READ)?;
         let offset = segment.get_offset(address).unwrap() as usize;
 
+        self.count_read(&segment.counters, size);
+
+        let memory = vm.get_allocation_slice(segment.handle)?;
+        buffer.copy_from_slice(&memory[offset..offset + size]);
+        Ok(())
+    }
+
+    /// Borrow `size` bytes of guest memory starting at `address` without copying
+    pub fn slice<'a>(
+        &self,
+        vm: &'a ahvf::VirtualMachine,
+        address: u64,
+        size: usize,
+    ) -> Result<&'a [u8], SimppleError> {
+        if size == 0 {
+            return Ok(&[]);
+        }
+
+        let segment = self.find_accessible_segment(address, size, ahvf::MemoryPermission::READ)?;
+        let offset = segment.get_offset(address).unwrap() as usize;
         let memory = vm.get_allocation_slice(segment.handle)?;
+        Ok(&memory[offset..offset + size])
+    }
+
+    /// CRC-32 of a guest memory range, comparable with `crc32` of the source file
+    pub fn checksum(
+        &self,
+        vm: &ahvf::VirtualMachine,
+        address: u64,
+        size: usize,
+    ) -> Result<u32, SimppleError> {
+        Ok(digest::crc32(self.slice(vm, address, size)?))
+    }
 
-        Ok(memory[offset..offset + size].to_vec())
+    /// SHA-256 of a guest memory range
+    pub fn hash(
+        &self,
+        vm: &ahvf::VirtualMachine,
+        address: u64,
+        size: usize,
+    ) -> Result<[u8; 32], SimppleError> {
+        Ok(digest::sha256(self.slice(vm, address, size)?))
+    }
+
+    /// Number of bytes from `address` to the end of the segment containing it
+    pub fn mapped_len(&self, address: u64) -> Option<u64> {
+        self.segments.iter().find_map(|seg| {
+            seg.get_offset(address)
+                .map(|offset| seg.size as u64 - offset)
+        })
     }
 
     pub fn write_bytes(
@@ -112,14 +379,119 @@ impl SharedMemory {
             return Ok(());
         }
 
-        let segment = self.find_segment(address, size)?;
+        let segment = self.find_accessible_segment(address, size, ahvf::MemoryPermission::WRITE)?;
+        self.write_segment(vm, segment, address, data)
+    }
+
+    /// Like `write_bytes`, but ignoring the segment permission.
+    ///
+    /// For the loader and the debugger, which must patch read-only segments
+    /// such as ROM. The range still has to be mapped.
+    pub fn write_bytes_privileged(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        address: u64,
+        data: &[u8],
+    ) -> Result<(), SimppleError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let segment = self.find_segment(address, data.len())?;
+        self.write_segment(vm, segment, address, data)
+    }
+
+    // Copy `data` into a segment already known to contain it
+    fn write_segment(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        segment: &Segment,
+        address: u64,
+        data: &[u8],
+    ) -> Result<(), SimppleError> {
+        let size = data.len();
         let offset = segment.get_offset(address).unwrap() as usize;
 
+        self.count_write(&segment.counters, size);
+
         let memory = vm.get_allocation_slice_mut(segment.handle)?;
         memory[offset..offset + size].copy_from_slice(data);
         Ok(())
     }
 
+    /// Fill `len` bytes of guest memory at `address` with `value`.
+    ///
+    /// The range must lie within one writable segment, a range crossing a
+    /// segment boundary is a segmentation fault even if both are mapped.
+    pub fn memset(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        address: u64,
+        value: u8,
+        len: usize,
+    ) -> Result<(), SimppleError> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let segment = self.find_accessible_segment(address, len, ahvf::MemoryPermission::WRITE)?;
+        let offset = segment.get_offset(address).unwrap() as usize;
+
+        self.count_write(&segment.counters, len);
+
+        let memory = vm.get_allocation_slice_mut(segment.handle)?;
+        memory[offset..offset + len].fill(value);
+        Ok(())
+    }
+
+    /// Write several images into guest memory at once.
+    ///
+    /// Every image must fit inside a mapped segment and no two images may
+    /// overlap; nothing is written unless all checks pass. Like
+    /// `write_bytes_privileged`, read-only segments can be loaded.
+    pub fn load_images(
+        &self,
+        vm: &mut ahvf::VirtualMachine,
+        images: &[(u64, &[u8])],
+    ) -> Result<(), SimppleError> {
+        Self::check_image_overlaps(images)?;
+
+        for (address, data) in images {
+            if !data.is_empty() {
+                self.find_segment(*address, data.len())?;
+            }
+        }
+
+        for (address, data) in images {
+            self.write_bytes_privileged(vm, *address, data)?;
+        }
+        Ok(())
+    }
+
+    // Reject any pair of images whose address ranges intersect
+    fn check_image_overlaps(images: &[(u64, &[u8])]) -> Result<(), MemoryError> {
+        let mut ranges: Vec<(u64, u64)> = images
+            .iter()
+            .filter(|(_, data)| !data.is_empty())
+            .map(|(address, data)| (*address, address.saturating_add(data.len() as u64)))
+            .collect();
+        ranges.sort_unstable();
+
+        // Track the range reaching furthest so far, so nested images are caught too
+        let mut furthest: Option<(u64, u64)> = None;
+        for range in ranges {
+            if let Some(previous) = furthest
+                && range.0 < previous.1
+            {
+                return Err(MemoryError::image_overlap(previous, range));
+            }
+            if furthest.is_none_or(|previous| range.1 > previous.1) {
+                furthest = Some(range);
+            }
+        }
+        Ok(())
+    }
+
     // Generic read/write for any sized integer type
     pub fn read<T>(&self, vm: &ahvf::VirtualMachine, address: u64) -> Result<T>
     where
@@ -210,3 +582,67 @@ impl ToBytes for u64 {
         (*self).to_le_bytes().to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlapping_images_rejected() {
+        let kernel = [0u8; 0x100];
+        let dtb = [0u8; 0x10];
+        let images: [(u64, &[u8]); 2] = [(0x4000_0000, &kernel), (0x4000_00f0, &dtb)];
+
+        let err = SharedMemory::check_image_overlaps(&images).unwrap_err();
+        assert!(matches!(
+            err,
+            MemoryError::ImageOverlap {
+                first_start: 0x4000_0000,
+                second_start: 0x4000_00f0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_adjacent_images_accepted() {
+        let kernel = [0u8; 0x100];
+        let dtb = [0u8; 0x10];
+        let images: [(u64, &[u8]); 2] = [(0x4000_0100, &dtb), (0x4000_0000, &kernel)];
+
+        assert!(SharedMemory::check_image_overlaps(&images).is_ok());
+    }
+
+    #[test]
+    fn test_access_counters_follow_profiling() {
+        let mut memory = SharedMemory::default();
+        let counters = SegmentCounters::default();
+
+        // Off by default, accesses leave the counters alone
+        memory.count_read(&counters, 8);
+        memory.count_write(&counters, 8);
+        assert_eq!(
+            counters.snapshot(0x4000_0000, 0x1000),
+            SegmentStats {
+                base: 0x4000_0000,
+                size: 0x1000,
+                reads: 0,
+                writes: 0,
+                bytes_read: 0,
+                bytes_written: 0,
+            }
+        );
+
+        memory.set_profiling(true);
+        memory.count_read(&counters, 8);
+        memory.count_read(&counters, 4);
+        memory.count_write(&counters, 16);
+        let stats = counters.snapshot(0x4000_0000, 0x1000);
+        assert_eq!((stats.reads, stats.bytes_read), (2, 12));
+        assert_eq!((stats.writes, stats.bytes_written), (1, 16));
+
+        memory.set_profiling(false);
+        memory.count_write(&counters, 4);
+        assert_eq!(counters.snapshot(0x4000_0000, 0x1000), stats);
+    }
+}
@@ -1,15 +1,12 @@
 use anyhow::Result;
-use keystone_engine::{Arch, Keystone, Mode};
+use simpple_vm::asm::assemble;
 
 use std::fs;
 
 pub fn gen_payload() -> Result<Vec<u8>> {
-    let engine = Keystone::new(Arch::ARM64, Mode::LITTLE_ENDIAN)?;
-
     let asm = include_str!("../tests/integration/uart.S");
 
-    let result = engine.asm(asm.to_string(), 0)?;
-    Ok(result.bytes)
+    Ok(assemble(asm, 0)?)
 }
 
 pub fn load_uboot() -> Result<Vec<u8>> {
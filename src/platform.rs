@@ -0,0 +1,239 @@
+use crate::devices::MmioDevice;
+use crate::devices::debug_port::DebugPort;
+use crate::devices::gpio::Pl061Gpio;
+use crate::devices::string_port::StringPort;
+use crate::devices::uart::{ConsoleHistory, ConsoleInput, Pl011Device};
+use crate::devices::virtio_console::VirtioConsole;
+use crate::err::{MemoryError, MmioError};
+use crate::vcpu::HVF_DEFAULT_IPA_BITS;
+use crate::{MmioManager, SharedMemory, SimppleError};
+use ahvf::{MemoryPermission, VirtualMachine};
+
+/// Devices that can be placed on the platform
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// PL011 UART writing to stdout
+    Pl011Uart,
+    /// PL061 GPIO controller
+    Pl061Gpio,
+    /// virtio-mmio console writing to stdout
+    VirtioConsole,
+    /// Debug port logging guest output through `log`
+    DebugPort,
+    /// Port logging whole guest messages copied out of guest memory
+    StringPort,
+}
+
+impl DeviceKind {
+    /// Instantiate the device
+    pub fn create(&self) -> Box<dyn MmioDevice> {
+        match self {
+            DeviceKind::Pl011Uart => Box::new(Pl011Device::stdout()),
+            DeviceKind::Pl061Gpio => Box::new(Pl061Gpio::default()),
+            DeviceKind::VirtioConsole => Box::new(VirtioConsole::stdout()),
+            DeviceKind::DebugPort => Box::new(DebugPort::new()),
+            DeviceKind::StringPort => Box::new(StringPort::new()),
+        }
+    }
+
+    /// Size of the MMIO window the device occupies
+    pub fn size(&self) -> u64 {
+        match self {
+            DeviceKind::Pl011Uart
+            | DeviceKind::Pl061Gpio
+            | DeviceKind::DebugPort
+            | DeviceKind::StringPort => 0x1000,
+            DeviceKind::VirtioConsole => 0x200,
+        }
+    }
+}
+
+/// A device mapped at a guest-physical address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DevicePlacement {
+    pub kind: DeviceKind,
+    pub base: u64,
+}
+
+/// Guest memory map: RAM regions and MMIO device placements
+///
+/// The default mirrors the layout U-Boot's QEMU `virt` build expects.
+#[derive(Clone, Debug)]
+pub struct PlatformConfig {
+    pub firmware_base: u64,
+    pub firmware_size: usize,
+    pub memory_base: u64,
+    pub memory_size: usize,
+    pub devices: Vec<DevicePlacement>,
+    /// Lines of PL011 output to keep for the debugger, 0 to disable
+    pub console_history: usize,
+    /// Host input for the first PL011, keep a clone to type into the guest
+    pub console_input: Option<ConsoleInput>,
+    /// Feed stdin to the first PL011 when there is no `console_input`
+    pub console_stdin: bool,
+    /// Handle for string port messages, keep a clone to read them
+    pub string_port: Option<StringPort>,
+    /// Guest-physical address bits of the VM, everything must be mapped below
+    pub ipa_bits: u8,
+}
+
+impl Default for PlatformConfig {
+    fn default() -> Self {
+        Self {
+            firmware_base: 0x0,
+            firmware_size: 128 * 1024 * 1024, // 128 MiB for firmware
+            memory_base: 0x40000000,
+            memory_size: 1024 * 1024 * 1024, // 1GiB of memory
+            devices: vec![
+                DevicePlacement {
+                    kind: DeviceKind::Pl011Uart,
+                    base: 0x9000000,
+                },
+                DevicePlacement {
+                    kind: DeviceKind::Pl061Gpio,
+                    base: 0x3fffe000,
+                },
+            ],
+            console_history: 0,
+            console_input: None,
+            console_stdin: false,
+            string_port: None,
+            ipa_bits: HVF_DEFAULT_IPA_BITS,
+        }
+    }
+}
+
+impl PlatformConfig {
+    /// RAM regions as (base, end)
+    fn memory_regions(&self) -> [(u64, u64); 2] {
+        [
+            (
+                self.firmware_base,
+                self.firmware_base + self.firmware_size as u64,
+            ),
+            (self.memory_base, self.memory_base + self.memory_size as u64),
+        ]
+    }
+
+    /// Check that no device window overlaps RAM.
+    ///
+    /// Overlaps among RAM regions or among devices are caught by
+    /// `SharedMemory` and `MmioManager` when the platform is built.
+    pub fn validate(&self) -> Result<(), SimppleError> {
+        let ipa_limit = 1u64 << self.ipa_bits;
+        let regions = self.memory_regions().into_iter().chain(
+            self.devices
+                .iter()
+                .map(|device| (device.base, device.base + device.kind.size())),
+        );
+        for (start, end) in regions {
+            if end > ipa_limit {
+                return Err(MemoryError::beyond_ipa_space(start, end, self.ipa_bits).into());
+            }
+        }
+
+        for device in &self.devices {
+            let device_range = (device.base, device.base + device.kind.size());
+            for memory_range in self.memory_regions() {
+                if device_range.0 < memory_range.1 && memory_range.0 < device_range.1 {
+                    return Err(MmioError::overlapping_region(memory_range, device_range).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A VM with its memory and devices set up
+pub struct Platform {
+    pub vm: VirtualMachine,
+    pub mmu: SharedMemory,
+    pub mmio: MmioManager,
+    /// Recent output of the first PL011, if `console_history` is enabled
+    pub console: Option<ConsoleHistory>,
+}
+
+/// Create a VM and lay out memory and devices as described by `config`
+pub fn build_vm(config: &PlatformConfig) -> Result<Platform, SimppleError> {
+    config.validate()?;
+
+    let mut vm = VirtualMachine::new(None)?;
+
+    let mut mmu = SharedMemory::default();
+    for (base, end) in config.memory_regions() {
+        mmu.add_segment(
+            &mut vm,
+            base,
+            (end - base) as usize,
+            MemoryPermission::READ_WRITE_EXECUTE,
+        )?;
+    }
+
+    let mut mmio = MmioManager::default();
+    let mut console = None;
+    let mut first_uart = true;
+    for device in &config.devices {
+        let instance: Box<dyn MmioDevice> = match device.kind {
+            DeviceKind::Pl011Uart if first_uart => {
+                first_uart = false;
+                let mut uart = if config.console_stdin && config.console_input.is_none() {
+                    Pl011Device::stdio()
+                } else {
+                    Pl011Device::stdout()
+                };
+                if config.console_history > 0 {
+                    console = Some(uart.enable_history(config.console_history));
+                }
+                if let Some(input) = &config.console_input {
+                    uart.attach_input(input.clone());
+                }
+                Box::new(uart)
+            }
+            DeviceKind::StringPort => Box::new(config.string_port.clone().unwrap_or_default()),
+            kind => kind.create(),
+        };
+        mmio.register_device(device.base, instance)?;
+    }
+
+    Ok(Platform {
+        vm,
+        mmu,
+        mmio,
+        console,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_layout_is_valid() {
+        assert!(PlatformConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_memory_beyond_ipa_rejected() {
+        let config = PlatformConfig {
+            memory_base: 0x10_0000_0000,
+            ..PlatformConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(SimppleError::Memory(MemoryError::BeyondIpaSpace {
+                ipa_bits: 36,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_device_inside_ram_rejected() {
+        let mut config = PlatformConfig::default();
+        config.devices.push(DevicePlacement {
+            kind: DeviceKind::Pl061Gpio,
+            base: config.memory_base + 0x1000,
+        });
+        assert!(config.validate().is_err());
+    }
+}
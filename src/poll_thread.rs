@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Helper thread that polls at a fixed interval until it is dropped.
+///
+/// Shared by the watchdog and the break request, which both watch some
+/// state from the side and kick the vCPU out of `run()` when needed.
+pub(crate) struct PollThread {
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PollThread {
+    /// Call `poll` every `interval` until it returns false or this is dropped
+    pub(crate) fn spawn(
+        interval: Duration,
+        mut poll: impl FnMut() -> bool + Send + 'static,
+    ) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let thread = thread::spawn(move || {
+            while !thread_stopped.load(Ordering::Acquire) {
+                thread::sleep(interval);
+                if !poll() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            stopped,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PollThread {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_stops_when_poll_returns_false() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let thread_calls = calls.clone();
+        let thread = PollThread::spawn(Duration::from_millis(1), move || {
+            thread_calls.fetch_add(1, Ordering::Relaxed) < 2
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        drop(thread);
+    }
+
+    #[test]
+    fn test_drop_stops_thread() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let thread_calls = calls.clone();
+        let thread = PollThread::spawn(Duration::from_millis(1), move || {
+            thread_calls.fetch_add(1, Ordering::Relaxed);
+            true
+        });
+
+        drop(thread);
+        let after_drop = calls.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(calls.load(Ordering::Relaxed), after_drop);
+    }
+}
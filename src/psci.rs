@@ -0,0 +1,87 @@
+//! Power State Coordination Interface calls the guest makes with `SMC #0`.
+//!
+//! X0 holds the function ID and X1 the first argument; the result goes back
+//! in X0. There is a single vCPU, so turning it off powers the system off.
+
+/// Return the implemented PSCI version
+pub const PSCI_VERSION: u32 = 0x8400_0000;
+/// Power down the calling core
+pub const PSCI_CPU_OFF: u32 = 0x8400_0002;
+/// Power the whole system off
+pub const PSCI_SYSTEM_OFF: u32 = 0x8400_0008;
+/// Reset the whole system, as on power-up
+pub const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+/// Whether the function ID in X1 is implemented
+pub const PSCI_FEATURES: u32 = 0x8400_000a;
+
+/// PSCI 1.0, the first version with PSCI_FEATURES
+const VERSION_1_0: u64 = 1 << 16;
+
+// Return codes, sign-extended into X0
+const SUCCESS: u64 = 0;
+const NOT_SUPPORTED: u64 = -1i64 as u64;
+
+/// What the run loop does for a PSCI call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PsciAction {
+    /// Write this to X0 and resume after the SMC
+    Return(u64),
+    /// The only vCPU turned itself off
+    CpuOff,
+    /// The guest powered the system off
+    SystemOff,
+    /// Reset the vCPU and devices and start over from the entry point
+    SystemReset,
+}
+
+/// Decode the call with `function_id` in X0 and `argument` in X1.
+///
+/// Only the low 32 bits of the function ID are used, all supported
+/// functions are SMC32 calls. Unknown functions return NOT_SUPPORTED.
+pub fn handle_call(function_id: u64, argument: u64) -> PsciAction {
+    match function_id as u32 {
+        PSCI_VERSION => PsciAction::Return(VERSION_1_0),
+        PSCI_CPU_OFF => PsciAction::CpuOff,
+        PSCI_SYSTEM_OFF => PsciAction::SystemOff,
+        PSCI_SYSTEM_RESET => PsciAction::SystemReset,
+        PSCI_FEATURES => match argument as u32 {
+            PSCI_VERSION | PSCI_CPU_OFF | PSCI_SYSTEM_OFF | PSCI_SYSTEM_RESET | PSCI_FEATURES => {
+                PsciAction::Return(SUCCESS)
+            }
+            _ => PsciAction::Return(NOT_SUPPORTED),
+        },
+        _ => PsciAction::Return(NOT_SUPPORTED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_call() {
+        assert_eq!(
+            handle_call(u64::from(PSCI_VERSION), 0),
+            PsciAction::Return(0x1_0000)
+        );
+        assert_eq!(
+            handle_call(u64::from(PSCI_SYSTEM_OFF), 0),
+            PsciAction::SystemOff
+        );
+        assert_eq!(
+            handle_call(u64::from(PSCI_SYSTEM_RESET), 0),
+            PsciAction::SystemReset
+        );
+        assert_eq!(
+            handle_call(u64::from(PSCI_FEATURES), u64::from(PSCI_CPU_OFF)),
+            PsciAction::Return(SUCCESS)
+        );
+
+        // CPU_ON is not implemented, there is no second core
+        assert_eq!(
+            handle_call(u64::from(PSCI_FEATURES), 0xc400_0003),
+            PsciAction::Return(u64::MAX)
+        );
+        assert_eq!(handle_call(0xc400_0003, 0), PsciAction::Return(u64::MAX));
+    }
+}
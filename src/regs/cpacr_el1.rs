@@ -0,0 +1,82 @@
+/// CPACR_EL1 - Architectural Feature Access Control Register
+use bitfield::bitfield;
+
+bitfield! {
+    /// CPACR_EL1 - Architectural Feature Access Control Register
+    ///
+    /// Controls whether EL1 and EL0 accesses to FP/SIMD, SVE and SME trap.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct CpacrEl1(u64);
+
+    /// Bit [28] - Trap trace register accesses
+    pub tta, set_tta: 28;
+
+    /// Bits [25:24] - SME trap control (FEAT_SME)
+    pub smen, set_smen: 25, 24;
+
+    /// Bits [21:20] - FP/SIMD trap control
+    pub fpen, set_fpen: 21, 20;
+
+    /// Bits [17:16] - SVE trap control (FEAT_SVE)
+    pub zen, set_zen: 17, 16;
+}
+
+impl CpacrEl1 {
+    /// Neither EL1 nor EL0 accesses trap
+    pub const TRAP_NONE: u64 = 0b11;
+
+    /// Only EL0 accesses trap
+    pub const TRAP_EL0: u64 = 0b01;
+
+    pub const fn from_raw(value: u64) -> Self {
+        Self(value)
+    }
+
+    pub const fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether FP/SIMD instructions run without trapping at `el`
+    pub fn fp_enabled(&self, el: u8) -> bool {
+        Self::enabled(self.fpen(), el)
+    }
+
+    /// Whether SVE instructions run without trapping at `el`.
+    ///
+    /// SVE also needs FP/SIMD, so FPEN must permit the access as well.
+    pub fn sve_enabled(&self, el: u8) -> bool {
+        Self::enabled(self.zen(), el) && self.fp_enabled(el)
+    }
+
+    // 0b00 and 0b10 trap EL1 and EL0, 0b01 traps EL0 only
+    fn enabled(field: u64, el: u8) -> bool {
+        match field {
+            Self::TRAP_NONE => true,
+            Self::TRAP_EL0 => el > 0,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fp_trap_levels() {
+        let mut cpacr = CpacrEl1::from_raw(0);
+        assert!(!cpacr.fp_enabled(1));
+        assert!(!cpacr.fp_enabled(0));
+
+        cpacr.set_fpen(CpacrEl1::TRAP_EL0);
+        assert!(cpacr.fp_enabled(1));
+        assert!(!cpacr.fp_enabled(0));
+
+        // SVE is only usable when FP/SIMD is too
+        cpacr.set_zen(CpacrEl1::TRAP_NONE);
+        assert!(cpacr.sve_enabled(1));
+        cpacr.set_fpen(0b10);
+        assert!(!cpacr.sve_enabled(1));
+        assert_eq!(cpacr.raw(), 0b10_00_11 << 16);
+    }
+}
@@ -1,4 +1,6 @@
 /// ESR_EL2 - Exception Syndrome Register (Exception Level 2)
+use crate::SimppleError;
+use crate::regs::iss::DataAbortISS2;
 use bitfield::bitfield;
 
 /// Exception Class values for ESR_EL2
@@ -103,6 +105,74 @@ pub enum ExceptionClass {
     Unrecognized(u8),
 }
 
+impl ExceptionClass {
+    /// The 6-bit EC encoding, also for `Unrecognized` classes
+    pub fn raw(&self) -> u8 {
+        match self {
+            ExceptionClass::Unknown => 0b000000,
+            ExceptionClass::TrappedWfInstruction => 0b000001,
+            ExceptionClass::TrappedMcrMrcCp15 => 0b000011,
+            ExceptionClass::TrappedMcrrMrrcCp15 => 0b000100,
+            ExceptionClass::TrappedMcrMrcCp14 => 0b000101,
+            ExceptionClass::TrappedLdcStc => 0b000110,
+            ExceptionClass::TrappedSimdFp => 0b000111,
+            ExceptionClass::TrappedVmrs => 0b001000,
+            ExceptionClass::TrappedPointerAuth => 0b001001,
+            ExceptionClass::TrappedOtherInstruction => 0b001010,
+            ExceptionClass::TrappedMrrcCp14 => 0b001100,
+            ExceptionClass::BranchTargetException => 0b001101,
+            ExceptionClass::IllegalExecutionState => 0b001110,
+            ExceptionClass::SvcAArch32 => 0b010001,
+            ExceptionClass::HvcAArch32 => 0b010010,
+            ExceptionClass::SmcAArch32 => 0b010011,
+            ExceptionClass::TrappedSysregAArch64_128bit => 0b010100,
+            ExceptionClass::SvcAArch64 => 0b010101,
+            ExceptionClass::HvcAArch64 => 0b010110,
+            ExceptionClass::SmcAArch64 => 0b010111,
+            ExceptionClass::TrappedSysregAArch64 => 0b011000,
+            ExceptionClass::TrappedSve => 0b011001,
+            ExceptionClass::TrappedEret => 0b011010,
+            ExceptionClass::TrappedTstart => 0b011011,
+            ExceptionClass::PacFail => 0b011100,
+            ExceptionClass::TrappedSme => 0b011101,
+            ExceptionClass::InstructionAbortLowerEl => 0b100000,
+            ExceptionClass::InstructionAbortSameEl => 0b100001,
+            ExceptionClass::PcAlignmentFault => 0b100010,
+            ExceptionClass::DataAbortLowerEl => 0b100100,
+            ExceptionClass::DataAbortSameEl => 0b100101,
+            ExceptionClass::SpAlignmentFault => 0b100110,
+            ExceptionClass::MemoryOperation => 0b100111,
+            ExceptionClass::TrappedFpAArch32 => 0b101000,
+            ExceptionClass::TrappedFpAArch64 => 0b101100,
+            ExceptionClass::GcsException => 0b101101,
+            ExceptionClass::SError => 0b101111,
+            ExceptionClass::BreakpointLowerEl => 0b110000,
+            ExceptionClass::BreakpointSameEl => 0b110001,
+            ExceptionClass::SoftwareStepLowerEl => 0b110010,
+            ExceptionClass::SoftwareStepSameEl => 0b110011,
+            ExceptionClass::WatchpointLowerEl => 0b110100,
+            ExceptionClass::WatchpointSameEl => 0b110101,
+            ExceptionClass::BkptAArch32 => 0b111000,
+            ExceptionClass::VectorCatchAArch32 => 0b111010,
+            ExceptionClass::BrkAArch64 => 0b111100,
+            ExceptionClass::ProfilingException => 0b111101,
+            ExceptionClass::Unrecognized(value) => *value,
+        }
+    }
+}
+
+impl TryFrom<u64> for ExceptionClass {
+    type Error = SimppleError;
+
+    /// Decode an EC value, which must fit in 6 bits
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match u8::try_from(value) {
+            Ok(ec) if ec < 0b100_0000 => Ok(ExceptionClass::from(ec)),
+            _ => Err(SimppleError::InvalidExceptionClass(value)),
+        }
+    }
+}
+
 impl From<u8> for ExceptionClass {
     fn from(value: u8) -> Self {
         match value {
@@ -162,12 +232,21 @@ bitfield! {
     /// ESR_EL2 - Exception Syndrome Register (Exception Level 2)
     ///
     /// This Register holds syndrome information for an exception taken to EL2.
+    ///
+    /// The raw setters do not check that the ISS layout matches the EC. When
+    /// building a syndrome to inject, prefer `EsrEl2::validated` or check the
+    /// result with `is_consistent`.
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct EsrEl2(u64);
 
     // Bits [63:56] - Reserved, RES0
 
     /// Bit [55:32] - ISS2
+    ///
+    /// Only a few exception classes populate ISS2: data aborts (FEAT_LS64,
+    /// FEAT_GCS, FEAT_MTE, overlay and dirty-state faults), instruction aborts
+    /// (overlay faults) and 128-bit system register traps (Rt2). Memory
+    /// operation (MOPS) exceptions carry their whole syndrome in ISS.
     pub iss2, set_iss2: 55, 32;
 
     /// Bit [31:26] - EC
@@ -200,6 +279,98 @@ impl EsrEl2 {
     pub fn exception_class(&self) -> ExceptionClass {
         ExceptionClass::from(self.ec() as u8)
     }
+
+    /// Size in bytes of the trapped instruction: 4 when IL is set, 2 for a
+    /// 16-bit T32 instruction
+    pub fn instruction_length(&self) -> u64 {
+        if self.il() { 4 } else { 2 }
+    }
+
+    /// Build a syndrome, rejecting ISS values that don't fit the EC
+    pub fn validated(ec: ExceptionClass, il: bool, iss: u32) -> Result<Self, SimppleError> {
+        let mut esr = Self::new();
+        esr.set_ec(u64::from(ec.raw()));
+        esr.set_il(il);
+        esr.set_iss(u64::from(iss));
+        if u64::from(iss) != esr.iss() {
+            return Err(SimppleError::invalid_syndrome(
+                esr.raw(),
+                format!("ISS 0x{iss:x} does not fit in 25 bits"),
+            ));
+        }
+        esr.check_consistency()?;
+        Ok(esr)
+    }
+
+    /// Whether the ISS and ISS2 layouts make sense for the EC
+    pub fn is_consistent(&self) -> bool {
+        self.check_consistency().is_ok()
+    }
+
+    /// Check the ISS and ISS2 against the EC, reporting the first mismatch.
+    ///
+    /// Only the RES0 fields of the exception classes the emulator decodes or
+    /// injects are checked, so passing does not mean every field is sensible.
+    pub fn check_consistency(&self) -> Result<(), SimppleError> {
+        let iss = self.iss();
+        let reject = |reason: &str| Err(SimppleError::invalid_syndrome(self.raw(), reason));
+
+        let iss2_allowed = matches!(
+            self.exception_class(),
+            ExceptionClass::DataAbortLowerEl
+                | ExceptionClass::DataAbortSameEl
+                | ExceptionClass::InstructionAbortLowerEl
+                | ExceptionClass::InstructionAbortSameEl
+                | ExceptionClass::TrappedSysregAArch64_128bit
+        );
+        if self.iss2() != 0 && !iss2_allowed {
+            return reject("ISS2 is only used by aborts and 128-bit system register traps");
+        }
+
+        // Without ISV, SAS/SSE/SRT/SF/AR (bits [23:14]) of a data abort carry no information
+        let isv = iss & (1 << 24) != 0;
+
+        match self.exception_class() {
+            ExceptionClass::Unrecognized(ec) => {
+                reject(&format!("unallocated exception class 0b{ec:06b}"))
+            }
+            ExceptionClass::Unknown
+            | ExceptionClass::IllegalExecutionState
+            | ExceptionClass::PcAlignmentFault
+            | ExceptionClass::SpAlignmentFault
+                if iss != 0 =>
+            {
+                reject("ISS is RES0 for this exception class")
+            }
+            ExceptionClass::DataAbortLowerEl | ExceptionClass::DataAbortSameEl
+                if !isv && iss & 0x00ff_c000 != 0 =>
+            {
+                reject("access fields are set but ISV is clear")
+            }
+            ExceptionClass::TrappedSysregAArch64 if iss & 0x01c0_0000 != 0 => {
+                reject("bits [24:22] of a system register trap ISS are RES0")
+            }
+            ExceptionClass::SvcAArch64
+            | ExceptionClass::HvcAArch64
+            | ExceptionClass::SmcAArch64
+            | ExceptionClass::BrkAArch64
+                if iss & 0x01ff_0000 != 0 =>
+            {
+                reject("only the 16-bit immediate is valid in this ISS")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode ISS2 for data aborts, `None` for any other exception class
+    pub fn data_abort_iss2(&self) -> Option<DataAbortISS2> {
+        match self.exception_class() {
+            ExceptionClass::DataAbortLowerEl | ExceptionClass::DataAbortSameEl => {
+                Some(DataAbortISS2::from_raw(self.iss2() as u32))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Default for EsrEl2 {
@@ -217,14 +388,16 @@ pub enum SyndromeAccessSize {
     DoubleWord = 0b11,
 }
 
-impl From<u8> for SyndromeAccessSize {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for SyndromeAccessSize {
+    type Error = SimppleError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0b00 => SyndromeAccessSize::Byte,
-            0b01 => SyndromeAccessSize::Halfword,
-            0b10 => SyndromeAccessSize::Word,
-            0b11 => SyndromeAccessSize::DoubleWord,
-            _ => panic!("Invalid access size value"),
+            0b00 => Ok(SyndromeAccessSize::Byte),
+            0b01 => Ok(SyndromeAccessSize::Halfword),
+            0b10 => Ok(SyndromeAccessSize::Word),
+            0b11 => Ok(SyndromeAccessSize::DoubleWord),
+            _ => Err(SimppleError::InvalidAccessSize(value)),
         }
     }
 }
@@ -239,3 +412,79 @@ impl From<SyndromeAccessSize> for usize {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exception_class_raw_round_trip() {
+        for code in 0..64u8 {
+            assert_eq!(ExceptionClass::from(code).raw(), code);
+        }
+
+        for class in [
+            ExceptionClass::Unknown,
+            ExceptionClass::DataAbortLowerEl,
+            ExceptionClass::BrkAArch64,
+            ExceptionClass::Unrecognized(0b000010),
+        ] {
+            assert_eq!(ExceptionClass::from(class.raw()).raw(), class.raw());
+            assert_eq!(
+                ExceptionClass::try_from(u64::from(class.raw())).unwrap(),
+                class
+            );
+        }
+        assert!(matches!(
+            ExceptionClass::try_from(0x40u64),
+            Err(SimppleError::InvalidExceptionClass(0x40))
+        ));
+    }
+
+    #[test]
+    fn test_access_size_out_of_range() {
+        assert_eq!(
+            SyndromeAccessSize::try_from(0b11).unwrap(),
+            SyndromeAccessSize::DoubleWord
+        );
+        assert!(matches!(
+            SyndromeAccessSize::try_from(4),
+            Err(SimppleError::InvalidAccessSize(4))
+        ));
+    }
+
+    #[test]
+    fn test_consistency() {
+        // Data abort with ISV and a word-sized write from X1
+        let esr = EsrEl2::validated(ExceptionClass::DataAbortLowerEl, true, 0x0188_0040).unwrap();
+        assert!(esr.is_consistent());
+
+        // Access size set without ISV
+        assert!(EsrEl2::validated(ExceptionClass::DataAbortLowerEl, true, 0x0080_0040).is_err());
+
+        // Sysreg trap with bits [24:22] set, e.g. a data abort ISS under the wrong EC
+        assert!(
+            EsrEl2::validated(ExceptionClass::TrappedSysregAArch64, true, 0x0188_0040).is_err()
+        );
+
+        // BRK #0x10 is fine, garbage above the immediate is not
+        assert!(EsrEl2::validated(ExceptionClass::BrkAArch64, true, 0x10).is_ok());
+        assert!(EsrEl2::validated(ExceptionClass::BrkAArch64, true, 0x1_0010).is_err());
+
+        // ISS2 on a sysreg trap
+        let mut esr = EsrEl2::new();
+        esr.set_ec(0b011000);
+        esr.set_iss2(1);
+        assert!(!esr.is_consistent());
+
+        assert!(!EsrEl2::from_raw(0b111111 << 26).is_consistent());
+    }
+
+    #[test]
+    fn test_instruction_length() {
+        let mut esr = EsrEl2::from_raw(0x9200_0046);
+        assert_eq!(esr.instruction_length(), 4);
+        esr.set_il(false);
+        assert_eq!(esr.instruction_length(), 2);
+    }
+}
@@ -0,0 +1,92 @@
+/// Decoded context of a guest exception
+use crate::regs::{EsrEl2, ExceptionClass};
+use ahvf::{VirtualCpuExitException, VirtualCpuExitReason};
+use std::fmt;
+
+/// Everything known about one exception exit, decoded once when it's taken.
+///
+/// Handlers pass this around instead of re-decoding the raw syndrome, so the
+/// logger, debugger and run loop all agree on what happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExceptionInfo {
+    /// PC at the exit, the faulting instruction for synchronous exceptions
+    pub pc: u64,
+    pub esr: EsrEl2,
+    pub class: ExceptionClass,
+    /// ISS field of `esr`, for the class-specific ISS decoders
+    pub iss_raw: u32,
+    /// FAR, only valid for aborts, watchpoints and PC alignment faults
+    pub fault_va: u64,
+    /// IPA of the faulting access, only valid for stage 2 aborts
+    pub fault_pa: u64,
+}
+
+impl ExceptionInfo {
+    pub fn new(pc: u64, exception: &VirtualCpuExitException) -> Self {
+        let esr = EsrEl2::from_raw(exception.syndrome);
+        Self {
+            pc,
+            esr,
+            class: esr.exception_class(),
+            iss_raw: esr.iss() as u32,
+            fault_va: exception.virtual_address,
+            fault_pa: exception.physical_address,
+        }
+    }
+
+    /// Decode `exit` if it is an exception, with `pc` read after the exit
+    pub fn from_exit(pc: u64, exit: &VirtualCpuExitReason) -> Option<Self> {
+        match exit {
+            VirtualCpuExitReason::Exception { exception } => Some(Self::new(pc, exception)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExceptionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} (EC = {:#04x}, ISS = {:#x}) at {:#x}, FAR = {:#x}, IPA = {:#x}",
+            self.class,
+            self.class.raw(),
+            self.iss_raw,
+            self.pc,
+            self.fault_va,
+            self.fault_pa
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_data_abort_exit() {
+        // Data abort from a lower EL, 32-bit write through X1
+        let exit = VirtualCpuExitReason::Exception {
+            exception: VirtualCpuExitException {
+                syndrome: (0x24 << 26)
+                    | (1 << 25)
+                    | (1 << 24)
+                    | (0b10 << 22)
+                    | (1 << 16)
+                    | (1 << 6),
+                virtual_address: 0xffff_0000_0900_0000,
+                physical_address: 0x0900_0000,
+            },
+        };
+        let info = ExceptionInfo::from_exit(0x6000_0000, &exit).unwrap();
+        assert_eq!(info.class, ExceptionClass::DataAbortLowerEl);
+        assert_eq!(info.iss_raw, info.esr.iss() as u32);
+        assert_eq!(info.fault_pa, 0x0900_0000);
+        assert_eq!(info.fault_va, 0xffff_0000_0900_0000);
+        assert_eq!(info.pc, 0x6000_0000);
+
+        assert_eq!(
+            ExceptionInfo::from_exit(0, &VirtualCpuExitReason::Cancelled),
+            None
+        );
+    }
+}
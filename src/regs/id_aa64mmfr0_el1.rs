@@ -0,0 +1,87 @@
+/// ID_AA64MMFR0_EL1 - AArch64 Memory Model Feature Register 0
+use crate::SimppleError;
+use bitfield::bitfield;
+
+// Physical address sizes by PARange encoding
+const PA_RANGE_BITS: [u8; 8] = [32, 36, 40, 42, 44, 48, 52, 56];
+
+bitfield! {
+    /// ID_AA64MMFR0_EL1 - AArch64 Memory Model Feature Register 0
+    ///
+    /// Only PARange is decoded. The guest sizes its stage 1 output addresses
+    /// from it, so it must not promise more bits than the VM's IPA space.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct IdAa64Mmfr0El1(u64);
+
+    /// Bits [3:0] - Physical address range supported
+    pub pa_range, set_pa_range: 3, 0;
+}
+
+impl IdAa64Mmfr0El1 {
+    pub const fn from_raw(value: u64) -> Self {
+        Self(value)
+    }
+
+    pub const fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Physical address bits advertised, None for a reserved encoding
+    pub fn pa_bits(&self) -> Option<u8> {
+        PA_RANGE_BITS.get(self.pa_range() as usize).copied()
+    }
+
+    /// Largest PARange encoding that fits in `ipa_bits`, None below 32 bits
+    pub fn pa_range_for(ipa_bits: u8) -> Option<u64> {
+        PA_RANGE_BITS
+            .iter()
+            .rposition(|&bits| bits <= ipa_bits)
+            .map(|encoding| encoding as u64)
+    }
+
+    /// Lower PARange to fit in `ipa_bits`, never raising it
+    pub fn limit_pa_range(&mut self, ipa_bits: u8) {
+        if let Some(limit) = Self::pa_range_for(ipa_bits)
+            && self.pa_bits().is_none_or(|bits| bits > ipa_bits)
+        {
+            self.set_pa_range(limit);
+        }
+    }
+
+    /// Check the advertised physical address size fits in `ipa_bits`
+    pub fn check_pa_range(&self, ipa_bits: u8) -> Result<(), SimppleError> {
+        match self.pa_bits() {
+            Some(bits) if bits <= ipa_bits => Ok(()),
+            advertised => Err(SimppleError::PaRangeExceedsIpa {
+                pa_range: self.pa_range() as u8,
+                advertised,
+                ipa_bits,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pa_range_limited_to_ipa() {
+        // 48-bit PA on a 36-bit IPA VM
+        let mut mmfr0 = IdAa64Mmfr0El1::from_raw(0x1122_0005);
+        assert!(mmfr0.check_pa_range(36).is_err());
+        mmfr0.limit_pa_range(36);
+        assert_eq!(mmfr0.pa_bits(), Some(36));
+        assert_eq!(mmfr0.raw(), 0x1122_0001);
+        assert!(mmfr0.check_pa_range(36).is_ok());
+
+        // Between two encodings the smaller one is used, smaller values stay
+        assert_eq!(IdAa64Mmfr0El1::pa_range_for(41), Some(0b0010));
+        assert_eq!(IdAa64Mmfr0El1::pa_range_for(31), None);
+        mmfr0.limit_pa_range(40);
+        assert_eq!(mmfr0.pa_bits(), Some(36));
+
+        let reserved = IdAa64Mmfr0El1::from_raw(0xf);
+        assert!(reserved.check_pa_range(56).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::regs::VRegister;
+use ahvf::*;
+use bitfield::bitfield;
+
+bitfield! {
+    /// ISS for trapped AArch32 MCR/MRC accesses (EC 0b000011 and 0b000101)
+    ///
+    /// The layout differs from `SysRegAbortISS`: AArch32 encodings carry a
+    /// condition code and have no Op0 field.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct CoprocRegAbortISS(u32);
+
+    /// Bits [24] - Condition code valid
+    cv, set_cv: 24;
+
+    /// Bits [23:20] - Condition code
+    cond, set_cond: 23, 20;
+
+    /// Bits [19:17] - Opc2
+    opc2, set_opc2: 19, 17;
+
+    /// Bits [16:14] - Opc1
+    opc1, set_opc1: 16, 14;
+
+    /// Bits [13:10] - CRn
+    crn, set_crn: 13, 10;
+
+    /// Bits [9:5] - Rt
+    rt, set_rt: 9, 5;
+
+    /// Bits [4:1] - CRm
+    crm, set_crm: 4, 1;
+
+    /// Bits [0] - Direction
+    direction, set_direction: 0;
+}
+
+/// The coprocessor targeted by a trapped AArch32 MCR/MRC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coprocessor {
+    Cp14,
+    Cp15,
+}
+
+impl CoprocRegAbortISS {
+    /// Create a new ISS with all fields cleared
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Create ISS from raw u32 value
+    pub const fn from_raw(value: u32) -> Self {
+        Self(value)
+    }
+
+    /// Get raw u32 value
+    pub const fn raw(&self) -> u32 {
+        self.0
+    }
+
+    /// Get the access direction (read/write)
+    pub fn is_write(&self) -> bool {
+        // 0b0: write (MCR)
+        // 0b1: read (MRC)
+        !self.direction()
+    }
+
+    /// Get the condition code, if the hardware reported a valid one
+    pub fn condition(&self) -> Option<u8> {
+        self.cv().then(|| self.cond() as u8)
+    }
+
+    /// AArch32 R0-R14 map onto the low halves of X0-X14.
+    ///
+    /// `None` for Rt = 15, an MRC into APSR_nzcv, which isn't emulated.
+    pub fn access_register(&self) -> Option<VRegister> {
+        let register = match self.rt() {
+            0b0000 => VRegister::Register(Register::X0),
+            0b0001 => VRegister::Register(Register::X1),
+            0b0010 => VRegister::Register(Register::X2),
+            0b0011 => VRegister::Register(Register::X3),
+            0b0100 => VRegister::Register(Register::X4),
+            0b0101 => VRegister::Register(Register::X5),
+            0b0110 => VRegister::Register(Register::X6),
+            0b0111 => VRegister::Register(Register::X7),
+            0b1000 => VRegister::Register(Register::X8),
+            0b1001 => VRegister::Register(Register::X9),
+            0b1010 => VRegister::Register(Register::X10),
+            0b1011 => VRegister::Register(Register::X11),
+            0b1100 => VRegister::Register(Register::X12),
+            0b1101 => VRegister::Register(Register::X13),
+            0b1110 => VRegister::Register(Register::X14),
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    /// Describe the access in assembler syntax, e.g. `mrc p15, 0, r0, c1, c0, 0`
+    pub fn describe(&self, coproc: Coprocessor) -> String {
+        let mnemonic = if self.is_write() { "mcr" } else { "mrc" };
+        let coproc = match coproc {
+            Coprocessor::Cp14 => "p14",
+            Coprocessor::Cp15 => "p15",
+        };
+        format!(
+            "{mnemonic} {coproc}, {}, r{}, c{}, c{}, {}",
+            self.opc1(),
+            self.rt(),
+            self.crn(),
+            self.crm(),
+            self.opc2()
+        )
+    }
+}
+
+impl Default for CoprocRegAbortISS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_register() {
+        // mrc p15, 0, r2, c1, c0, 0
+        let mut iss = CoprocRegAbortISS::new();
+        iss.set_direction(true);
+        iss.set_crn(1);
+        iss.set_rt(2);
+        assert!(matches!(
+            iss.access_register(),
+            Some(VRegister::Register(Register::X2))
+        ));
+
+        // mrc p14, 0, APSR_nzcv, c0, c1, 0
+        iss.set_rt(15);
+        assert!(iss.access_register().is_none());
+        assert_eq!(
+            iss.describe(Coprocessor::Cp14),
+            "mrc p14, 0, r15, c1, c0, 0"
+        );
+    }
+}
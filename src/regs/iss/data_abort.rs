@@ -1,5 +1,8 @@
-use crate::regs::{SyndromeAccessSize, VRegister};
-use ahvf::*;
+use crate::SimppleError;
+use crate::regs::{
+    GP_REGISTERS, SyndromeAccessSize, VRegister, get_stack_pointer, set_stack_pointer,
+};
+use ahvf::{Register, VirtualCpu};
 use bitfield::bitfield;
 
 bitfield! {
@@ -50,15 +53,110 @@ impl DataAbortISS {
         self.0
     }
 
+    /// Decode a single-register load or store into the ISS it would carry
+    /// with ISV set. Pairs, exclusives and FP/SIMD accesses are `None`.
+    pub fn from_instruction(insn: u32) -> Option<Self> {
+        // size 111 V=0 0 x opc ..., V=0 rules out FP/SIMD registers
+        let size = insn >> 30;
+        let opc = (insn >> 22) & 0b11;
+        let register_or_indexed = insn & 0x3f00_0000 == 0x3800_0000;
+        let unsigned_offset = insn & 0x3f00_0000 == 0x3900_0000;
+        if !register_or_indexed && !unsigned_offset {
+            return None;
+        }
+        if register_or_indexed && insn & (1 << 21) != 0 && (insn >> 10) & 0b11 != 0b10 {
+            // Atomic memory operations share this space
+            return None;
+        }
+        // PRFM and the unallocated sign-extending loads of 32 and 64-bit values
+        if (size == 0b11 && opc >= 0b10) || (size == 0b10 && opc == 0b11) {
+            return None;
+        }
+
+        let mut iss = Self::new();
+        iss.set_isv(true);
+        iss.set_sas(size);
+        iss.set_sse(opc >= 0b10);
+        iss.set_srt(insn & 0b11111);
+        iss.set_sf(size == 0b11 || opc == 0b10);
+        iss.set_wnr(opc == 0b00);
+        Some(iss)
+    }
+
     /// Get the access size
-    pub fn access_size(&self) -> SyndromeAccessSize {
-        SyndromeAccessSize::from(self.sas() as u8)
+    pub fn access_size(&self) -> Result<SyndromeAccessSize, SimppleError> {
+        SyndromeAccessSize::try_from(self.sas() as u8)
     }
 
     pub fn is_write(&self) -> bool {
         self.wnr()
     }
 
+    /// Whether ISV is set, i.e. bits [23:14] hold a valid instruction syndrome
+    pub fn is_valid(&self) -> bool {
+        self.isv()
+    }
+
+    /// Whether the transfer register is 64 bits wide (Xn rather than Wn)
+    pub fn is_64bit(&self) -> bool {
+        self.sf()
+    }
+
+    /// Whether a load sign-extends the loaded value
+    pub fn is_sign_extended(&self) -> bool {
+        self.sse()
+    }
+
+    /// Widen a loaded value to the transfer register, as the load would.
+    ///
+    /// The low `access_size()` bytes of `raw_value` are sign-extended if SSE
+    /// is set, zero-extended otherwise, and the result is cut to 32 bits for
+    /// a Wn transfer register.
+    pub fn extend(&self, raw_value: u64) -> u64 {
+        let shift = 64 - (8 << self.sas());
+        let value = if self.is_sign_extended() {
+            (((raw_value << shift) as i64) >> shift) as u64
+        } else {
+            (raw_value << shift) >> shift
+        };
+        if self.is_64bit() {
+            value
+        } else {
+            value & 0xffff_ffff
+        }
+    }
+
+    /// Human readable summary of the access, e.g. `64-bit write of x3, size 4, to 0x9000000`
+    pub fn describe(&self, address: u64) -> String {
+        let direction = if self.is_write() { "write" } else { "read" };
+        let preposition = if self.is_write() { "to" } else { "from" };
+        if !self.is_valid() {
+            return format!("{direction} {preposition} {address:#x} (no instruction syndrome)");
+        }
+
+        let (width, prefix) = if self.is_64bit() {
+            (64, 'x')
+        } else {
+            (32, 'w')
+        };
+        let register = match self.srt() {
+            31 => format!("{prefix}zr"),
+            srt => format!("{prefix}{srt}"),
+        };
+        let size = match self.access_size() {
+            Ok(size) => usize::from(size).to_string(),
+            Err(_) => "?".to_string(),
+        };
+        let extend = if self.is_sign_extended() {
+            ", sign-extended"
+        } else {
+            ""
+        };
+        format!(
+            "{width}-bit {direction} of {register}, size {size}{extend}, {preposition} {address:#x}"
+        )
+    }
+
     pub fn access_register(&self) -> VRegister {
         match self.srt() {
             0b00000 => VRegister::Register(Register::X0),
@@ -103,3 +201,188 @@ impl Default for DataAbortISS {
         Self::new()
     }
 }
+
+/// Base register update of a pre- or post-indexed load or store.
+///
+/// These never carry a valid ISS, the emulator has to update the base itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Writeback {
+    /// Rn, where 31 is the current stack pointer
+    pub base: u32,
+    pub offset: i64,
+}
+
+impl Writeback {
+    /// The writeback of a single-register load or store, `None` if it has none
+    pub fn from_instruction(insn: u32) -> Option<Self> {
+        // size 111 0 00 opc 0 imm9 idx Rn Rt, idx is 01 for post- and 11 for pre-index
+        if insn & 0x3f20_0000 != 0x3800_0000 || (insn >> 10) & 0b01 == 0 {
+            return None;
+        }
+        DataAbortISS::from_instruction(insn)?;
+        Some(Self {
+            base: (insn >> 5) & 0b11111,
+            offset: i64::from(((insn >> 12) as i32) << 23 >> 23),
+        })
+    }
+
+    /// Add the offset to the base register
+    pub fn apply(&self, vcpu: &mut VirtualCpu) -> Result<(), SimppleError> {
+        if self.base == 31 {
+            let sp = get_stack_pointer(vcpu)?;
+            set_stack_pointer(vcpu, sp.wrapping_add_signed(self.offset))?;
+        } else {
+            let register = GP_REGISTERS[self.base as usize];
+            let value = vcpu.get_register(register)?;
+            vcpu.set_register(register, value.wrapping_add_signed(self.offset))?;
+        }
+        Ok(())
+    }
+}
+
+bitfield! {
+    /// ISS2 for data aborts - ESR_EL2 bits [55:32]
+    ///
+    /// Only populated when the corresponding features are implemented,
+    /// otherwise it reads as zero.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct DataAbortISS2(u32);
+
+    // Bits [23:11] - Reserved, RES0
+
+    /// Bits [10] - Tag not Data, the fault was on an MTE tag access (FEAT_MTE_STORE_ONLY)
+    pub tnd, set_tnd: 10;
+
+    /// Bits [9] - Tag access, the fault was on an MTE tag access (FEAT_MTE_PERM)
+    pub tag_access, set_tag_access: 9;
+
+    /// Bits [8] - Guarded Control Stack data access (FEAT_GCS)
+    pub gcs, set_gcs: 8;
+
+    /// Bits [7] - AssuredOnly check failed (FEAT_THE)
+    pub assured_only, set_assured_only: 7;
+
+    /// Bits [6] - Permission Overlay fault (FEAT_S1POE/FEAT_S2POE)
+    pub overlay, set_overlay: 6;
+
+    /// Bits [5] - Dirty state fault (FEAT_S2PIE)
+    pub dirty_bit, set_dirty_bit: 5;
+
+    /// Bits [4:0] - Status register of a faulting ST64BV/ST64BV0 (FEAT_LS64_V)
+    pub xs, set_xs: 4, 0;
+}
+
+impl DataAbortISS2 {
+    /// Create a new ISS2 with all fields cleared
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Create ISS2 from raw u32 value
+    pub const fn from_raw(value: u32) -> Self {
+        Self(value)
+    }
+
+    /// Get raw u32 value
+    pub const fn raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for DataAbortISS2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_known_iss() {
+        // str w3, [x1] - ISV, SAS=word, SRT=3, WnR
+        let iss = DataAbortISS::from_raw(0x0183_0040);
+        assert!(iss.is_valid());
+        assert!(!iss.is_64bit());
+        assert!(!iss.is_sign_extended());
+        assert_eq!(
+            iss.describe(0x900_0000),
+            "32-bit write of w3, size 4, to 0x9000000"
+        );
+
+        // ldrsh x5, [x1] - ISV, SAS=halfword, SSE, SRT=5, SF
+        let iss = DataAbortISS::from_raw(0x0165_8000);
+        assert_eq!(
+            iss.describe(0x900_0018),
+            "64-bit read of x5, size 2, sign-extended, from 0x9000018"
+        );
+
+        assert_eq!(
+            DataAbortISS::from_raw(0x40).describe(0x1000),
+            "write to 0x1000 (no instruction syndrome)"
+        );
+    }
+
+    #[test]
+    fn test_from_instruction() {
+        // ldrsb w3, [x1], #1 - post-indexed, so the abort has no ISS
+        let insn = 0x38c0_1423;
+        let iss = DataAbortISS::from_instruction(insn).unwrap();
+        assert_eq!(
+            iss.describe(0x900_0000),
+            "32-bit read of w3, size 1, sign-extended, from 0x9000000"
+        );
+        assert_eq!(
+            Writeback::from_instruction(insn),
+            Some(Writeback { base: 1, offset: 1 })
+        );
+
+        // str x5, [sp, #-16]!
+        let insn = 0xf81f_0fe5;
+        assert_eq!(
+            DataAbortISS::from_instruction(insn)
+                .unwrap()
+                .describe(0x900_0000),
+            "64-bit write of x5, size 8, to 0x9000000"
+        );
+        assert_eq!(
+            Writeback::from_instruction(insn),
+            Some(Writeback {
+                base: 31,
+                offset: -16
+            })
+        );
+
+        // ldr w0, [x1, #4] has no writeback
+        assert!(DataAbortISS::from_instruction(0xb940_0420).is_some());
+        assert_eq!(Writeback::from_instruction(0xb940_0420), None);
+
+        // Neither do register offsets: ldr x0, [x1, x2]
+        assert!(DataAbortISS::from_instruction(0xf862_6820).is_some());
+        assert_eq!(Writeback::from_instruction(0xf862_6820), None);
+
+        // ldp x0, x1, [x2], ldadd w0, w1, [x2] and ldr q0, [x1] can't be described
+        assert!(DataAbortISS::from_instruction(0xa940_0440).is_none());
+        assert!(DataAbortISS::from_instruction(0xb820_0041).is_none());
+        assert!(DataAbortISS::from_instruction(0x3dc0_0020).is_none());
+    }
+
+    #[test]
+    fn test_extend() {
+        // ldrsb x0 and ldrsb w0
+        let mut iss = DataAbortISS::from_raw(0x0120_8000);
+        assert_eq!(iss.extend(0xff), 0xffff_ffff_ffff_ffff);
+        iss.set_sf(false);
+        assert_eq!(iss.extend(0xff), 0xffff_ffff);
+        assert_eq!(iss.extend(0x7f), 0x7f);
+
+        // ldrh w0 ignores anything the device returned above the halfword
+        let iss = DataAbortISS::from_raw(0x0140_0000);
+        assert_eq!(iss.extend(0x1234_8001), 0x8001);
+
+        // ldr x0 is passed through
+        let iss = DataAbortISS::from_raw(0x01c0_8000);
+        assert_eq!(iss.extend(u64::MAX), u64::MAX);
+    }
+}
@@ -0,0 +1,70 @@
+use bitfield::bitfield;
+
+bitfield! {
+    /// ISS for trapped ERET, ERETAA and ERETAB (EC 0b011010)
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct EretISS(u32);
+
+    // Bits [24:2] - Reserved, RES0
+
+    /// Bits [1] - ERET, set for the authenticating ERETAA/ERETAB
+    eret, set_eret: 1;
+
+    /// Bits [0] - ERETA, selects ERETAB over ERETAA when bit [1] is set
+    ereta, set_ereta: 0;
+}
+
+/// The exception return instruction that trapped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EretKind {
+    Eret,
+    /// ERET authenticating ELR with the A key
+    Eretaa,
+    /// ERET authenticating ELR with the B key
+    Eretab,
+}
+
+impl EretISS {
+    /// Create a new ISS with all fields cleared
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Create ISS from raw u32 value
+    pub const fn from_raw(value: u32) -> Self {
+        Self(value)
+    }
+
+    /// Get raw u32 value
+    pub const fn raw(&self) -> u32 {
+        self.0
+    }
+
+    pub fn kind(&self) -> EretKind {
+        match (self.eret(), self.ereta()) {
+            (false, _) => EretKind::Eret,
+            (true, false) => EretKind::Eretaa,
+            (true, true) => EretKind::Eretab,
+        }
+    }
+}
+
+impl Default for EretISS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eret_kind() {
+        assert_eq!(EretISS::from_raw(0b00).kind(), EretKind::Eret);
+        // ERETA is ignored for a plain ERET
+        assert_eq!(EretISS::from_raw(0b01).kind(), EretKind::Eret);
+        assert_eq!(EretISS::from_raw(0b10).kind(), EretKind::Eretaa);
+        assert_eq!(EretISS::from_raw(0b11).kind(), EretKind::Eretab);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod cp_reg;
 pub mod data_abort;
+pub mod eret;
 pub mod sys_reg;
 
-pub use data_abort::DataAbortISS;
+pub use cp_reg::{CoprocRegAbortISS, Coprocessor};
+pub use data_abort::{DataAbortISS, DataAbortISS2, Writeback};
+pub use eret::{EretISS, EretKind};
 pub use sys_reg::SysRegAbortISS;
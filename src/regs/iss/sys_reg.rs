@@ -1,6 +1,8 @@
-use crate::regs::{EmulatedSystemRegister, VRegister};
+use crate::SimppleError;
+use crate::regs::{EmulatedSystemRegister, SysRegEncoding, VRegister, format_sysreg_encoding};
 use ahvf::*;
 use bitfield::bitfield;
+use std::fmt;
 
 bitfield! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -56,13 +58,12 @@ impl SysRegAbortISS {
     /// Reconstruct the system register access instruction (MSR or MRS)
     pub fn reconstruct(&self) -> u32 {
         let mut insn: u32 = 0xD5000000; // Base instruction for MSR
-        insn |= 1 << 20;
         if !self.is_write() {
-            insn |= 1 << 21;
+            insn |= 1 << 21; // L: read
         }
-        insn |= 1 << 19;
 
-        insn |= (self.op1() << 16)
+        insn |= (self.op0() << 19)
+            | (self.op1() << 16)
             | (self.crn() << 12)
             | (self.crm() << 8)
             | (self.op2() << 5)
@@ -71,6 +72,24 @@ impl SysRegAbortISS {
         insn
     }
 
+    /// Decode an MSR/MRS (register) instruction back into the ISS it traps with
+    pub fn from_instruction(insn: u32) -> Option<Self> {
+        // 1101010100 L 1 o0 op1 CRn CRm op2 Rt, op0 = 0b1:o0
+        if insn & 0xFFD0_0000 != 0xD510_0000 {
+            return None;
+        }
+
+        let mut iss = Self::new();
+        iss.set_direction(insn & (1 << 21) != 0);
+        iss.set_op0((insn >> 19) & 0b11);
+        iss.set_op1((insn >> 16) & 0b111);
+        iss.set_crn((insn >> 12) & 0b1111);
+        iss.set_crm((insn >> 8) & 0b1111);
+        iss.set_op2((insn >> 5) & 0b111);
+        iss.set_rt(insn & 0b11111);
+        Some(iss)
+    }
+
     pub fn access_register(&self) -> VRegister {
         match self.rt() {
             0b00000 => VRegister::Register(Register::X0),
@@ -109,14 +128,77 @@ impl SysRegAbortISS {
         }
     }
 
-    pub fn system_register(&self) -> EmulatedSystemRegister {
-        match (self.op0(), self.op1(), self.crn(), self.crm(), self.op2()) {
-            (3, 7, 7, 12, 1) => EmulatedSystemRegister::CntpCtEl0,
-            (3, 3, 14, 0, 1) => EmulatedSystemRegister::CntpCtEl0,
-            (op0, op1, crn, crm, op2) => panic!(
-                "Unsupported system register access: op0={op0}, op1={op1}, crn={crn}, crm={crm}, op2={op2}"
-            ),
-        }
+    /// Encoding of the accessed register as (op0, op1, crn, crm, op2)
+    pub fn encoding(&self) -> SysRegEncoding {
+        (
+            self.op0() as u8,
+            self.op1() as u8,
+            self.crn() as u8,
+            self.crm() as u8,
+            self.op2() as u8,
+        )
+    }
+
+    /// Map the encoding to an emulated register, `SysRegNotFound` if we don't know about it
+    pub fn system_register(&self) -> std::result::Result<EmulatedSystemRegister, SimppleError> {
+        let register = match (self.op0(), self.op1(), self.crn(), self.crm(), self.op2()) {
+            (3, 3, 14, 0, 0) => Some(EmulatedSystemRegister::CntfrqEl0),
+            (3, 3, 14, 0, 1) => Some(EmulatedSystemRegister::CntpCtEl0),
+            (3, 3, 14, 0, 2) => Some(EmulatedSystemRegister::CntvCtEl0),
+            (3, 3, 14, 0, 5) => Some(EmulatedSystemRegister::CntpCtSsEl0),
+            (3, 3, 14, 0, 6) => Some(EmulatedSystemRegister::CntvCtSsEl0),
+            (3, 3, 14, 3, 1) => Some(EmulatedSystemRegister::CntvCtlEl0),
+            (3, 3, 14, 3, 2) => Some(EmulatedSystemRegister::CntvCvalEl0),
+            (3, 3, 14, 2, 1) => Some(EmulatedSystemRegister::CntpCtlEl0),
+            (3, 3, 14, 2, 2) => Some(EmulatedSystemRegister::CntpCvalEl0),
+            (3, 3, 14, 2, 0) => Some(EmulatedSystemRegister::CntpTvalEl0),
+            (3, 0, 14, 1, 0) => Some(EmulatedSystemRegister::CntkctlEl1),
+            (3, 3, 13, 0, 2) => Some(EmulatedSystemRegister::TpidrEl0),
+            (3, 0, 13, 0, 4) => Some(EmulatedSystemRegister::TpidrEl1),
+            (3, 3, 13, 0, 3) => Some(EmulatedSystemRegister::TpidrroEl0),
+            (2, 0, 0, 2, 2) => Some(EmulatedSystemRegister::MdscrEl1),
+            (2, 0, 1, 0, 4) => Some(EmulatedSystemRegister::OslarEl1),
+            (2, 0, 1, 1, 4) => Some(EmulatedSystemRegister::OslsrEl1),
+            (3, 0, 1, 0, 2) => Some(EmulatedSystemRegister::CpacrEl1),
+            (3, 3, 9, 12, 0) => Some(EmulatedSystemRegister::PmcrEl0),
+            (3, 3, 9, 12, 1) => Some(EmulatedSystemRegister::PmcntensetEl0),
+            (3, 3, 9, 12, 2) => Some(EmulatedSystemRegister::PmcntenclrEl0),
+            (3, 3, 9, 13, 0) => Some(EmulatedSystemRegister::PmccntrEl0),
+            (3, 3, 9, 14, 0) => Some(EmulatedSystemRegister::PmuserenrEl0),
+            (3, 0, 0, 0, 0) => Some(EmulatedSystemRegister::MidrEl1),
+            (3, 0, 0, 0, 5) => Some(EmulatedSystemRegister::MpidrEl1),
+            _ => None,
+        };
+        register.ok_or_else(|| SimppleError::SysRegNotFound(self.to_string()))
+    }
+
+    /// Describe the access, e.g. `mrs x0, S3_3_C14_C0_1 (op0=3, op1=3, crn=14, crm=0, op2=1)`
+    pub fn describe(&self) -> String {
+        let mnemonic = if self.is_write() { "msr" } else { "mrs" };
+        let register = match self.rt() {
+            31 => "xzr".to_string(),
+            rt => format!("x{rt}"),
+        };
+        let operands = if self.is_write() {
+            format!("{self}, {register}")
+        } else {
+            format!("{register}, {self}")
+        };
+        format!(
+            "{mnemonic} {operands} (op0={}, op1={}, crn={}, crm={}, op2={})",
+            self.op0(),
+            self.op1(),
+            self.crn(),
+            self.crm(),
+            self.op2()
+        )
+    }
+}
+
+/// Formats the generic encoding name, e.g. `S3_3_C14_C0_1`
+impl fmt::Display for SysRegAbortISS {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_sysreg_encoding(self.encoding()))
     }
 }
 
@@ -125,3 +207,126 @@ impl Default for SysRegAbortISS {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_cntpct_read() {
+        let mut iss = SysRegAbortISS::new();
+        iss.set_op0(3);
+        iss.set_op1(3);
+        iss.set_crn(14);
+        iss.set_crm(0);
+        iss.set_op2(1);
+        iss.set_rt(2);
+        iss.set_direction(true);
+
+        assert_eq!(iss.to_string(), "S3_3_C14_C0_1");
+        assert_eq!(
+            iss.describe(),
+            "mrs x2, S3_3_C14_C0_1 (op0=3, op1=3, crn=14, crm=0, op2=1)"
+        );
+        assert!(matches!(
+            iss.system_register(),
+            Ok(EmulatedSystemRegister::CntpCtEl0)
+        ));
+    }
+
+    #[test]
+    fn test_reconstruct_round_trips() {
+        // mrs x2, cntpct_el0
+        let cntpct = SysRegAbortISS::from_instruction(0xd53b_e022).unwrap();
+        assert_eq!(cntpct.encoding(), (3, 3, 14, 0, 1));
+        assert_eq!(cntpct.rt(), 2);
+        assert!(!cntpct.is_write());
+        assert_eq!(cntpct.reconstruct(), 0xd53b_e022);
+
+        // msr mdscr_el1, x5 uses op0 = 2
+        let mdscr = SysRegAbortISS::from_instruction(0xd510_0245).unwrap();
+        assert_eq!(mdscr.encoding(), (2, 0, 0, 2, 2));
+        assert!(mdscr.is_write());
+        assert_eq!(mdscr.reconstruct(), 0xd510_0245);
+
+        // Not a system register access: nop
+        assert!(SysRegAbortISS::from_instruction(0xd503_201f).is_none());
+    }
+
+    #[test]
+    fn test_counter_encodings() {
+        let lookup = |(op0, op1, crn, crm, op2): (u32, u32, u32, u32, u32)| {
+            let mut iss = SysRegAbortISS::new();
+            iss.set_op0(op0);
+            iss.set_op1(op1);
+            iss.set_crn(crn);
+            iss.set_crm(crm);
+            iss.set_op2(op2);
+            iss.system_register().ok()
+        };
+
+        // CNTPCT and CNTVCT only differ in op2, as do their self-synchronized views
+        assert_eq!(
+            lookup((3, 3, 14, 0, 1)),
+            Some(EmulatedSystemRegister::CntpCtEl0)
+        );
+        assert_eq!(
+            lookup((3, 3, 14, 0, 2)),
+            Some(EmulatedSystemRegister::CntvCtEl0)
+        );
+        assert_eq!(
+            lookup((3, 3, 14, 0, 5)),
+            Some(EmulatedSystemRegister::CntpCtSsEl0)
+        );
+        assert_eq!(
+            lookup((3, 3, 14, 0, 6)),
+            Some(EmulatedSystemRegister::CntvCtSsEl0)
+        );
+
+        // Not an architectural counter encoding
+        assert_eq!(lookup((3, 7, 7, 12, 1)), None);
+    }
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let registers = [
+            EmulatedSystemRegister::CntfrqEl0,
+            EmulatedSystemRegister::CntpCtEl0,
+            EmulatedSystemRegister::CntvCtEl0,
+            EmulatedSystemRegister::CntpCtSsEl0,
+            EmulatedSystemRegister::CntvCtSsEl0,
+            EmulatedSystemRegister::CntvCtlEl0,
+            EmulatedSystemRegister::CntvCvalEl0,
+            EmulatedSystemRegister::CntpCtlEl0,
+            EmulatedSystemRegister::CntpCvalEl0,
+            EmulatedSystemRegister::CntpTvalEl0,
+            EmulatedSystemRegister::CntkctlEl1,
+            EmulatedSystemRegister::TpidrEl0,
+            EmulatedSystemRegister::TpidrEl1,
+            EmulatedSystemRegister::TpidrroEl0,
+            EmulatedSystemRegister::MdscrEl1,
+            EmulatedSystemRegister::OslarEl1,
+            EmulatedSystemRegister::OslsrEl1,
+            EmulatedSystemRegister::CpacrEl1,
+            EmulatedSystemRegister::PmcrEl0,
+            EmulatedSystemRegister::PmcntensetEl0,
+            EmulatedSystemRegister::PmcntenclrEl0,
+            EmulatedSystemRegister::PmccntrEl0,
+            EmulatedSystemRegister::PmuserenrEl0,
+            EmulatedSystemRegister::MidrEl1,
+            EmulatedSystemRegister::MpidrEl1,
+        ];
+
+        for register in registers {
+            let (op0, op1, crn, crm, op2) = register.encoding();
+            let mut iss = SysRegAbortISS::new();
+            iss.set_op0(op0.into());
+            iss.set_op1(op1.into());
+            iss.set_crn(crn.into());
+            iss.set_crm(crm.into());
+            iss.set_op2(op2.into());
+
+            assert_eq!(iss.system_register().ok(), Some(register), "{register}");
+        }
+    }
+}
@@ -1,8 +1,19 @@
+pub mod cpacr_el1;
 pub mod esr_el2;
+pub mod exception_info;
+pub mod id_aa64mmfr0_el1;
 pub mod iss;
+pub mod raz_wi;
 pub mod spsr_el3;
+pub mod sysreg_file;
 pub mod utils;
 
+pub use ahvf::Register;
+pub use cpacr_el1::*;
 pub use esr_el2::*;
+pub use exception_info::ExceptionInfo;
+pub use id_aa64mmfr0_el1::*;
+pub use raz_wi::RazWiRegisters;
 pub use spsr_el3::*;
+pub use sysreg_file::*;
 pub use utils::*;
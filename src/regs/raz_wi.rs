@@ -0,0 +1,83 @@
+use crate::SimppleError;
+use crate::regs::{EmulatedSystemRegister, SysRegEncoding, parse_sysreg_encoding};
+use std::collections::HashSet;
+
+/// IMPLEMENTATION DEFINED registers firmware commonly pokes at
+const DEFAULT_RAZ_WI: [SysRegEncoding; 5] = [
+    (3, 0, 1, 0, 1),  // ACTLR_EL1
+    (3, 1, 11, 0, 2), // L2CTLR_EL1
+    (3, 1, 11, 0, 3), // L2ECTLR_EL1
+    (3, 1, 15, 2, 0), // CPUACTLR_EL1
+    (3, 1, 15, 2, 1), // CPUECTLR_EL1
+];
+
+/// System registers accepted as read-as-zero, write-ignored.
+///
+/// Lets guests touch implementation-defined registers with no meaningful
+/// behaviour under emulation instead of stopping the VM. Encodings can be
+/// added at runtime, e.g. from a command-line list of `S3_1_C15_C2_1` names.
+#[derive(Debug, Clone)]
+pub struct RazWiRegisters {
+    encodings: HashSet<SysRegEncoding>,
+}
+
+impl RazWiRegisters {
+    /// An empty allowlist
+    pub fn new() -> Self {
+        Self {
+            encodings: HashSet::new(),
+        }
+    }
+
+    pub fn insert(&mut self, encoding: SysRegEncoding) {
+        self.encodings.insert(encoding);
+    }
+
+    /// Add a register by its generic name, e.g. `S3_1_C15_C2_1`
+    pub fn insert_named(&mut self, name: &str) -> Result<(), SimppleError> {
+        let encoding = parse_sysreg_encoding(name.trim())
+            .ok_or_else(|| SimppleError::SysRegNotFound(name.to_string()))?;
+        self.insert(encoding);
+        Ok(())
+    }
+
+    /// The emulated register for `encoding`, if it is on the allowlist
+    pub fn lookup(&self, encoding: SysRegEncoding) -> Option<EmulatedSystemRegister> {
+        self.encodings
+            .contains(&encoding)
+            .then_some(EmulatedSystemRegister::ImplementationDefined(encoding))
+    }
+}
+
+impl Default for RazWiRegisters {
+    /// The allowlist pre-populated with common IMPLEMENTATION DEFINED registers
+    fn default() -> Self {
+        Self {
+            encodings: DEFAULT_RAZ_WI.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowlist() {
+        let mut registers = RazWiRegisters::default();
+        assert_eq!(
+            registers.lookup((3, 0, 1, 0, 1)),
+            Some(EmulatedSystemRegister::ImplementationDefined((
+                3, 0, 1, 0, 1
+            )))
+        );
+        assert_eq!(registers.lookup((3, 2, 15, 0, 0)), None);
+
+        registers.insert_named("S3_2_C15_C0_0").unwrap();
+        let register = registers.lookup((3, 2, 15, 0, 0)).unwrap();
+        assert_eq!(register.to_string(), "S3_2_C15_C0_0");
+
+        assert!(registers.insert_named("S3_2_C16_C0_0").is_err());
+        assert!(registers.insert_named("ACTLR_EL1").is_err());
+    }
+}
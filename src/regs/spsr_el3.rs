@@ -120,11 +120,11 @@ impl SpsrEl3 {
     }
 
     /// Set stack pointer selection (bit [0] of M field)
-    /// false = SP_ELx (dedicated stack pointer)
-    /// true = SP_EL0 (shared stack pointer)
-    pub fn set_stack_pointer(&mut self, use_el0_sp: bool) {
+    /// false = SP_EL0 (shared stack pointer)
+    /// true = SP_ELx (dedicated stack pointer)
+    pub fn set_stack_pointer(&mut self, use_elx_sp: bool) {
         let current_m = self.m3_0();
-        let new_m = (current_m & 0b1110) | (use_el0_sp as u64);
+        let new_m = (current_m & 0b1110) | (use_elx_sp as u64);
         self.set_m3_0(new_m);
     }
 
@@ -148,6 +148,61 @@ impl SpsrEl3 {
         self.set_i(i);
         self.set_f(f);
     }
+
+    /// Explain why an exception return to this saved PSTATE would be illegal.
+    ///
+    /// `current_el` is the level executing the ERET and `highest_el` the
+    /// highest level implemented for the guest. Returns an empty list if the
+    /// return looks legal.
+    pub fn illegal_return_reasons(&self, current_el: u8, highest_el: u8) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let target_el = self.exception_level();
+
+        if self.il() {
+            reasons.push("SPSR.IL is already set".to_string());
+        }
+        if self.m4() {
+            reasons.push("SPSR.M[4] requests AArch32, which is not supported".to_string());
+        }
+        if self.m3_0() & 0b0010 != 0 {
+            reasons.push(format!(
+                "SPSR.M[3:0]={:#06b} is a reserved encoding",
+                self.m3_0()
+            ));
+        }
+        if target_el > current_el {
+            reasons.push(format!(
+                "return from EL{current_el} to a higher exception level EL{target_el}"
+            ));
+        }
+        if target_el > highest_el {
+            reasons.push(format!("target EL{target_el} is not implemented"));
+        }
+        if target_el == 0 && self.m3_0() & 1 != 0 {
+            reasons.push("EL0 cannot select a dedicated stack pointer (M[0]=1)".to_string());
+        }
+
+        reasons
+    }
+
+    /// PSTATE after an exception return that restores this saved PSTATE.
+    ///
+    /// An illegal return keeps the current EL and stack pointer selection,
+    /// takes the remaining fields from the SPSR and sets PSTATE.IL.
+    pub fn exception_return(&self, current: SpsrEl3, highest_el: u8) -> SpsrEl3 {
+        if self
+            .illegal_return_reasons(current.exception_level(), highest_el)
+            .is_empty()
+        {
+            return *self;
+        }
+
+        let mut pstate = *self;
+        pstate.set_m4(current.m4());
+        pstate.set_m3_0(current.m3_0());
+        pstate.set_il(true);
+        pstate
+    }
 }
 
 impl Default for SpsrEl3 {
@@ -211,4 +266,37 @@ mod tests {
         assert_eq!(spsr.exception_level(), 2);
         assert!(!spsr.stack_pointer_is_el0());
     }
+
+    #[test]
+    fn test_exception_return() {
+        let mut current = SpsrEl3::new();
+        current.set_m3_0(SpsrEl3::EL1H);
+
+        let mut saved = SpsrEl3::new();
+        saved.set_m3_0(SpsrEl3::EL0);
+        saved.set_n(true);
+        assert_eq!(saved.exception_return(current, 1), saved);
+
+        // Returning upwards is illegal: stay at EL1h with IL set
+        saved.set_m3_0(SpsrEl3::EL2H);
+        let pstate = saved.exception_return(current, 1);
+        assert_eq!(pstate.exception_level(), 1);
+        assert!(!pstate.stack_pointer_is_el0());
+        assert!(pstate.il());
+        assert!(pstate.n());
+    }
+
+    #[test]
+    fn test_illegal_return_reasons() {
+        let mut spsr = SpsrEl3::new();
+        spsr.set_m3_0(SpsrEl3::EL1H);
+        assert!(spsr.illegal_return_reasons(1, 1).is_empty());
+
+        spsr.set_m3_0(SpsrEl3::EL2H);
+        assert_eq!(spsr.illegal_return_reasons(1, 1).len(), 2);
+
+        spsr.set_m3_0(0b0001);
+        spsr.set_m4(true);
+        assert_eq!(spsr.illegal_return_reasons(1, 1).len(), 2);
+    }
 }
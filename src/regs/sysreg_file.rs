@@ -0,0 +1,133 @@
+use crate::regs::EmulatedSystemRegister;
+use std::collections::HashMap;
+
+// OSLSR_EL1.OSLM = 0b10: the OS Lock is implemented
+const OSLSR_OSLM: u64 = 1 << 3;
+// OSLSR_EL1.OSLK: the OS Lock is locked
+const OSLSR_OSLK: u64 = 1 << 1;
+
+/// Cortex-A53 r0p4
+pub const DEFAULT_MIDR_EL1: u64 = 0x410f_d034;
+/// Core 0 of a uniprocessor system, bit 31 is RES1
+pub const DEFAULT_MPIDR_EL1: u64 = 0x8000_0000;
+
+/// Identification registers reported to the guest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuIdentity {
+    pub midr: u64,
+    pub mpidr: u64,
+}
+
+impl Default for CpuIdentity {
+    fn default() -> Self {
+        Self {
+            midr: DEFAULT_MIDR_EL1,
+            mpidr: DEFAULT_MPIDR_EL1,
+        }
+    }
+}
+
+/// Per-vCPU backing storage for emulated system registers.
+///
+/// Registers are plain storage, except that writing OSLAR_EL1.OSLK sets the
+/// lock state reported in OSLSR_EL1, and MIDR_EL1 and MPIDR_EL1 ignore
+/// writes.
+///
+/// MDSCR_EL1 is stored but never reaches the vCPU: the host traps debug
+/// exceptions to drive its own single-stepping and breakpoints, so a guest
+/// setting MDSCR_EL1.SS or KDE does not get software-step or debug exceptions
+/// of its own.
+#[derive(Debug, Default, Clone)]
+pub struct SystemRegisterFile {
+    values: HashMap<EmulatedSystemRegister, u64>,
+}
+
+impl SystemRegisterFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a file reporting `identity` in MIDR_EL1 and MPIDR_EL1
+    pub fn with_identity(identity: CpuIdentity) -> Self {
+        let mut file = Self::new();
+        file.values
+            .insert(EmulatedSystemRegister::MidrEl1, identity.midr);
+        file.values
+            .insert(EmulatedSystemRegister::MpidrEl1, identity.mpidr);
+        file
+    }
+
+    /// Read a register, registers never written read as zero
+    pub fn read(&self, register: EmulatedSystemRegister) -> u64 {
+        let value = self.values.get(&register).copied().unwrap_or(0);
+        match register {
+            // Write-only
+            EmulatedSystemRegister::OslarEl1 => 0,
+            EmulatedSystemRegister::OslsrEl1 => OSLSR_OSLM | value,
+            _ => value,
+        }
+    }
+
+    pub fn write(&mut self, register: EmulatedSystemRegister, value: u64) {
+        match register {
+            EmulatedSystemRegister::OslarEl1 => {
+                // OSLAR_EL1.OSLK is bit 0, reported back as OSLSR_EL1.OSLK
+                let locked = if value & 1 != 0 { OSLSR_OSLK } else { 0 };
+                self.values.insert(EmulatedSystemRegister::OslsrEl1, locked);
+            }
+            // Read-only
+            EmulatedSystemRegister::OslsrEl1
+            | EmulatedSystemRegister::MidrEl1
+            | EmulatedSystemRegister::MpidrEl1 => {}
+            _ => {
+                self.values.insert(register, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_then_read() {
+        let mut file = SystemRegisterFile::new();
+        assert_eq!(file.read(EmulatedSystemRegister::TpidrEl0), 0);
+
+        file.write(EmulatedSystemRegister::TpidrEl0, 0xdead_beef);
+        file.write(EmulatedSystemRegister::TpidrEl1, 0x1234);
+        assert_eq!(file.read(EmulatedSystemRegister::TpidrEl0), 0xdead_beef);
+        assert_eq!(file.read(EmulatedSystemRegister::TpidrEl1), 0x1234);
+        assert_eq!(file.read(EmulatedSystemRegister::TpidrroEl0), 0);
+    }
+
+    #[test]
+    fn test_os_lock() {
+        let mut file = SystemRegisterFile::new();
+        assert_eq!(file.read(EmulatedSystemRegister::OslsrEl1), OSLSR_OSLM);
+
+        file.write(EmulatedSystemRegister::OslarEl1, 1);
+        assert_eq!(
+            file.read(EmulatedSystemRegister::OslsrEl1),
+            OSLSR_OSLM | OSLSR_OSLK
+        );
+
+        // OSLSR_EL1 is read-only, unlocking goes through OSLAR_EL1
+        file.write(EmulatedSystemRegister::OslsrEl1, 0);
+        assert_ne!(file.read(EmulatedSystemRegister::OslsrEl1) & OSLSR_OSLK, 0);
+        file.write(EmulatedSystemRegister::OslarEl1, 0);
+        assert_eq!(file.read(EmulatedSystemRegister::OslsrEl1), OSLSR_OSLM);
+    }
+
+    #[test]
+    fn test_identity_is_read_only() {
+        let mut file = SystemRegisterFile::with_identity(CpuIdentity::default());
+        file.write(EmulatedSystemRegister::MpidrEl1, 1);
+        assert_eq!(
+            file.read(EmulatedSystemRegister::MpidrEl1),
+            DEFAULT_MPIDR_EL1
+        );
+        assert_eq!(file.read(EmulatedSystemRegister::MidrEl1), DEFAULT_MIDR_EL1);
+    }
+}
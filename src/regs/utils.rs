@@ -1,4 +1,6 @@
+use crate::regs::SpsrEl3;
 use ahvf::*;
+use std::fmt;
 
 #[derive(Debug)]
 pub enum VRegister {
@@ -20,7 +22,333 @@ pub fn set_register_value(vcpu: &mut VirtualCpu, vreg: VRegister, value: u64) ->
     }
 }
 
-#[derive(Debug)]
+/// Canonical order of the general-purpose registers: X0-X30, then PC.
+///
+/// Shared by everything that transfers the register file in bulk, so dumps,
+/// snapshots and debugger packets agree on the layout.
+pub const GP_REGISTERS: [Register; 32] = [
+    Register::X0,
+    Register::X1,
+    Register::X2,
+    Register::X3,
+    Register::X4,
+    Register::X5,
+    Register::X6,
+    Register::X7,
+    Register::X8,
+    Register::X9,
+    Register::X10,
+    Register::X11,
+    Register::X12,
+    Register::X13,
+    Register::X14,
+    Register::X15,
+    Register::X16,
+    Register::X17,
+    Register::X18,
+    Register::X19,
+    Register::X20,
+    Register::X21,
+    Register::X22,
+    Register::X23,
+    Register::X24,
+    Register::X25,
+    Register::X26,
+    Register::X27,
+    Register::X28,
+    Register::X29,
+    Register::X30,
+    Register::PC,
+];
+
+/// Every register of the `Register` enum: `GP_REGISTERS`, then FPCR, FPSR and CPSR
+pub const ALL_REGISTERS: [Register; 35] = {
+    let mut all = [Register::CPSR; 35];
+    let mut i = 0;
+    while i < GP_REGISTERS.len() {
+        all[i] = GP_REGISTERS[i];
+        i += 1;
+    }
+    all[32] = Register::FPCR;
+    all[33] = Register::FPSR;
+    all
+};
+
+/// Architectural name of a register, as shown in register dumps
+pub const fn register_name(register: Register) -> &'static str {
+    match register {
+        Register::X0 => "X0",
+        Register::X1 => "X1",
+        Register::X2 => "X2",
+        Register::X3 => "X3",
+        Register::X4 => "X4",
+        Register::X5 => "X5",
+        Register::X6 => "X6",
+        Register::X7 => "X7",
+        Register::X8 => "X8",
+        Register::X9 => "X9",
+        Register::X10 => "X10",
+        Register::X11 => "X11",
+        Register::X12 => "X12",
+        Register::X13 => "X13",
+        Register::X14 => "X14",
+        Register::X15 => "X15",
+        Register::X16 => "X16",
+        Register::X17 => "X17",
+        Register::X18 => "X18",
+        Register::X19 => "X19",
+        Register::X20 => "X20",
+        Register::X21 => "X21",
+        Register::X22 => "X22",
+        Register::X23 => "X23",
+        Register::X24 => "X24",
+        Register::X25 => "X25",
+        Register::X26 => "X26",
+        Register::X27 => "X27",
+        Register::X28 => "X28",
+        Register::X29 => "X29",
+        Register::X30 => "X30",
+        Register::PC => "PC",
+        Register::FPCR => "FPCR",
+        Register::FPSR => "FPSR",
+        Register::CPSR => "CPSR",
+    }
+}
+
+/// Access to the general-purpose registers of a vCPU
+pub trait RegisterAccess {
+    fn get_register(&mut self, register: Register) -> Result<u64>;
+    fn set_register(&mut self, register: Register, value: u64) -> Result<()>;
+}
+
+impl RegisterAccess for VirtualCpu {
+    fn get_register(&mut self, register: Register) -> Result<u64> {
+        VirtualCpu::get_register(self, register)
+    }
+
+    fn set_register(&mut self, register: Register, value: u64) -> Result<()> {
+        VirtualCpu::set_register(self, register, value)
+    }
+}
+
+/// Read all of `GP_REGISTERS`, in order
+pub fn read_gp_registers<V: RegisterAccess>(vcpu: &mut V) -> Result<[u64; 32]> {
+    let mut values = [0; 32];
+    for (value, register) in values.iter_mut().zip(GP_REGISTERS) {
+        *value = vcpu.get_register(register)?;
+    }
+    Ok(values)
+}
+
+/// Write all of `GP_REGISTERS`, in order
+pub fn write_gp_registers<V: RegisterAccess>(vcpu: &mut V, values: &[u64; 32]) -> Result<()> {
+    for (value, register) in values.iter().zip(GP_REGISTERS) {
+        vcpu.set_register(register, *value)?;
+    }
+    Ok(())
+}
+
+/// Read the stack pointer selected by PSTATE.SP at the current EL
+pub fn get_stack_pointer(vcpu: &mut VirtualCpu) -> Result<u64> {
+    let pstate = SpsrEl3::from_raw(vcpu.get_register(Register::CPSR)?);
+    if pstate.stack_pointer_is_el0() {
+        vcpu.get_system_register(SystemRegister::SP_EL0)
+    } else {
+        // Guests never run above EL1, see `HVF_MAX_EXCEPTION_LEVEL`
+        vcpu.get_system_register(SystemRegister::SP_EL1)
+    }
+}
+
+/// Write the stack pointer selected by PSTATE.SP at the current EL
+pub fn set_stack_pointer(vcpu: &mut VirtualCpu, value: u64) -> Result<()> {
+    let pstate = SpsrEl3::from_raw(vcpu.get_register(Register::CPSR)?);
+    if pstate.stack_pointer_is_el0() {
+        vcpu.set_system_register(SystemRegister::SP_EL0, value)
+    } else {
+        vcpu.set_system_register(SystemRegister::SP_EL1, value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum EmulatedSystemRegister {
+    CntfrqEl0,
     CntpCtEl0,
+    CntvCtEl0,
+    /// Self-synchronized view of CNTPCT_EL0 (FEAT_ECV)
+    CntpCtSsEl0,
+    /// Self-synchronized view of CNTVCT_EL0 (FEAT_ECV)
+    CntvCtSsEl0,
+    CntvCtlEl0,
+    CntvCvalEl0,
+    CntpCtlEl0,
+    CntpCvalEl0,
+    CntpTvalEl0,
+    CntkctlEl1,
+    TpidrEl0,
+    TpidrEl1,
+    TpidrroEl0,
+    MdscrEl1,
+    OslarEl1,
+    OslsrEl1,
+    CpacrEl1,
+    PmcrEl0,
+    PmcntensetEl0,
+    PmcntenclrEl0,
+    PmccntrEl0,
+    PmuserenrEl0,
+    MidrEl1,
+    MpidrEl1,
+    /// IMPLEMENTATION DEFINED register treated as RAZ/WI, by encoding
+    ImplementationDefined(SysRegEncoding),
+}
+
+/// System register encoding as (op0, op1, crn, crm, op2)
+pub type SysRegEncoding = (u8, u8, u8, u8, u8);
+
+/// Format an encoding in the generic `S<op0>_<op1>_C<n>_C<m>_<op2>` syntax
+pub fn format_sysreg_encoding((op0, op1, crn, crm, op2): SysRegEncoding) -> String {
+    format!("S{op0}_{op1}_C{crn}_C{crm}_{op2}")
+}
+
+/// Parse the generic `S<op0>_<op1>_C<n>_C<m>_<op2>` syntax, e.g. `S3_1_C15_C2_1`
+pub fn parse_sysreg_encoding(name: &str) -> Option<SysRegEncoding> {
+    let mut fields = name.strip_prefix(['S', 's'])?.split('_');
+    let mut field = |prefix: Option<char>, max: u8| {
+        let text = fields.next()?;
+        let digits = match prefix {
+            Some(prefix) => text.strip_prefix([prefix, prefix.to_ascii_lowercase()])?,
+            None => text,
+        };
+        digits.parse::<u8>().ok().filter(|value| *value <= max)
+    };
+    let encoding = (
+        field(None, 3)?,
+        field(None, 7)?,
+        field(Some('C'), 15)?,
+        field(Some('C'), 15)?,
+        field(None, 7)?,
+    );
+    fields.next().is_none().then_some(encoding)
+}
+
+impl EmulatedSystemRegister {
+    /// Architectural encoding as (op0, op1, crn, crm, op2)
+    pub const fn encoding(&self) -> SysRegEncoding {
+        match self {
+            EmulatedSystemRegister::CntfrqEl0 => (3, 3, 14, 0, 0),
+            EmulatedSystemRegister::CntpCtEl0 => (3, 3, 14, 0, 1),
+            EmulatedSystemRegister::CntvCtEl0 => (3, 3, 14, 0, 2),
+            EmulatedSystemRegister::CntpCtSsEl0 => (3, 3, 14, 0, 5),
+            EmulatedSystemRegister::CntvCtSsEl0 => (3, 3, 14, 0, 6),
+            EmulatedSystemRegister::CntvCtlEl0 => (3, 3, 14, 3, 1),
+            EmulatedSystemRegister::CntvCvalEl0 => (3, 3, 14, 3, 2),
+            EmulatedSystemRegister::CntpCtlEl0 => (3, 3, 14, 2, 1),
+            EmulatedSystemRegister::CntpCvalEl0 => (3, 3, 14, 2, 2),
+            EmulatedSystemRegister::CntpTvalEl0 => (3, 3, 14, 2, 0),
+            EmulatedSystemRegister::CntkctlEl1 => (3, 0, 14, 1, 0),
+            EmulatedSystemRegister::TpidrEl0 => (3, 3, 13, 0, 2),
+            EmulatedSystemRegister::TpidrEl1 => (3, 0, 13, 0, 4),
+            EmulatedSystemRegister::TpidrroEl0 => (3, 3, 13, 0, 3),
+            EmulatedSystemRegister::MdscrEl1 => (2, 0, 0, 2, 2),
+            EmulatedSystemRegister::OslarEl1 => (2, 0, 1, 0, 4),
+            EmulatedSystemRegister::OslsrEl1 => (2, 0, 1, 1, 4),
+            EmulatedSystemRegister::CpacrEl1 => (3, 0, 1, 0, 2),
+            EmulatedSystemRegister::PmcrEl0 => (3, 3, 9, 12, 0),
+            EmulatedSystemRegister::PmcntensetEl0 => (3, 3, 9, 12, 1),
+            EmulatedSystemRegister::PmcntenclrEl0 => (3, 3, 9, 12, 2),
+            EmulatedSystemRegister::PmccntrEl0 => (3, 3, 9, 13, 0),
+            EmulatedSystemRegister::PmuserenrEl0 => (3, 3, 9, 14, 0),
+            EmulatedSystemRegister::MidrEl1 => (3, 0, 0, 0, 0),
+            EmulatedSystemRegister::MpidrEl1 => (3, 0, 0, 0, 5),
+            EmulatedSystemRegister::ImplementationDefined(encoding) => *encoding,
+        }
+    }
+
+    /// Architectural register name
+    pub const fn name(&self) -> &'static str {
+        match self {
+            EmulatedSystemRegister::CntfrqEl0 => "CNTFRQ_EL0",
+            EmulatedSystemRegister::CntpCtEl0 => "CNTPCT_EL0",
+            EmulatedSystemRegister::CntvCtEl0 => "CNTVCT_EL0",
+            EmulatedSystemRegister::CntpCtSsEl0 => "CNTPCTSS_EL0",
+            EmulatedSystemRegister::CntvCtSsEl0 => "CNTVCTSS_EL0",
+            EmulatedSystemRegister::CntvCtlEl0 => "CNTV_CTL_EL0",
+            EmulatedSystemRegister::CntvCvalEl0 => "CNTV_CVAL_EL0",
+            EmulatedSystemRegister::CntpCtlEl0 => "CNTP_CTL_EL0",
+            EmulatedSystemRegister::CntpCvalEl0 => "CNTP_CVAL_EL0",
+            EmulatedSystemRegister::CntpTvalEl0 => "CNTP_TVAL_EL0",
+            EmulatedSystemRegister::CntkctlEl1 => "CNTKCTL_EL1",
+            EmulatedSystemRegister::TpidrEl0 => "TPIDR_EL0",
+            EmulatedSystemRegister::TpidrEl1 => "TPIDR_EL1",
+            EmulatedSystemRegister::TpidrroEl0 => "TPIDRRO_EL0",
+            EmulatedSystemRegister::MdscrEl1 => "MDSCR_EL1",
+            EmulatedSystemRegister::OslarEl1 => "OSLAR_EL1",
+            EmulatedSystemRegister::OslsrEl1 => "OSLSR_EL1",
+            EmulatedSystemRegister::CpacrEl1 => "CPACR_EL1",
+            EmulatedSystemRegister::PmcrEl0 => "PMCR_EL0",
+            EmulatedSystemRegister::PmcntensetEl0 => "PMCNTENSET_EL0",
+            EmulatedSystemRegister::PmcntenclrEl0 => "PMCNTENCLR_EL0",
+            EmulatedSystemRegister::PmccntrEl0 => "PMCCNTR_EL0",
+            EmulatedSystemRegister::PmuserenrEl0 => "PMUSERENR_EL0",
+            EmulatedSystemRegister::MidrEl1 => "MIDR_EL1",
+            EmulatedSystemRegister::MpidrEl1 => "MPIDR_EL1",
+            EmulatedSystemRegister::ImplementationDefined(_) => "IMPLEMENTATION DEFINED",
+        }
+    }
+}
+
+impl fmt::Display for EmulatedSystemRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatedSystemRegister::ImplementationDefined(encoding) => {
+                f.write_str(&format_sysreg_encoding(*encoding))
+            }
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Register file keyed by position in `GP_REGISTERS`
+    struct FakeCpu([u64; 32]);
+
+    impl FakeCpu {
+        fn index(register: Register) -> usize {
+            GP_REGISTERS.iter().position(|r| *r == register).unwrap()
+        }
+    }
+
+    impl RegisterAccess for FakeCpu {
+        fn get_register(&mut self, register: Register) -> Result<u64> {
+            Ok(self.0[Self::index(register)])
+        }
+
+        fn set_register(&mut self, register: Register, value: u64) -> Result<()> {
+            self.0[Self::index(register)] = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_register_names_are_unique() {
+        let names: std::collections::HashSet<_> =
+            ALL_REGISTERS.iter().map(|r| register_name(*r)).collect();
+        assert_eq!(names.len(), ALL_REGISTERS.len());
+        assert_eq!(register_name(ALL_REGISTERS[31]), "PC");
+        assert_eq!(register_name(ALL_REGISTERS[34]), "CPSR");
+    }
+
+    #[test]
+    fn test_gp_registers_round_trip() {
+        let mut cpu = FakeCpu([0; 32]);
+        let values = std::array::from_fn(|i| 0x1000 + i as u64);
+
+        write_gp_registers(&mut cpu, &values).unwrap();
+        assert_eq!(read_gp_registers(&mut cpu).unwrap(), values);
+        assert_eq!(cpu.get_register(Register::PC).unwrap(), 0x1000 + 31);
+        assert_eq!(cpu.get_register(Register::X29).unwrap(), 0x1000 + 29);
+    }
 }
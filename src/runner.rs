@@ -0,0 +1,1172 @@
+//! Run a booted guest one exit at a time.
+//!
+//! `VmRunner` builds the platform from a `BootConfig`, loads the images and
+//! starts the vCPU. Each `step` runs the vCPU until it exits and emulates
+//! that exit, so tests can drive the guest and inspect it in between.
+
+use crate::boot::BootConfig;
+use crate::break_request::BreakRequest;
+use crate::debugger::{Debugger, HwBreakpoints, TemporaryBreakpoint};
+use crate::devices::disk::SectorDisk;
+use crate::devices::pmu::Pmu;
+use crate::devices::timer::{
+    COUNTER_FREQUENCY_HZ, Clock, HostClock, PhysicalTimer, VirtualClock, cntkctl_el0_permits,
+    ctl_asserts_interrupt, fires_at, get_cntpct_el0,
+};
+use crate::devices::trace::MmioLog;
+use crate::devices::uart::ConsoleHistory;
+use crate::devices::{GuestDma, MmioFaultPolicy};
+use crate::fault_loop::FaultLoopDetector;
+use crate::hypercall::HypercallContext;
+use crate::inject::{ExceptionInjector, SERROR_ISS_UNCONTAINABLE};
+use crate::mems::BootManifest;
+use crate::platform::{Platform, build_vm};
+use crate::psci::PsciAction;
+use crate::regs::iss::{
+    CoprocRegAbortISS, Coprocessor, DataAbortISS, EretISS, EretKind, SysRegAbortISS, Writeback,
+};
+use crate::regs::utils::{
+    get_register_value, get_stack_pointer, set_register_value, write_gp_registers,
+};
+use crate::regs::{
+    CpacrEl1, EmulatedSystemRegister, ExceptionClass, ExceptionInfo, SpsrEl3, SystemRegisterFile,
+};
+use crate::vcpu::{HVF_MAX_EXCEPTION_LEVEL, SpSelect, VcpuConfig};
+use crate::vm::VmExit;
+use crate::watchdog::Watchdog;
+use crate::{MmioManager, SharedMemory, SimppleError};
+use ahvf::{
+    InterruptType, MemoryPermission, Register, SystemRegister, VirtualCpu, VirtualCpuExitReason,
+    VirtualMachine,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const STACK_DUMP_WORDS: usize = 8; // Stack doublewords shown on SP faults
+const FAULT_LOOP_LIMIT: u32 = 64; // Identical faults in a row before giving up
+const WFI_POLL_INTERVAL: Duration = Duration::from_millis(1); // Wake source checks while parked
+const DISK_BASE: u64 = 0x9080000; // Where the sector disk is mapped
+const MAILBOX_BASE: u64 = 0x9090000; // Where the mailbox is mapped
+
+// EL1 state the firmware may have changed, put back on a PSCI SYSTEM_RESET
+const RESET_SYSTEM_REGISTERS: [SystemRegister; 16] = [
+    SystemRegister::SCTLR_EL1,
+    SystemRegister::TCR_EL1,
+    SystemRegister::TTBR0_EL1,
+    SystemRegister::TTBR1_EL1,
+    SystemRegister::MAIR_EL1,
+    SystemRegister::VBAR_EL1,
+    SystemRegister::CPACR_EL1,
+    SystemRegister::SP_EL0,
+    SystemRegister::SP_EL1,
+    SystemRegister::ELR_EL1,
+    SystemRegister::SPSR_EL1,
+    SystemRegister::ESR_EL1,
+    SystemRegister::FAR_EL1,
+    SystemRegister::TPIDR_EL1,
+    SystemRegister::CNTV_CTL_EL0,
+    SystemRegister::CNTV_CVAL_EL0,
+];
+
+/// What became of the guest after one `VmRunner::step`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The exit was handled, step again to resume the guest
+    Continue,
+    /// The guest stopped; call `finish` and don't step again
+    Exit(VmExit),
+}
+
+/// A booted guest and everything emulated around it
+pub struct VmRunner<'a> {
+    // The watchdog and break request threads hold the vCPU handle, drop them first
+    watchdog: Option<Watchdog>,
+    break_request: Option<BreakRequest>,
+    config: &'a BootConfig,
+    vm: VirtualMachine,
+    mmu: SharedMemory,
+    mmio: MmioManager,
+    debugger: Debugger,
+    vcpu: VirtualCpu,
+    manifest: BootManifest<'a>,
+    vcpu_config: VcpuConfig,
+    reset_state: Vec<(SystemRegister, u64)>, // EL1 registers as set up at entry
+    virtual_clock: Option<(VirtualClock, u64)>, // Clock and nanoseconds per exit
+    clock: Clock,
+    vtimer_fired: bool, // The hardware vtimer is masked until the guest handles its IRQ
+    ptimer: PhysicalTimer,
+    sysregs: SystemRegisterFile,
+    pmu: Pmu,
+    injector: ExceptionInjector,
+    break_cancel_pending: bool,
+    stop_at: Option<TemporaryBreakpoint>,
+    hw_breakpoints: HwBreakpoints,
+    steps_requested: u64, // Instructions to single-step, from the config or `step_n`
+    steps_remaining: u64,
+    fault_loop: FaultLoopDetector,
+}
+
+impl<'a> VmRunner<'a> {
+    /// Build the platform, load the images and set up the vCPU at the entry point
+    pub fn new(config: &'a BootConfig) -> Result<Self, SimppleError> {
+        let Platform {
+            vm: mut virtual_machine,
+            mmu,
+            mmio: mut mmio_manager,
+            console,
+        } = build_vm(&config.platform)?;
+
+        // Optionally record MMIO accesses, or replay a previous recording
+        if let Some(path) = &config.mmio_replay {
+            mmio_manager.start_replay(MmioLog::load(path)?);
+        } else if config.mmio_record.is_some() {
+            mmio_manager.start_recording();
+        }
+
+        mmio_manager.set_mmio_fault_policy(config.mmio_fault_policy);
+
+        // Optionally attach a host file as a sector disk
+        if let Some(path) = &config.disk {
+            let disk = SectorDisk::open(path)?;
+            log::info!(
+                "Attached {} as a {}-sector disk",
+                path.display(),
+                disk.capacity()
+            );
+            mmio_manager.register_device(DISK_BASE, Box::new(disk))?;
+        }
+
+        if let Some(mailbox) = &config.mailbox {
+            mmio_manager.register_device(MAILBOX_BASE, Box::new(mailbox.clone()))?;
+        }
+
+        // Setup Debugger
+        let mut debugger = Debugger::new()?;
+        if let Some(console) = console {
+            debugger.set_console_history(console);
+        }
+
+        // Setup Memory
+        let manifest = BootManifest::new()
+            .entry_image(config.firmware.as_slice(), config.platform.firmware_base, 4)
+            .image(config.dtb.as_slice(), config.dtb_address, 8);
+        let entry = manifest.load(&mut virtual_machine, &mmu)?;
+
+        // Setup vCPU
+        let mut vcpu = virtual_machine.create_vcpu(None)?;
+
+        let vcpu_config = VcpuConfig::new()
+            .entry(entry)
+            .dtb(config.dtb_address)
+            .exception_level(1)
+            .sp(SpSelect::El0)
+            .mask_interrupts(true)
+            .traps(config.traps)
+            .ipa_bits(config.platform.ipa_bits);
+        vcpu_config.build_and_apply(&mut vcpu)?;
+        let reset_state = RESET_SYSTEM_REGISTERS
+            .iter()
+            .map(|&register| Ok((register, vcpu.get_system_register(register)?)))
+            .collect::<Result<Vec<_>, SimppleError>>()?;
+        if !config.traps.debug_registers && (config.steps > 0 || !config.hw_breakpoints.is_empty())
+        {
+            log::warn!(
+                "Debug registers are passed through, the guest may disturb stepping and breakpoints"
+            );
+        }
+
+        vcpu.set_vtimer_mask(false)?;
+        // In deterministic mode guest time only advances by a fixed step per exit
+        let frequency = config.timer.map(|timer| u64::from(timer.freq_hz));
+        let virtual_clock = config.virtual_clock_step.map(|step| {
+            let frequency = frequency.unwrap_or(COUNTER_FREQUENCY_HZ);
+            (VirtualClock::with_frequency(frequency), step)
+        });
+        let clock = match (&virtual_clock, frequency) {
+            (Some((virtual_clock, _)), _) => Clock::Virtual(virtual_clock.clone()),
+            (None, Some(frequency)) => Clock::Host(HostClock::with_frequency(frequency)),
+            (None, None) => Clock::default(),
+        };
+        let ptimer = PhysicalTimer::with_clock(clock.clone());
+        let sysregs = SystemRegisterFile::with_identity(config.cpu_identity);
+        let pmu = Pmu::with_clock(clock.clone());
+        let injector = ExceptionInjector::new();
+
+        let watchdog = config
+            .timeout
+            .map(|timeout| Watchdog::start(&vcpu, timeout));
+        // Ctrl-C shows the debug info and resumes the guest instead of killing it
+        let break_request = config
+            .break_on_interrupt
+            .then(|| BreakRequest::start(&vcpu));
+
+        // Optionally run freely until PC reaches a given address
+        let stop_at = match config.run_until {
+            Some(address) => Some(TemporaryBreakpoint::insert(
+                &mut virtual_machine,
+                &mmu,
+                address,
+            )?),
+            None => None,
+        };
+
+        // Hardware breakpoints leave guest memory untouched
+        let mut hw_breakpoints = HwBreakpoints::new(&mut vcpu)?;
+        for &address in &config.hw_breakpoints {
+            let slot = hw_breakpoints.set_hw_breakpoint(&mut vcpu, address)?;
+            log::info!("Hardware breakpoint {slot} set at {address:#x}");
+        }
+
+        Ok(Self {
+            watchdog,
+            break_request,
+            config,
+            vm: virtual_machine,
+            mmu,
+            mmio: mmio_manager,
+            debugger,
+            vcpu,
+            manifest,
+            vcpu_config,
+            reset_state,
+            virtual_clock,
+            clock,
+            vtimer_fired: false,
+            ptimer,
+            sysregs,
+            pmu,
+            injector,
+            break_cancel_pending: false,
+            stop_at,
+            hw_breakpoints,
+            // Optionally trace the first instructions one at a time
+            steps_requested: config.steps,
+            steps_remaining: config.steps,
+            fault_loop: FaultLoopDetector::new(FAULT_LOOP_LIMIT),
+        })
+    }
+
+    /// Run the guest until it stops, then `finish`
+    pub fn run(mut self) -> Result<VmExit, SimppleError> {
+        loop {
+            if let StepOutcome::Exit(exit) = self.step()? {
+                self.finish()?;
+                return Ok(exit);
+            }
+        }
+    }
+
+    /// Run until PC reaches `pc`, like `BootConfig::run_until`.
+    ///
+    /// Returns `VmExit::ReachedAddress(pc)`, or the exit that stopped the
+    /// guest first. Any earlier stop address is replaced.
+    pub fn run_until(&mut self, pc: u64) -> Result<VmExit, SimppleError> {
+        if let Some(breakpoint) = self.stop_at.take() {
+            breakpoint.remove(&mut self.vm, &self.mmu)?;
+        }
+        self.stop_at = Some(TemporaryBreakpoint::insert(&mut self.vm, &self.mmu, pc)?);
+        loop {
+            if let StepOutcome::Exit(exit) = self.step()? {
+                // Don't leave the BRK behind when the guest stopped elsewhere
+                if let Some(breakpoint) = self.stop_at.take() {
+                    breakpoint.remove(&mut self.vm, &self.mmu)?;
+                }
+                return Ok(exit);
+            }
+        }
+    }
+
+    /// Single-step up to `count` instructions.
+    ///
+    /// Returns how many were stepped and `VmExit::Stepped(count)`, or the
+    /// exit that stopped the guest early. An instruction that stops the
+    /// guest, such as a halting HVC, counts as stepped.
+    pub fn step_n(&mut self, count: u64) -> Result<(u64, VmExit), SimppleError> {
+        self.steps_requested = count;
+        self.steps_remaining = count;
+        while self.steps_remaining > 0 {
+            if let StepOutcome::Exit(exit) = self.step()? {
+                return Ok((count - self.steps_remaining, exit));
+            }
+        }
+        Ok((count, VmExit::Stepped(count)))
+    }
+
+    /// Run the vCPU until its next exit and emulate it.
+    ///
+    /// While single-stepping (`BootConfig::steps`) this is one instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, SimppleError> {
+        if self.config.stop_after_steps && self.steps_remaining == 0 {
+            return Ok(StepOutcome::Exit(VmExit::Stepped(self.steps_requested)));
+        }
+        self.injector.deliver_pending(&mut self.vcpu)?;
+        // Unmask the vtimer once the guest has handled the tick that fired it
+        if self.vtimer_fired
+            && !ctl_asserts_interrupt(
+                self.vcpu
+                    .get_system_register(SystemRegister::CNTV_CTL_EL0)?,
+            )
+        {
+            self.vcpu.set_vtimer_mask(false)?;
+            self.vtimer_fired = false;
+        }
+        let device_irq = self.mmio.irq_pending();
+        let timer_irq = self.vtimer_fired || self.ptimer.poll(self.clock.now());
+        self.vcpu
+            .set_pending_interrupt(InterruptType::IRQ, timer_irq || device_irq)?;
+        let armed_step = match self.steps_remaining {
+            0 => None,
+            _ => Some(
+                self.config
+                    .step_mode
+                    .arm(&mut self.vm, &mut self.vcpu, &self.mmu)?,
+            ),
+        };
+        let result = self.vcpu.run()?;
+        let exception = ExceptionInfo::from_exit(self.vcpu.get_register(Register::PC)?, &result);
+        self.fault_loop.begin_exit();
+        if let Some((virtual_clock, step)) = &self.virtual_clock {
+            virtual_clock.advance(*step);
+        }
+
+        // Undo the step before any early return, and count the instruction
+        // unless the vCPU was stopped before it ran
+        let stepped = match armed_step {
+            Some(step) => {
+                if retires_instruction(&result) {
+                    self.steps_remaining -= 1;
+                }
+                step.finish(&mut self.vm, &mut self.vcpu, &self.mmu, exception.as_ref())?
+            }
+            None => false,
+        };
+
+        if let Some(watchdog) = &self.watchdog {
+            if watchdog.fired() {
+                log::error!("Guest timed out, PC appears to be stuck");
+                self.debugger
+                    .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                return Ok(StepOutcome::Exit(VmExit::Timeout));
+            }
+            watchdog.pet();
+        }
+
+        if self.break_request.as_ref().is_some_and(BreakRequest::take) {
+            self.clock.pause();
+            log::info!("Break requested, guest state:");
+            self.debugger
+                .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+            resume_guest_time(&self.clock, &mut self.vcpu)?;
+            // The forced exit may still be pending if the guest trapped first
+            self.break_cancel_pending = true;
+        }
+
+        // Any other exit ends the step too, the trapped instruction is emulated below
+        if stepped {
+            let pc_addr = self.vcpu.get_register(Register::PC)?;
+            self.debugger
+                .decode(&self.mmu.read_bytes(&self.vm, pc_addr, 4)?, pc_addr)?;
+            return Ok(StepOutcome::Continue);
+        }
+
+        match (result, exception) {
+            (VirtualCpuExitReason::Cancelled, _) if self.break_cancel_pending => {
+                // Nothing was executed, resume without advancing PC
+                self.break_cancel_pending = false;
+                return Ok(StepOutcome::Continue);
+            }
+            (_, Some(exception)) => {
+                log::trace!("Exception: {exception}");
+                match exception.class {
+                    ExceptionClass::DataAbortLowerEl | ExceptionClass::DataAbortSameEl => {
+                        let iss = DataAbortISS::from_raw(exception.iss_raw);
+                        if let Some(iss2) = exception.esr.data_abort_iss2()
+                            && iss2.raw() != 0
+                        {
+                            log::warn!("Data abort carries extra syndrome: {iss2:?}");
+                        }
+                        log::trace!(
+                            target: "mmio",
+                            "{}",
+                            iss.describe(exception.fault_pa)
+                        );
+
+                        if iss.is_write()
+                            && self
+                                .mmu
+                                .segment_permission(exception.fault_pa)
+                                .is_some_and(|p| !p.contains(MemoryPermission::WRITE))
+                        {
+                            self.debugger
+                                .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                            self.debugger.print_faulting_instruction(
+                                &self.vm,
+                                &mut self.vcpu,
+                                &self.mmu,
+                            )?;
+                            log::error!(
+                                "Guest wrote to read-only memory: {}",
+                                iss.describe(exception.fault_pa)
+                            );
+                            return Ok(StepOutcome::Exit(VmExit::Halted));
+                        }
+
+                        // Without ISV the register and size fields are UNKNOWN, so the
+                        // access is decoded from the instruction itself
+                        let (iss, writeback) = if iss.is_valid() {
+                            (iss, None)
+                        } else {
+                            match self.decode_access(exception.pc) {
+                                Some(decoded) => decoded,
+                                None => {
+                                    self.debugger.print_debug_info(
+                                        &self.vm,
+                                        &mut self.vcpu,
+                                        &self.mmu,
+                                    )?;
+                                    self.debugger.print_faulting_instruction(
+                                        &self.vm,
+                                        &mut self.vcpu,
+                                        &self.mmu,
+                                    )?;
+                                    log::error!(
+                                        "Data abort without an instruction syndrome, and the \
+                                         instruction is not a load or store that can be \
+                                         emulated: {}",
+                                        iss.describe(exception.fault_pa)
+                                    );
+                                    return Ok(StepOutcome::Exit(VmExit::Halted));
+                                }
+                            }
+                        };
+
+                        let address = exception.fault_pa;
+                        let mmio_error = match iss.is_write() {
+                            true => {
+                                let value =
+                                    get_register_value(&mut self.vcpu, iss.access_register())?;
+                                self.mmio
+                                    .handle_write_dma(
+                                        address,
+                                        iss.access_size()?.into(),
+                                        value,
+                                        &mut GuestDma::new(&mut self.vm, &self.mmu),
+                                    )
+                                    .err()
+                            }
+                            false => {
+                                let mmio_result = self.mmio.handle_read_dma(
+                                    address,
+                                    iss.access_size()?.into(),
+                                    &mut GuestDma::new(&mut self.vm, &self.mmu),
+                                );
+                                match mmio_result {
+                                    Ok(value) => {
+                                        set_register_value(
+                                            &mut self.vcpu,
+                                            iss.access_register(),
+                                            iss.extend(value),
+                                        )?;
+                                        None
+                                    }
+                                    Err(e) => Some(e),
+                                }
+                            }
+                        };
+                        // Unless the guest is halted the instruction is skipped, as if
+                        // the access went through, so its base register moves on too
+                        if let Some(writeback) = writeback {
+                            writeback.apply(&mut self.vcpu)?;
+                        }
+
+                        if let Some(e) = mmio_error {
+                            let direction = if iss.is_write() {
+                                "write to"
+                            } else {
+                                "read from"
+                            };
+                            // The alternate form follows the chain down to the device's own error
+                            log::error!(
+                                "{:#}: invalid {direction} {address:#0x}",
+                                anyhow::Error::from(e)
+                            );
+                            if self.fault_loop.record(&exception) {
+                                return Ok(StepOutcome::Exit(self.fault_loop_exit(&exception)?));
+                            }
+                            match self.mmio.mmio_fault_policy() {
+                                MmioFaultPolicy::Ignore => {
+                                    if !iss.is_write() {
+                                        let _ = self.debugger.print_debug_info(
+                                            &self.vm,
+                                            &mut self.vcpu,
+                                            &self.mmu,
+                                        );
+                                    }
+                                }
+                                MmioFaultPolicy::Halt => {
+                                    self.debugger.print_debug_info(
+                                        &self.vm,
+                                        &mut self.vcpu,
+                                        &self.mmu,
+                                    )?;
+                                    return Ok(StepOutcome::Exit(VmExit::MmioFault(address)));
+                                }
+                                MmioFaultPolicy::InjectSError => {
+                                    // The SError is taken after the access, like an asynchronous abort
+                                    self.skip_instruction(&exception)?;
+                                    self.injector
+                                        .inject_serror(&mut self.vcpu, SERROR_ISS_UNCONTAINABLE)?;
+                                    return Ok(StepOutcome::Continue);
+                                }
+                            }
+                        }
+                    }
+                    ExceptionClass::BrkAArch64 => {
+                        let pc_addr = exception.pc;
+                        if let Some(breakpoint) = self
+                            .stop_at
+                            .take_if(|breakpoint| breakpoint.address() == pc_addr)
+                        {
+                            breakpoint.remove(&mut self.vm, &self.mmu)?;
+                            self.debugger
+                                .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                            log::info!("Reached stop address {pc_addr:#x}");
+                            return Ok(StepOutcome::Exit(VmExit::ReachedAddress(pc_addr)));
+                        }
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        log::error!("Unexpected BRK at {pc_addr:#x}");
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                    ExceptionClass::BreakpointLowerEl | ExceptionClass::BreakpointSameEl => {
+                        // Breakpoints are taken before the instruction, PC is the address
+                        let pc_addr = exception.pc;
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        match self.hw_breakpoints.host_slot(pc_addr) {
+                            Some(slot) => {
+                                self.hw_breakpoints.clear(&mut self.vcpu, slot)?;
+                                log::info!("Hit hardware breakpoint {slot} at {pc_addr:#x}");
+                                return Ok(StepOutcome::Exit(VmExit::ReachedAddress(pc_addr)));
+                            }
+                            None => {
+                                // No breakpoint syndrome can be injected, so the guest's
+                                // debugger sees an UNDEFINED instruction instead
+                                log::warn!("Guest hardware breakpoint hit at {pc_addr:#x}");
+                                if self.fault_loop.record(&exception) {
+                                    return Ok(StepOutcome::Exit(
+                                        self.fault_loop_exit(&exception)?,
+                                    ));
+                                }
+                                self.injector.inject_undefined(&mut self.vcpu)?;
+                                return Ok(StepOutcome::Continue);
+                            }
+                        }
+                    }
+                    ExceptionClass::HvcAArch64 => {
+                        if let Some(hypercall) =
+                            self.config.hypercalls.get(exception.iss_raw as u16)
+                        {
+                            hypercall(&mut HypercallContext {
+                                vm: &self.vm,
+                                vcpu: &mut self.vcpu,
+                                mmu: &self.mmu,
+                                debugger: &self.debugger,
+                            })?;
+                            // PC already points past the HVC
+                            return Ok(StepOutcome::Continue);
+                        }
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        log::info!("HVC instruction executed successfully.");
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                    ExceptionClass::SmcAArch64 => {
+                        let function_id = self.vcpu.get_register(Register::X0)?;
+                        let argument = self.vcpu.get_register(Register::X1)?;
+                        match crate::psci::handle_call(function_id, argument) {
+                            PsciAction::Return(value) => {
+                                self.vcpu.set_register(Register::X0, value)?
+                            }
+                            PsciAction::CpuOff | PsciAction::SystemOff => {
+                                log::info!("Guest powered off through PSCI");
+                                return Ok(StepOutcome::Exit(VmExit::PoweredOff));
+                            }
+                            PsciAction::SystemReset => {
+                                log::info!("Guest requested a system reset through PSCI");
+                                self.reset()?;
+                                return Ok(StepOutcome::Continue);
+                            }
+                        }
+                    }
+                    ExceptionClass::TrappedSysregAArch64 => {
+                        let iss = SysRegAbortISS::from_raw(exception.iss_raw);
+                        let gp_register = iss.access_register();
+
+                        let system_register = match iss
+                            .system_register()
+                            .or_else(|e| self.config.raz_wi.lookup(iss.encoding()).ok_or(e))
+                        {
+                            Ok(system_register) => system_register,
+                            Err(e) => {
+                                log::error!(
+                                    target: "sysreg",
+                                    "{e}, the access is UNDEFINED: {}",
+                                    iss.describe()
+                                );
+                                if self.fault_loop.record(&exception) {
+                                    return Ok(StepOutcome::Exit(
+                                        self.fault_loop_exit(&exception)?,
+                                    ));
+                                }
+                                self.injector.inject_undefined(&mut self.vcpu)?;
+                                return Ok(StepOutcome::Continue);
+                            }
+                        };
+
+                        let pstate = SpsrEl3::from_raw(self.vcpu.get_register(Register::CPSR)?);
+                        let el0_denied_by = if pstate.exception_level() != 0 {
+                            None
+                        } else if !self.pmu.el0_permits(system_register, iss.is_write()) {
+                            Some("PMUSERENR_EL0")
+                        } else if !cntkctl_el0_permits(
+                            self.sysregs.read(EmulatedSystemRegister::CntkctlEl1),
+                            system_register,
+                        ) {
+                            Some("CNTKCTL_EL1")
+                        } else {
+                            None
+                        };
+                        if let Some(control) = el0_denied_by {
+                            // The access is UNDEFINED, nothing is emulated
+                            log::warn!(
+                                target: "sysreg",
+                                "EL0 access not permitted by {control}: {}",
+                                iss.describe()
+                            );
+                            if self.fault_loop.record(&exception) {
+                                return Ok(StepOutcome::Exit(self.fault_loop_exit(&exception)?));
+                            }
+                            self.injector.inject_undefined(&mut self.vcpu)?;
+                            return Ok(StepOutcome::Continue);
+                        }
+
+                        let value = if iss.is_write() {
+                            let value = get_register_value(&mut self.vcpu, gp_register)?;
+                            match system_register {
+                                // The vCPU runs the virtual timer itself, these accesses
+                                // only trap if the hypervisor is told to trap them
+                                EmulatedSystemRegister::CntvCtlEl0 => self
+                                    .vcpu
+                                    .set_system_register(SystemRegister::CNTV_CTL_EL0, value)?,
+                                EmulatedSystemRegister::CntvCvalEl0 => self
+                                    .vcpu
+                                    .set_system_register(SystemRegister::CNTV_CVAL_EL0, value)?,
+                                EmulatedSystemRegister::CntpCtlEl0 => self.ptimer.write_ctl(value),
+                                EmulatedSystemRegister::CntpCvalEl0 => self.ptimer.set_cval(value),
+                                EmulatedSystemRegister::CntpTvalEl0 => self.ptimer.set_tval(value),
+                                EmulatedSystemRegister::CntkctlEl1
+                                | EmulatedSystemRegister::TpidrEl0
+                                | EmulatedSystemRegister::TpidrEl1
+                                | EmulatedSystemRegister::TpidrroEl0
+                                | EmulatedSystemRegister::MdscrEl1
+                                | EmulatedSystemRegister::OslarEl1
+                                | EmulatedSystemRegister::OslsrEl1
+                                | EmulatedSystemRegister::MidrEl1
+                                | EmulatedSystemRegister::MpidrEl1 => {
+                                    self.sysregs.write(system_register, value)
+                                }
+                                // Counters are read-only, writes are ignored. CNTFRQ_EL0
+                                // is only writable at the highest EL
+                                EmulatedSystemRegister::CntfrqEl0
+                                | EmulatedSystemRegister::CntpCtEl0
+                                | EmulatedSystemRegister::CntvCtEl0
+                                | EmulatedSystemRegister::CntpCtSsEl0
+                                | EmulatedSystemRegister::CntvCtSsEl0 => {}
+                                EmulatedSystemRegister::CpacrEl1 => self
+                                    .vcpu
+                                    .set_system_register(SystemRegister::CPACR_EL1, value)?,
+                                EmulatedSystemRegister::PmcrEl0 => self.pmu.write_pmcr(value),
+                                EmulatedSystemRegister::PmcntensetEl0 => {
+                                    self.pmu.set_counter_enables(value)
+                                }
+                                EmulatedSystemRegister::PmcntenclrEl0 => {
+                                    self.pmu.clear_counter_enables(value)
+                                }
+                                EmulatedSystemRegister::PmccntrEl0 => {
+                                    self.pmu.set_cycle_counter(value)
+                                }
+                                EmulatedSystemRegister::PmuserenrEl0 => self.pmu.set_userenr(value),
+                                EmulatedSystemRegister::ImplementationDefined(_) => {}
+                            }
+                            value
+                        } else {
+                            let value = match system_register {
+                                EmulatedSystemRegister::CntfrqEl0 => self.clock.frequency(),
+                                // No virtual offset is applied to a trapped CNTVCT_EL0
+                                EmulatedSystemRegister::CntpCtEl0
+                                | EmulatedSystemRegister::CntpCtSsEl0
+                                | EmulatedSystemRegister::CntvCtEl0
+                                | EmulatedSystemRegister::CntvCtSsEl0 => self.clock.now(),
+                                EmulatedSystemRegister::CntvCtlEl0 => self
+                                    .vcpu
+                                    .get_system_register(SystemRegister::CNTV_CTL_EL0)?,
+                                EmulatedSystemRegister::CntvCvalEl0 => self
+                                    .vcpu
+                                    .get_system_register(SystemRegister::CNTV_CVAL_EL0)?,
+                                EmulatedSystemRegister::CntpCtlEl0 => self.ptimer.read_ctl(),
+                                EmulatedSystemRegister::CntpCvalEl0 => self.ptimer.cval(),
+                                EmulatedSystemRegister::CntpTvalEl0 => self.ptimer.tval(),
+                                EmulatedSystemRegister::CntkctlEl1
+                                | EmulatedSystemRegister::TpidrEl0
+                                | EmulatedSystemRegister::TpidrEl1
+                                | EmulatedSystemRegister::TpidrroEl0
+                                | EmulatedSystemRegister::MdscrEl1
+                                | EmulatedSystemRegister::OslarEl1
+                                | EmulatedSystemRegister::OslsrEl1
+                                | EmulatedSystemRegister::MidrEl1
+                                | EmulatedSystemRegister::MpidrEl1 => {
+                                    self.sysregs.read(system_register)
+                                }
+                                EmulatedSystemRegister::CpacrEl1 => {
+                                    self.vcpu.get_system_register(SystemRegister::CPACR_EL1)?
+                                }
+                                EmulatedSystemRegister::PmcrEl0 => self.pmu.read_pmcr(),
+                                EmulatedSystemRegister::PmcntensetEl0
+                                | EmulatedSystemRegister::PmcntenclrEl0 => {
+                                    self.pmu.counter_enables()
+                                }
+                                EmulatedSystemRegister::PmccntrEl0 => self.pmu.cycle_counter(),
+                                EmulatedSystemRegister::PmuserenrEl0 => self.pmu.userenr(),
+                                EmulatedSystemRegister::ImplementationDefined(_) => 0,
+                            };
+                            set_register_value(&mut self.vcpu, gp_register, value)?;
+                            value
+                        };
+
+                        if log::log_enabled!(target: "sysreg", log::Level::Info) {
+                            let text = self
+                                .debugger
+                                .disassemble_sysreg(&iss)
+                                .unwrap_or_else(|_| iss.describe());
+                            log::info!(
+                                target: "sysreg",
+                                "{text} [{system_register}] = {value:#x}"
+                            );
+                        }
+                    }
+                    ExceptionClass::TrappedMcrMrcCp15 | ExceptionClass::TrappedMcrMrcCp14 => {
+                        let iss = CoprocRegAbortISS::from_raw(exception.iss_raw);
+                        let coproc = match exception.class {
+                            ExceptionClass::TrappedMcrMrcCp15 => Coprocessor::Cp15,
+                            _ => Coprocessor::Cp14,
+                        };
+
+                        let Some(register) = iss.access_register() else {
+                            log::error!(
+                                "AArch32 coprocessor access through APSR_nzcv, the access is \
+                                 UNDEFINED: {}",
+                                iss.describe(coproc)
+                            );
+                            if self.fault_loop.record(&exception) {
+                                return Ok(StepOutcome::Exit(self.fault_loop_exit(&exception)?));
+                            }
+                            self.injector.inject_undefined(&mut self.vcpu)?;
+                            return Ok(StepOutcome::Continue);
+                        };
+
+                        // No AArch32 coprocessor registers are emulated yet: log the
+                        // access and treat it as RAZ/WI so the guest can make progress.
+                        log::warn!(
+                            "Unhandled AArch32 coprocessor access: {} ({:?})",
+                            iss.describe(coproc),
+                            iss.condition()
+                        );
+                        if !iss.is_write() {
+                            set_register_value(&mut self.vcpu, register, 0)?;
+                        }
+                    }
+                    ExceptionClass::TrappedEret => {
+                        let kind = EretISS::from_raw(exception.iss_raw).kind();
+                        let elr = self.vcpu.get_system_register(SystemRegister::ELR_EL1)?;
+                        let saved = SpsrEl3::from_raw(
+                            self.vcpu.get_system_register(SystemRegister::SPSR_EL1)?,
+                        );
+                        let current = SpsrEl3::from_raw(self.vcpu.get_register(Register::CPSR)?);
+                        log::info!(
+                            "{kind:?} to EL{} @ {elr:#x} (SPSR_EL1 = {:#x})",
+                            saved.exception_level(),
+                            saved.raw()
+                        );
+                        if kind != EretKind::Eret {
+                            // Pointer authentication is not emulated
+                            log::warn!("ELR_EL1 used without authentication");
+                        }
+
+                        let pstate = saved.exception_return(current, HVF_MAX_EXCEPTION_LEVEL);
+                        for reason in saved.illegal_return_reasons(
+                            current.exception_level(),
+                            HVF_MAX_EXCEPTION_LEVEL,
+                        ) {
+                            log::warn!("Illegal exception return: {reason}");
+                        }
+
+                        // Perform the return ourselves, PC must not be advanced
+                        self.vcpu.set_register(Register::CPSR, pstate.raw())?;
+                        self.vcpu.set_register(Register::PC, elr)?;
+                        return Ok(StepOutcome::Continue);
+                    }
+                    ExceptionClass::TrappedSimdFp | ExceptionClass::TrappedSve => {
+                        let sve = exception.class == ExceptionClass::TrappedSve;
+                        let feature = if sve { "SVE" } else { "FP/SIMD" };
+                        let mut cpacr = CpacrEl1::from_raw(
+                            self.vcpu.get_system_register(SystemRegister::CPACR_EL1)?,
+                        );
+                        let el = SpsrEl3::from_raw(self.vcpu.get_register(Register::CPSR)?)
+                            .exception_level();
+
+                        if !self.config.lazy_fp {
+                            self.debugger
+                                .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                            self.debugger.print_faulting_instruction(
+                                &self.vm,
+                                &mut self.vcpu,
+                                &self.mmu,
+                            )?;
+                            log::error!(
+                                "{feature} trapped at EL{el} because CPACR_EL1 disables it \
+                                 (FPEN = {:#04b}, ZEN = {:#04b})",
+                                cpacr.fpen(),
+                                cpacr.zen()
+                            );
+                            return Ok(StepOutcome::Exit(VmExit::Halted));
+                        }
+
+                        // Enable the feature and restart the instruction, PC must not be advanced
+                        if self.fault_loop.record(&exception) {
+                            return Ok(StepOutcome::Exit(self.fault_loop_exit(&exception)?));
+                        }
+                        log::info!("Lazily enabling {feature} at EL{el}");
+                        cpacr.set_fpen(CpacrEl1::TRAP_NONE);
+                        if sve {
+                            cpacr.set_zen(CpacrEl1::TRAP_NONE);
+                        }
+                        self.vcpu
+                            .set_system_register(SystemRegister::CPACR_EL1, cpacr.raw())?;
+                        return Ok(StepOutcome::Continue);
+                    }
+                    ExceptionClass::TrappedWfInstruction => {
+                        // WFI and WFE may complete early, a deterministic clock only moves
+                        // between exits so the guest is let go at once
+                        if self.virtual_clock.is_none() {
+                            // The vCPU's own virtual timer counts the host counter minus its offset
+                            let vtimer_ctl = self
+                                .vcpu
+                                .get_system_register(SystemRegister::CNTV_CTL_EL0)?;
+                            let vtimer_cval = self
+                                .vcpu
+                                .get_system_register(SystemRegister::CNTV_CVAL_EL0)?;
+                            let vtimer_offset = self.vcpu.get_vtimer_offset()?;
+                            let woke = park_vcpu(self.config.wfi_timeout, || {
+                                // An idle guest isn't a stuck one
+                                if let Some(watchdog) = &self.watchdog {
+                                    watchdog.pet();
+                                }
+                                let vtimer_count = get_cntpct_el0().wrapping_sub(vtimer_offset);
+                                self.vtimer_fired
+                                    || fires_at(vtimer_ctl, vtimer_cval, vtimer_count)
+                                    || self.mmio.irq_pending()
+                                    || self.ptimer.poll(self.clock.now())
+                                    || self
+                                        .break_request
+                                        .as_ref()
+                                        .is_some_and(BreakRequest::pending)
+                            });
+                            if !woke {
+                                self.debugger.print_debug_info(
+                                    &self.vm,
+                                    &mut self.vcpu,
+                                    &self.mmu,
+                                )?;
+                                log::error!(
+                                    "Guest waited for an interrupt at {:#x} with nothing to wake it",
+                                    exception.pc
+                                );
+                                return Ok(StepOutcome::Exit(VmExit::Idle(exception.pc)));
+                            }
+                        }
+                    }
+                    ExceptionClass::PcAlignmentFault => {
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        // FAR holds the misaligned PC; usually a corrupted return address
+                        log::error!(
+                            "PC not 4-byte aligned: {:#x} (LR = {:#x})",
+                            exception.fault_va,
+                            self.vcpu.get_register(Register::X30)?
+                        );
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                    ExceptionClass::SpAlignmentFault => {
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        self.debugger.print_faulting_instruction(
+                            &self.vm,
+                            &mut self.vcpu,
+                            &self.mmu,
+                        )?;
+                        let sp = get_stack_pointer(&mut self.vcpu)?;
+                        self.debugger
+                            .print_stack(&self.vm, &self.mmu, sp, STACK_DUMP_WORDS)?;
+                        log::error!("SP not 16-byte aligned: {sp:#x}");
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                    ExceptionClass::SError => {
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        log::error!("Guest raised an SError (ISS = {:#x})", exception.iss_raw);
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                    ExceptionClass::IllegalExecutionState => {
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+
+                        // The faulting ERET ran at the current EL and restored SPSR_EL1
+                        let pstate = SpsrEl3::from_raw(self.vcpu.get_register(Register::CPSR)?);
+                        let saved = SpsrEl3::from_raw(
+                            self.vcpu.get_system_register(SystemRegister::SPSR_EL1)?,
+                        );
+                        log::error!(
+                            "Illegal execution state at EL{} (saved SPSR_EL1 = {:#x})",
+                            pstate.exception_level(),
+                            saved.raw()
+                        );
+                        let reasons = saved.illegal_return_reasons(
+                            pstate.exception_level(),
+                            HVF_MAX_EXCEPTION_LEVEL,
+                        );
+                        if reasons.is_empty() {
+                            log::error!("  no illegal field found in SPSR_EL1");
+                        }
+                        for reason in reasons {
+                            log::error!("  likely cause: {reason}");
+                        }
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                    _ => {
+                        self.debugger
+                            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                        self.debugger.print_faulting_instruction(
+                            &self.vm,
+                            &mut self.vcpu,
+                            &self.mmu,
+                        )?;
+                        log::error!("unexpected exception: {exception}");
+                        return Ok(StepOutcome::Exit(VmExit::Halted));
+                    }
+                };
+
+                // Arms that get here emulated the trapped instruction, the rest
+                // returned early to retry it, resume elsewhere or stop
+                self.skip_instruction(&exception)?;
+            }
+            (VirtualCpuExitReason::VTimerActivated, None) => {
+                // The framework masks the vtimer until it is unmasked again, the
+                // IRQ stays pending until the guest clears the timer condition
+                log::trace!("Virtual timer fired");
+                self.vtimer_fired = true;
+                return Ok(StepOutcome::Continue);
+            }
+            (reason, None) => {
+                self.debugger
+                    .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+                self.debugger
+                    .print_faulting_instruction(&self.vm, &mut self.vcpu, &self.mmu)?;
+                log::error!("Unexpected exit reason: {reason:#?}");
+                return Ok(StepOutcome::Exit(VmExit::Halted));
+            }
+        };
+
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Deliver buffered MMIO writes and save the MMIO recording, if any
+    pub fn finish(&mut self) -> Result<(), SimppleError> {
+        self.mmio.flush_coalesced()?;
+
+        if self.steps_remaining > 0 {
+            log::info!(
+                "Stopped after {} of {} steps",
+                self.steps_requested - self.steps_remaining,
+                self.steps_requested
+            );
+        }
+
+        if let (Some(path), Some(log)) = (&self.config.mmio_record, self.mmio.take_recording()) {
+            log.save(path)?;
+            log::info!(
+                "Saved {} MMIO accesses to {}",
+                log.entries().len(),
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn vm(&self) -> &VirtualMachine {
+        &self.vm
+    }
+
+    pub fn mmu(&self) -> &SharedMemory {
+        &self.mmu
+    }
+
+    pub fn vcpu(&mut self) -> &mut VirtualCpu {
+        &mut self.vcpu
+    }
+
+    pub fn mmio(&mut self) -> &mut MmioManager {
+        &mut self.mmio
+    }
+
+    /// Recent output of the first PL011, if `PlatformConfig::console_history` is set
+    pub fn console_history(&self) -> Option<&ConsoleHistory> {
+        self.debugger.console_history()
+    }
+
+    /// Map `contents` as ROM at `base`, see `SharedMemory::add_rom_segment`
+    pub fn add_rom(&mut self, base: u64, contents: &[u8]) -> Result<(), SimppleError> {
+        self.mmu.add_rom_segment(&mut self.vm, base, contents)
+    }
+
+    /// Start over from the entry point, as after a PSCI SYSTEM_RESET
+    fn reset(&mut self) -> Result<(), SimppleError> {
+        // The images are reloaded, keep the stop address breakpoint out of the way
+        let stop_address = match self.stop_at.take() {
+            Some(breakpoint) => {
+                let address = breakpoint.address();
+                breakpoint.remove(&mut self.vm, &self.mmu)?;
+                Some(address)
+            }
+            None => None,
+        };
+        self.manifest.load(&mut self.vm, &self.mmu)?;
+        if let Some(address) = stop_address {
+            self.stop_at = Some(TemporaryBreakpoint::insert(
+                &mut self.vm,
+                &self.mmu,
+                address,
+            )?);
+        }
+
+        self.mmio.reset_devices();
+        self.ptimer = PhysicalTimer::with_clock(self.clock.clone());
+        self.sysregs = SystemRegisterFile::with_identity(self.config.cpu_identity);
+        self.pmu = Pmu::with_clock(self.clock.clone());
+        self.injector = ExceptionInjector::new();
+
+        write_gp_registers(&mut self.vcpu, &[0; 32])?;
+        for &(register, value) in &self.reset_state {
+            self.vcpu.set_system_register(register, value)?;
+        }
+        self.vtimer_fired = false;
+        self.vcpu.set_vtimer_mask(false)?;
+        self.vcpu_config.build_and_apply(&mut self.vcpu)?;
+        Ok(())
+    }
+
+    /// Decode the load or store at `pc`, for a data abort without ISV
+    fn decode_access(&self, pc: u64) -> Option<(DataAbortISS, Option<Writeback>)> {
+        let bytes = self.mmu.read_bytes(&self.vm, pc, 4).ok()?;
+        let insn = u32::from_le_bytes(bytes.try_into().ok()?);
+        let iss = DataAbortISS::from_instruction(insn)?;
+        log::debug!(target: "mmio", "Decoded the access of {insn:#010x} at {pc:#x}");
+        Some((iss, Writeback::from_instruction(insn)))
+    }
+
+    /// Resume after the instruction that trapped with `exception`
+    fn skip_instruction(&mut self, exception: &ExceptionInfo) -> Result<(), SimppleError> {
+        let pc_addr = self.vcpu.get_register(Register::PC)?;
+        let next = pc_addr + exception.esr.instruction_length();
+        self.vcpu.set_register(Register::PC, next)?;
+        Ok(())
+    }
+
+    /// Report a fault loop and return the exit for it
+    fn fault_loop_exit(&mut self, exception: &ExceptionInfo) -> Result<VmExit, SimppleError> {
+        self.debugger
+            .print_debug_info(&self.vm, &mut self.vcpu, &self.mmu)?;
+        self.debugger
+            .print_faulting_instruction(&self.vm, &mut self.vcpu, &self.mmu)?;
+        log::error!(
+            "Guest took the same fault {} times in a row: {exception}",
+            self.fault_loop.count()
+        );
+        Ok(VmExit::FaultLoop {
+            pc: exception.pc,
+            esr: exception.esr.raw(),
+        })
+    }
+}
+
+/// Whether the vCPU ran an instruction before exiting with `reason`.
+///
+/// Forced exits (a break request or the watchdog) and timer exits stop the
+/// vCPU between instructions.
+fn retires_instruction(reason: &VirtualCpuExitReason) -> bool {
+    !matches!(
+        reason,
+        VirtualCpuExitReason::Cancelled | VirtualCpuExitReason::VTimerActivated
+    )
+}
+
+/// Block a vCPU waiting for an interrupt until `wake` reports one.
+///
+/// Returns false if `timeout` passed first.
+fn park_vcpu(timeout: Option<Duration>, mut wake: impl FnMut() -> bool) -> bool {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        if wake() {
+            return true;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return false;
+        }
+        thread::sleep(WFI_POLL_INTERVAL);
+    }
+}
+
+/// Resume a paused clock, hiding the pause from the vCPU's own CNTVCT_EL0 too
+fn resume_guest_time(clock: &Clock, vcpu: &mut ahvf::VirtualCpu) -> Result<(), SimppleError> {
+    let paused = clock.resume();
+    if paused > 0 {
+        let offset = vcpu.get_vtimer_offset()?;
+        vcpu.set_vtimer_offset(offset.wrapping_add(paused))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ahvf::VirtualCpuExitException;
+
+    #[test]
+    fn test_step_exit_retires_instruction() {
+        let exit = VirtualCpuExitReason::Exception {
+            exception: VirtualCpuExitException {
+                syndrome: 0xcc00_0000, // Software step from a lower EL
+                virtual_address: 0,
+                physical_address: 0,
+            },
+        };
+        assert!(retires_instruction(&exit));
+    }
+
+    #[test]
+    fn test_forced_exit_retires_nothing() {
+        // Break requests and the watchdog both kick the vCPU out of `run()`
+        assert!(!retires_instruction(&VirtualCpuExitReason::Cancelled));
+        assert!(!retires_instruction(&VirtualCpuExitReason::VTimerActivated));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::SimppleError;
+use crate::regs::{IdAa64Mmfr0El1, SpsrEl3};
+use ahvf::{Register, SystemRegister, VirtualCpu};
+
+/// Highest exception level a Hypervisor.framework guest can start at.
+///
+/// EL2 guests need a VM created with nested virtualization enabled (macOS 15
+/// on M3 or later), which `ahvf` doesn't expose, and EL3 is never available.
+pub const HVF_MAX_EXCEPTION_LEVEL: u8 = 1;
+
+/// IPA size of a VM created without a configuration.
+///
+/// Hypervisor.framework defaults to 36 bits whatever the host supports, and
+/// `ahvf` doesn't expose asking for more.
+pub const HVF_DEFAULT_IPA_BITS: u8 = 36;
+
+/// Stack pointer used by the vCPU at entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpSelect {
+    /// SP_EL0, shared with EL0 (the "t" modes, e.g. EL1t)
+    El0,
+    /// SP_ELx, dedicated to the current exception level (the "h" modes, e.g. EL1h)
+    ElX,
+}
+
+/// Which guest accesses exit to the host.
+///
+/// Hypervisor.framework owns HCR_EL2 and the fine-grained trap registers
+/// (HFGRTR_EL2/HFGWTR_EL2) and does not let a VMM program them, so only the
+/// debug controls it exposes can be chosen here. Everything else is fixed:
+///
+/// - The EL1 translation and control registers (SCTLR, TTBRn, TCR, MAIR,
+///   VBAR, ESR, FAR, ...) and the ID registers are context switched by the
+///   framework and always run natively.
+/// - The counters, timers, PMU and implementation-defined registers always
+///   trap and must be emulated, see `EmulatedSystemRegister` and
+///   `RazWiRegisters`.
+/// - MDSCR_EL1, the OS Lock and the breakpoint/watchpoint registers trap only
+///   while `debug_registers` is set. Passing them through is safe as long as
+///   the host doesn't single-step or set hardware breakpoints itself, since
+///   the guest would overwrite the host's settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrapConfig {
+    /// Breakpoint, watchpoint and software step exceptions exit to the host
+    pub debug_exceptions: bool,
+    /// Debug system register accesses exit to the host
+    pub debug_registers: bool,
+}
+
+impl Default for TrapConfig {
+    fn default() -> Self {
+        Self {
+            debug_exceptions: false,
+            debug_registers: true,
+        }
+    }
+}
+
+/// Initial PSTATE and entry point of a vCPU
+///
+/// Defaults to EL1h with all of DAIF masked and cleared condition flags,
+/// entering at address 0.
+#[derive(Clone, Copy, Debug)]
+pub struct VcpuConfig {
+    entry: u64,
+    exception_level: u8,
+    sp: SpSelect,
+    mask_interrupts: bool,
+    traps: TrapConfig,
+    ipa_bits: u8,
+    dtb: Option<u64>,
+}
+
+impl Default for VcpuConfig {
+    fn default() -> Self {
+        Self {
+            entry: 0,
+            exception_level: 1,
+            sp: SpSelect::ElX,
+            mask_interrupts: true,
+            traps: TrapConfig::default(),
+            ipa_bits: HVF_DEFAULT_IPA_BITS,
+            dtb: None,
+        }
+    }
+}
+
+impl VcpuConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Address the vCPU starts executing at
+    pub fn entry(mut self, pc: u64) -> Self {
+        self.entry = pc;
+        self
+    }
+
+    pub fn exception_level(mut self, el: u8) -> Self {
+        self.exception_level = el;
+        self
+    }
+
+    pub fn sp(mut self, sp: SpSelect) -> Self {
+        self.sp = sp;
+        self
+    }
+
+    /// Pass the DTB at `address` in X0, as the Linux arm64 boot protocol expects.
+    ///
+    /// X1-X3 are zeroed too, they are reserved by the protocol.
+    pub fn dtb(mut self, address: u64) -> Self {
+        self.dtb = Some(address);
+        self
+    }
+
+    /// Mask debug, SError, IRQ and FIQ exceptions (PSTATE.DAIF)
+    pub fn mask_interrupts(mut self, mask: bool) -> Self {
+        self.mask_interrupts = mask;
+        self
+    }
+
+    /// Trap guest debug exceptions to the host
+    pub fn trap_debug(mut self, trap: bool) -> Self {
+        self.traps.debug_exceptions = trap;
+        self
+    }
+
+    /// Replace every trap control at once
+    pub fn traps(mut self, traps: TrapConfig) -> Self {
+        self.traps = traps;
+        self
+    }
+
+    /// IPA size of the VM, `HVF_DEFAULT_IPA_BITS` by default.
+    ///
+    /// ID_AA64MMFR0_EL1.PARange is lowered to match, so the guest never
+    /// builds page tables for physical addresses the VM can't map.
+    pub fn ipa_bits(mut self, bits: u8) -> Self {
+        self.ipa_bits = bits;
+        self
+    }
+
+    /// Check the entry EL is one the hypervisor can launch the vCPU at.
+    ///
+    /// Nothing above `HVF_MAX_EXCEPTION_LEVEL` is, so EL2 and EL3 entry
+    /// only exist in `spsr` for now.
+    pub fn validate(&self) -> Result<(), SimppleError> {
+        if self.exception_level > HVF_MAX_EXCEPTION_LEVEL {
+            return Err(SimppleError::UnsupportedExceptionLevel {
+                requested: self.exception_level,
+                supported: HVF_MAX_EXCEPTION_LEVEL,
+            });
+        }
+        Ok(())
+    }
+
+    /// The PSTATE the vCPU will be started with
+    pub fn spsr(&self) -> SpsrEl3 {
+        let mut spsr = SpsrEl3::new();
+        spsr.set_condition_flags(false, false, false, false);
+        spsr.set_interrupt_masks(
+            self.mask_interrupts,
+            self.mask_interrupts,
+            self.mask_interrupts,
+            self.mask_interrupts,
+        );
+        spsr.set_exception_level(self.exception_level);
+        spsr.set_stack_pointer(self.sp == SpSelect::ElX);
+        spsr
+    }
+
+    /// Registers programmed at entry, with their values
+    pub fn entry_registers(&self) -> Vec<(Register, u64)> {
+        let mut registers = vec![
+            (Register::CPSR, self.spsr().raw()),
+            (Register::PC, self.entry),
+        ];
+        if let Some(dtb) = self.dtb {
+            registers.extend([
+                (Register::X0, dtb),
+                (Register::X1, 0),
+                (Register::X2, 0),
+                (Register::X3, 0),
+            ]);
+        }
+        registers
+    }
+
+    /// Program the entry registers and the trap controls into the vCPU
+    pub fn build_and_apply(self, vcpu: &mut VirtualCpu) -> Result<(), SimppleError> {
+        self.validate()?;
+        for (register, value) in self.entry_registers() {
+            vcpu.set_register(register, value)?;
+        }
+        vcpu.set_trap_debug_exceptions(self.traps.debug_exceptions)?;
+        vcpu.set_trap_debug_reg_accesses(self.traps.debug_registers)?;
+
+        let mut mmfr0 =
+            IdAa64Mmfr0El1::from_raw(vcpu.get_system_register(SystemRegister::ID_AA64MMFR0_EL1)?);
+        if mmfr0.check_pa_range(self.ipa_bits).is_err() {
+            log::debug!(
+                "Lowering PARange from {:?} bits to the {}-bit IPA space",
+                mmfr0.pa_bits(),
+                self.ipa_bits
+            );
+            mmfr0.limit_pa_range(self.ipa_bits);
+            vcpu.set_system_register(SystemRegister::ID_AA64MMFR0_EL1, mmfr0.raw())?;
+            // Make sure the hypervisor kept the new value
+            IdAa64Mmfr0El1::from_raw(vcpu.get_system_register(SystemRegister::ID_AA64MMFR0_EL1)?)
+                .check_pa_range(self.ipa_bits)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spsr_matches_hand_written() {
+        let mut spsr = SpsrEl3::new();
+        spsr.set_condition_flags(false, false, false, false);
+        spsr.set_interrupt_masks(true, true, true, true);
+        spsr.set_exception_level(1);
+        spsr.set_stack_pointer(false);
+
+        let config = VcpuConfig::new()
+            .entry(0x0)
+            .exception_level(1)
+            .sp(SpSelect::El0)
+            .mask_interrupts(true)
+            .trap_debug(true);
+        assert_eq!(config.spsr().raw(), spsr.raw());
+        assert_eq!(config.spsr().raw(), 0x3c4);
+    }
+
+    #[test]
+    fn test_default_is_el1h() {
+        let spsr = VcpuConfig::default().spsr();
+        assert_eq!(spsr.m3_0(), SpsrEl3::EL1H);
+        assert_eq!(spsr.exception_level(), 1);
+        assert!(!spsr.stack_pointer_is_el0());
+    }
+
+    #[test]
+    fn test_high_el_entry() {
+        let el2 = VcpuConfig::new().exception_level(2);
+        assert_eq!(el2.spsr().m3_0(), SpsrEl3::EL2H);
+        assert_eq!(el2.spsr().raw(), 0x3c9);
+
+        let el3 = VcpuConfig::new().exception_level(3);
+        assert_eq!(el3.spsr().m3_0(), SpsrEl3::EL3H);
+        assert_eq!(el3.spsr().raw(), 0x3cd);
+
+        // Hypervisor.framework can't start a vCPU above EL1
+        for (config, requested) in [(el2, 2), (el3, 3)] {
+            assert!(matches!(
+                config.validate(),
+                Err(SimppleError::UnsupportedExceptionLevel {
+                    requested: r,
+                    supported: 1
+                }) if r == requested
+            ));
+        }
+    }
+
+    #[test]
+    fn test_dtb_passed_in_x0() {
+        let registers = VcpuConfig::new()
+            .entry(0x8_0000)
+            .dtb(0x4000_0000)
+            .entry_registers();
+        let value = |register| {
+            registers
+                .iter()
+                .find(|(r, _)| *r == register)
+                .map(|(_, value)| *value)
+        };
+        assert_eq!(value(Register::PC), Some(0x8_0000));
+        assert_eq!(value(Register::X0), Some(0x4000_0000));
+        assert_eq!(value(Register::X3), Some(0));
+
+        // Without a DTB the argument registers are left alone
+        assert_eq!(VcpuConfig::new().entry_registers().len(), 2);
+    }
+}
@@ -0,0 +1,20 @@
+/// Why the run loop stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmExit {
+    /// The guest stopped on purpose or hit an unrecoverable exception
+    Halted,
+    /// The guest made no progress before the watchdog deadline
+    Timeout,
+    /// PC reached the requested stop address
+    ReachedAddress(u64),
+    /// This many instructions were stepped, under `BootConfig::stop_after_steps`
+    Stepped(u64),
+    /// A guest MMIO access to this address failed under `MmioFaultPolicy::Halt`
+    MmioFault(u64),
+    /// The guest took the same fault at `pc` with syndrome `esr` repeatedly
+    FaultLoop { pc: u64, esr: u64 },
+    /// The guest turned itself off with PSCI SYSTEM_OFF or CPU_OFF
+    PoweredOff,
+    /// The guest waited for an interrupt at this PC past `BootConfig::wfi_timeout`
+    Idle(u64),
+}
@@ -0,0 +1,63 @@
+use crate::poll_thread::PollThread;
+use ahvf::VirtualCpu;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Upper bound on how long the watchdog thread sleeps between checks
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct WatchdogState {
+    last_progress: Mutex<Instant>,
+    fired: AtomicBool,
+}
+
+/// Wall-clock guard that kicks a vCPU out of `run()` when the guest stops
+/// exiting to the host for longer than the timeout.
+///
+/// Call `pet()` on every exit; once the deadline passes the vCPU is forced
+/// out with `hv_vcpus_exit` and `fired()` turns true.
+pub struct Watchdog {
+    state: Arc<WatchdogState>,
+    _thread: PollThread,
+}
+
+impl Watchdog {
+    pub fn start(vcpu: &VirtualCpu, timeout: Duration) -> Self {
+        let state = Arc::new(WatchdogState {
+            last_progress: Mutex::new(Instant::now()),
+            fired: AtomicBool::new(false),
+        });
+
+        let handle = vcpu.get_handle();
+        let thread_state = state.clone();
+        let thread = PollThread::spawn(POLL_INTERVAL.min(timeout / 4), move || {
+            let idle = thread_state.last_progress.lock().unwrap().elapsed();
+            if idle < timeout {
+                return true;
+            }
+
+            log::error!("vCPU made no progress for {idle:?}, forcing it to exit");
+            thread_state.fired.store(true, Ordering::Release);
+            if let Err(e) = ahvf::vcpus_exit(&[handle]) {
+                log::error!("Failed to force vCPU exit: {e:?}");
+            }
+            false
+        });
+
+        Self {
+            state,
+            _thread: thread,
+        }
+    }
+
+    /// Record that the guest made progress
+    pub fn pet(&self) {
+        *self.state.last_progress.lock().unwrap() = Instant::now();
+    }
+
+    /// Whether the deadline passed and the vCPU was forced out
+    pub fn fired(&self) -> bool {
+        self.state.fired.load(Ordering::Acquire)
+    }
+}
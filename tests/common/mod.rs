@@ -0,0 +1,24 @@
+//! Setup shared by the tests that boot a guest.
+//!
+//! Creating a VM needs Hypervisor.framework and the hypervisor entitlement,
+//! so those tests are ignored by default; run them with
+//! `cargo test -- --ignored`.
+
+use simpple_vm::BootConfig;
+use simpple_vm::platform::PlatformConfig;
+use std::time::Duration;
+
+// Long enough for any test guest, short enough that a hang fails quickly
+const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Boot `firmware` on the default platform, entering at address 0.
+///
+/// The watchdog and the WFI timeout are both set, so a guest that spins or
+/// waits for an interrupt that never comes fails the test instead of
+/// blocking it.
+pub fn guest_config(firmware: Vec<u8>) -> BootConfig {
+    let mut config = BootConfig::new(PlatformConfig::default(), firmware, Vec::new());
+    config.timeout = Some(TEST_TIMEOUT);
+    config.wfi_timeout = Some(TEST_TIMEOUT);
+    config
+}
@@ -0,0 +1,25 @@
+//! A guest's hexdump hypercall on unmapped memory is reported and the
+//! guest resumes instead of halting.
+
+mod common;
+
+use common::guest_config;
+use simpple_vm::asm::assemble;
+use simpple_vm::boot;
+use simpple_vm::vm::VmExit;
+
+// Dump 16 bytes from an address nothing is mapped at, then halt
+const PROGRAM: &str = "
+    movz x0, #0xdead, lsl #16
+    mov x1, #16
+    hvc #0xdec
+    hvc #0
+";
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_hexdump_of_unmapped_memory_resumes() {
+    let config = guest_config(assemble(PROGRAM, 0).unwrap());
+
+    assert_eq!(boot(&config).unwrap(), VmExit::Halted);
+}
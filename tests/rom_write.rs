@@ -0,0 +1,47 @@
+//! A guest store into a ROM segment halts the guest and leaves the ROM
+//! contents intact, while loads from it go through.
+
+mod common;
+
+use ahvf::Register;
+use common::guest_config;
+use simpple_vm::asm::assemble;
+use simpple_vm::runner::{StepOutcome, VmRunner};
+use simpple_vm::vm::VmExit;
+
+// Between the firmware region and RAM of the default platform
+const ROM_BASE: u64 = 0x2000_0000;
+
+const PROGRAM: &str = "
+    movz x1, #0x2000, lsl #16
+    ldr w0, [x1]
+    str w0, [x1]
+    hvc #0
+";
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_guest_write_to_rom_faults() {
+    let config = guest_config(assemble(PROGRAM, 0).unwrap());
+
+    let mut runner = VmRunner::new(&config).unwrap();
+    runner.add_rom(ROM_BASE, &[0xaa, 0xbb, 0xcc, 0xdd]).unwrap();
+    let exit = loop {
+        if let StepOutcome::Exit(exit) = runner.step().unwrap() {
+            break exit;
+        }
+    };
+
+    // Stopped at the store, the load went through
+    assert_eq!(exit, VmExit::Halted);
+    assert_eq!(runner.vcpu().get_register(Register::PC).unwrap(), 8);
+    assert_eq!(
+        runner.vcpu().get_register(Register::X0).unwrap(),
+        0xddcc_bbaa
+    );
+    assert_eq!(
+        runner.mmu().read_bytes(runner.vm(), ROM_BASE, 4).unwrap(),
+        [0xaa, 0xbb, 0xcc, 0xdd]
+    );
+    runner.finish().unwrap();
+}
@@ -0,0 +1,31 @@
+//! `VmRunner::run_until` stops before the target instruction and puts the
+//! original instruction back, so the guest can run on afterwards.
+
+mod common;
+
+use ahvf::Register;
+use common::guest_config;
+use simpple_vm::asm::assemble;
+use simpple_vm::runner::VmRunner;
+use simpple_vm::vm::VmExit;
+
+const PROGRAM: &str = "
+    mov x0, #1
+    mov x0, #2
+    mov x0, #3
+    hvc #0
+";
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_run_until_stops_before_target() {
+    let config = guest_config(assemble(PROGRAM, 0).unwrap());
+
+    let mut runner = VmRunner::new(&config).unwrap();
+    assert_eq!(runner.run_until(8).unwrap(), VmExit::ReachedAddress(8));
+    assert_eq!(runner.vcpu().get_register(Register::PC).unwrap(), 8);
+    assert_eq!(runner.vcpu().get_register(Register::X0).unwrap(), 2);
+
+    // The original instruction is back in place
+    assert_eq!(runner.run().unwrap(), VmExit::Halted);
+}
@@ -0,0 +1,95 @@
+//! `VmRunner` single-steps one instruction per `step`, counts retired
+//! instructions in `step_n`, runs to the end with `run` and restarts the
+//! guest on a PSCI SYSTEM_RESET.
+
+mod common;
+
+use ahvf::Register;
+use common::guest_config;
+use simpple_vm::asm::assemble;
+use simpple_vm::runner::{StepOutcome, VmRunner};
+use simpple_vm::vm::VmExit;
+
+const PROGRAM: &str = "
+    mov x0, #1
+    add x0, x0, #1
+    add x0, x0, #1
+    hvc #0
+";
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_step_runs_one_instruction() {
+    let mut config = guest_config(assemble(PROGRAM, 0).unwrap());
+    config.steps = 3;
+
+    let mut runner = VmRunner::new(&config).unwrap();
+    for expected in 1..=3 {
+        assert_eq!(runner.step().unwrap(), StepOutcome::Continue);
+        assert_eq!(runner.vcpu().get_register(Register::X0).unwrap(), expected);
+        assert_eq!(
+            runner.vcpu().get_register(Register::PC).unwrap(),
+            expected * 4
+        );
+    }
+
+    // Stepping is over, the guest runs on to the HVC
+    assert_eq!(runner.step().unwrap(), StepOutcome::Exit(VmExit::Halted));
+    runner.finish().unwrap();
+}
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_step_n_reports_retired_instructions() {
+    let config = guest_config(assemble(PROGRAM, 0).unwrap());
+
+    let mut runner = VmRunner::new(&config).unwrap();
+    assert_eq!(runner.step_n(3).unwrap(), (3, VmExit::Stepped(3)));
+    assert_eq!(runner.vcpu().get_register(Register::X0).unwrap(), 3);
+
+    // The HVC halts the guest on the first of the five steps
+    assert_eq!(runner.step_n(5).unwrap(), (1, VmExit::Halted));
+    runner.finish().unwrap();
+}
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_run_until_exit() {
+    let config = guest_config(assemble(PROGRAM, 0).unwrap());
+
+    let runner = VmRunner::new(&config).unwrap();
+    assert_eq!(runner.run().unwrap(), VmExit::Halted);
+}
+
+// Counts boots in the first word of RAM, which a reset leaves alone
+const RESET_PROGRAM: &str = "
+    movz x1, #0x4000, lsl #16
+    ldr x2, [x1]
+    add x2, x2, #1
+    str x2, [x1]
+    cmp x2, #2
+    b.eq done
+    movz x0, #0x0009
+    movk x0, #0x8400, lsl #16
+    smc #0
+done:
+    mov x0, x2
+    hvc #0
+";
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_psci_system_reset_restarts_guest() {
+    let config = guest_config(assemble(RESET_PROGRAM, 0).unwrap());
+
+    let mut runner = VmRunner::new(&config).unwrap();
+    let exit = loop {
+        if let StepOutcome::Exit(exit) = runner.step().unwrap() {
+            break exit;
+        }
+    };
+    assert_eq!(exit, VmExit::Halted);
+    // The guest booted twice
+    assert_eq!(runner.vcpu().get_register(Register::X0).unwrap(), 2);
+    runner.finish().unwrap();
+}
@@ -0,0 +1,91 @@
+//! Host accesses to guest memory through `SharedMemory`: write permission
+//! checks, removing and re-adding a segment, and `memset`.
+
+use ahvf::{MemoryPermission, VirtualMachine};
+use simpple_vm::err::MemoryError;
+use simpple_vm::{SharedMemory, SimppleError};
+
+const BASE: u64 = 0x4000_0000;
+const SIZE: usize = 0x10000;
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_write_to_read_only_segment_denied() {
+    let mut vm = VirtualMachine::new(None).unwrap();
+    let mut mmu = SharedMemory::default();
+    mmu.add_segment(&mut vm, BASE, SIZE, MemoryPermission::READ)
+        .unwrap();
+    assert_eq!(
+        mmu.segment_permission(BASE + 0x100),
+        Some(MemoryPermission::READ)
+    );
+
+    assert!(matches!(
+        mmu.write_bytes(&mut vm, BASE + 0x100, &[1, 2, 3, 4]),
+        Err(SimppleError::Memory(MemoryError::PermissionDenied {
+            address: 0x4000_0100,
+            size: 4,
+            access: "write",
+        }))
+    ));
+    assert_eq!(mmu.read_bytes(&vm, BASE + 0x100, 4).unwrap(), [0; 4]);
+
+    // The loader and the debugger can still patch it
+    mmu.write_bytes_privileged(&mut vm, BASE + 0x100, &[1, 2, 3, 4])
+        .unwrap();
+    assert_eq!(mmu.read_bytes(&vm, BASE + 0x100, 4).unwrap(), [1, 2, 3, 4]);
+}
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_segment_can_be_added_again_after_removal() {
+    let mut vm = VirtualMachine::new(None).unwrap();
+    let mut mmu = SharedMemory::default();
+    mmu.add_segment(&mut vm, BASE, SIZE, MemoryPermission::READ_WRITE)
+        .unwrap();
+    mmu.write_bytes(&mut vm, BASE, &[0xff; 4]).unwrap();
+
+    mmu.remove_segment(&mut vm, BASE).unwrap();
+    assert_eq!(mmu.segment_permission(BASE), None);
+    assert!(mmu.read_bytes(&vm, BASE, 4).is_err());
+
+    // The range is free again, and comes back zeroed
+    mmu.add_segment(&mut vm, BASE, SIZE, MemoryPermission::READ_WRITE)
+        .unwrap();
+    assert_eq!(mmu.read_bytes(&vm, BASE, 4).unwrap(), [0; 4]);
+}
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_memset() {
+    let mut vm = VirtualMachine::new(None).unwrap();
+    let mut mmu = SharedMemory::default();
+    mmu.add_segment(&mut vm, BASE, SIZE, MemoryPermission::READ_WRITE)
+        .unwrap();
+    mmu.add_segment(
+        &mut vm,
+        BASE + SIZE as u64,
+        SIZE,
+        MemoryPermission::READ_WRITE,
+    )
+    .unwrap();
+
+    mmu.memset(&mut vm, BASE + 0x10, 0xa5, 8).unwrap();
+    assert_eq!(
+        mmu.read_bytes(&vm, BASE + 0xf, 10).unwrap(),
+        [0, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0]
+    );
+
+    // Nothing to fill, even where nothing is mapped
+    mmu.memset(&mut vm, 0, 0xa5, 0).unwrap();
+
+    // Both segments are mapped, but the range may not cross between them
+    assert!(matches!(
+        mmu.memset(&mut vm, BASE + SIZE as u64 - 4, 0xa5, 8),
+        Err(SimppleError::Memory(MemoryError::SegmentationFault { .. }))
+    ));
+    assert_eq!(
+        mmu.read_bytes(&vm, BASE + SIZE as u64 - 4, 4).unwrap(),
+        [0; 4]
+    );
+}
@@ -0,0 +1,86 @@
+//! Interrupt-driven PL011 console, end to end.
+//!
+//! The guest unmasks the UART receive interrupt and waits. The host scripts
+//! input, the run loop raises the IRQ and the guest's handler reads UARTDR,
+//! echoes the byte and returns. The echo must show up in the console
+//! output.
+
+mod common;
+
+use common::guest_config;
+use simpple_vm::asm::assemble;
+use simpple_vm::devices::uart::ConsoleInput;
+use simpple_vm::runner::VmRunner;
+use simpple_vm::vm::VmExit;
+
+const VECTORS: u64 = 0x800; // VBAR_EL1, 2 KiB aligned
+const IRQ_SP0: u64 = VECTORS + 0x080; // IRQ from the current EL with SP_EL0
+const IRQ_SPX: u64 = VECTORS + 0x280; // IRQ from the current EL with SP_ELx
+const DONE: u64 = 0x400; // The guest spins here once the input matched
+
+const MAIN: &str = "
+    movz x19, #0x0900, lsl #16
+    mov x0, #0x800
+    msr vbar_el1, x0
+    isb
+    mov w0, #0x301
+    str w0, [x19, #0x30]
+    mov w0, #0x10
+    str w0, [x19, #0x38]
+    mov x20, #0
+    mov x21, #0
+    msr daifclr, #2
+wait:
+    cmp x20, #2
+    b.ne wait
+    movz x0, #0x6f6b
+    cmp x21, x0
+    b.ne fail
+    mov w0, #0x0a
+    str w0, [x19]
+    b 0x400
+fail:
+    hvc #0
+";
+
+// Taking the byte from UARTDR deasserts the receive interrupt
+const HANDLER: &str = "
+    ldr w1, [x19]
+    str w1, [x19]
+    orr x21, x1, x21, lsl #8
+    add x20, x20, #1
+    eret
+";
+
+fn place(image: &mut Vec<u8>, address: u64, code: &[u8]) {
+    let start = address as usize;
+    if image.len() < start + code.len() {
+        image.resize(start + code.len(), 0);
+    }
+    image[start..start + code.len()].copy_from_slice(code);
+}
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_guest_echoes_scripted_input() {
+    let mut firmware = Vec::new();
+    place(&mut firmware, 0, &assemble(MAIN, 0).unwrap());
+    place(&mut firmware, DONE, &assemble("b 0x400", DONE).unwrap());
+    place(&mut firmware, IRQ_SP0, &assemble(HANDLER, IRQ_SP0).unwrap());
+    place(&mut firmware, IRQ_SPX, &assemble(HANDLER, IRQ_SPX).unwrap());
+
+    let input = ConsoleInput::new();
+    input.send(b"ok");
+    let mut config = guest_config(firmware);
+    config.platform.console_input = Some(input.clone());
+    config.platform.console_history = 4;
+
+    let mut runner = VmRunner::new(&config).unwrap();
+    assert_eq!(
+        runner.run_until(DONE).unwrap(),
+        VmExit::ReachedAddress(DONE)
+    );
+    assert_eq!(input.pending(), 0);
+    // The handler echoed each byte and the guest ended the line
+    assert_eq!(runner.console_history().unwrap().recent_lines(1), ["ok"]);
+}
@@ -0,0 +1,42 @@
+//! A trapped WFI stops the guest with `VmExit::Idle` once the WFI timeout
+//! passes with nothing to wake it, and resumes it when the virtual timer
+//! fires.
+
+mod common;
+
+use common::guest_config;
+use simpple_vm::asm::assemble;
+use simpple_vm::boot;
+use simpple_vm::vm::VmExit;
+use std::time::Duration;
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_wfi_without_wake_source_stops() {
+    let mut config = guest_config(assemble("wfi\nb 0", 0).unwrap());
+    config.wfi_timeout = Some(Duration::from_millis(100));
+
+    assert_eq!(boot(&config).unwrap(), VmExit::Idle(0));
+}
+
+#[test]
+#[ignore = "needs Hypervisor.framework"]
+fn test_wfi_wakes_on_virtual_timer() {
+    // Arm the virtual timer a few milliseconds ahead and wait for it
+    let firmware = assemble(
+        "
+        mrs x0, cntvct_el0
+        add x0, x0, #0x10, lsl #12
+        msr cntv_cval_el0, x0
+        mov x0, #1
+        msr cntv_ctl_el0, x0
+        wfi
+        hvc #0
+        ",
+        0,
+    )
+    .unwrap();
+    let config = guest_config(firmware);
+
+    assert_eq!(boot(&config).unwrap(), VmExit::Halted);
+}